@@ -0,0 +1,147 @@
+//! Compares uploading batched vertices with `StreamBuffer` against reallocating the whole buffer
+//! with `glBufferData` for every batch.
+//!
+//! Run with `cargo bench --bench stream_buffer`. Requires a display to create a GL context.
+
+use game_util::glutil::{self, AttributeFormat, StreamBuffer, Vertex, VertexArray, VertexLayout};
+use game_util::prelude::*;
+use glutin::dpi::PhysicalSize;
+use glutin::{Api, ContextBuilder, GlRequest};
+use instant::{Duration, Instant};
+use std::collections::VecDeque;
+
+const FRAMES: usize = 200;
+/// How many frames the CPU may get ahead of the GPU, like with a swap chain.
+const FRAMES_IN_FLIGHT: usize = 2;
+const BATCHES_PER_FRAME: usize = 4;
+/// Six vertices per sprite.
+const VERTICES_PER_BATCH: usize = 10_000 * 6;
+
+/// Same size as `SpriteBatch`'s vertices.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct BenchVertex {
+    pos: [f32; 2],
+    rest: [f32; 4],
+}
+
+impl Vertex for BenchVertex {
+    fn layout() -> VertexLayout {
+        VertexLayout::new()
+            .attribute("pos", AttributeFormat::Float(2))
+            .with_stride(24)
+    }
+}
+
+const VERTEX_SHADER: &str = "
+layout(location = 0) in vec2 pos;
+void main() {
+    gl_Position = vec4(pos, 0.0, 1.0);
+}
+";
+
+const FRAGMENT_SHADER: &str = "
+out vec4 color;
+void main() {
+    color = vec4(1.0);
+}
+";
+
+fn main() {
+    let el = glutin::event_loop::EventLoop::new();
+    let context = ContextBuilder::new()
+        .with_gl(GlRequest::Specific(Api::OpenGl, (3, 3)))
+        .build_headless(&el, PhysicalSize::new(1, 1))
+        .expect("failed to create GL context");
+    let context = unsafe { context.make_current() }.unwrap();
+    let gl = Gl::new(
+        unsafe { glow::Context::from_loader_function(|s| context.get_proc_address(s)) },
+        false,
+    );
+
+    let shader = glutil::ShaderProgram::from_source(&gl, VERTEX_SHADER, FRAGMENT_SHADER).unwrap();
+    shader.bind();
+
+    let vertices: Vec<_> = (0..VERTICES_PER_BATCH)
+        .map(|i| BenchVertex {
+            pos: [
+                (i % 100) as f32 / 50.0 - 1.0,
+                (i / 100 % 100) as f32 / 50.0 - 1.0,
+            ],
+            rest: [0.0; 4],
+        })
+        .collect();
+
+    let buffer = unsafe { gl.create_buffer().unwrap() };
+    let vao = VertexArray::new::<BenchVertex>(&gl, &shader, buffer, None).unwrap();
+    vao.bind();
+    let buffer_data = run(&gl, |_| unsafe {
+        gl.bind_buffer(glow::ARRAY_BUFFER, Some(buffer));
+        gl.buffer_data_u8_slice(
+            glow::ARRAY_BUFFER,
+            glutil::as_u8_slice(&vertices),
+            glow::STREAM_DRAW,
+        );
+        gl.draw_arrays(glow::POINTS, 0, vertices.len() as i32);
+    });
+    drop(vao);
+    unsafe { gl.delete_buffer(buffer) };
+
+    let mut stream = StreamBuffer::new(&gl, glow::ARRAY_BUFFER).unwrap();
+    let vao = VertexArray::new::<BenchVertex>(&gl, &shader, stream.raw(), None).unwrap();
+    vao.bind();
+    let stream_buffer = run(&gl, |_| unsafe {
+        let first = stream.write(&vertices);
+        gl.draw_arrays(glow::POINTS, first as i32, vertices.len() as i32);
+    });
+
+    println!(
+        "{} frames of {} batches of {} vertices",
+        FRAMES, BATCHES_PER_FRAME, VERTICES_PER_BATCH
+    );
+    report("glBufferData per batch", buffer_data);
+    report("StreamBuffer", stream_buffer);
+}
+
+/// Runs `batch` for each batch of each frame, returning the average frame time.
+///
+/// The CPU waits for the GPU to finish the frame `FRAMES_IN_FLIGHT` frames back rather than the
+/// last one, so that uploads that stall the pipeline show up without serializing every frame.
+fn run(gl: &Gl, mut batch: impl FnMut(usize)) -> Duration {
+    // Warm up allocations and driver state.
+    for i in 0..BATCHES_PER_FRAME * 10 {
+        batch(i);
+    }
+    unsafe { gl.finish() };
+
+    let mut fences = VecDeque::new();
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        for i in 0..BATCHES_PER_FRAME {
+            batch(i);
+        }
+        unsafe {
+            fences.push_back(gl.fence_sync(glow::SYNC_GPU_COMMANDS_COMPLETE, 0).unwrap());
+            if fences.len() > FRAMES_IN_FLIGHT {
+                let fence = fences.pop_front().unwrap();
+                gl.client_wait_sync(fence, glow::SYNC_FLUSH_COMMANDS_BIT, i32::MAX);
+                gl.delete_sync(fence);
+            }
+        }
+    }
+    unsafe {
+        gl.finish();
+        for fence in fences {
+            gl.delete_sync(fence);
+        }
+    }
+    start.elapsed() / FRAMES as u32
+}
+
+fn report(name: &str, frame_time: Duration) {
+    println!(
+        "{:>24}: {:.3} ms/frame",
+        name,
+        frame_time.as_secs_f64() * 1000.0
+    );
+}
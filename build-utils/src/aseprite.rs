@@ -0,0 +1,424 @@
+//! A reader for the parts of Aseprite files needed to pack them: the flattened frames, tags and
+//! slices. See https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+
+use image::{Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::Path;
+
+pub struct Aseprite {
+    pub height: u32,
+    pub frames: Vec<Frame>,
+    pub tags: Vec<Tag>,
+    pub slices: Vec<Slice>,
+}
+
+pub struct Frame {
+    /// The visible layers blended together.
+    pub image: RgbaImage,
+    /// In milliseconds.
+    pub duration: u32,
+}
+
+pub struct Tag {
+    pub name: String,
+    pub from: usize,
+    /// Inclusive.
+    pub to: usize,
+    pub direction: Direction,
+    /// How many times the tag plays, or 0 for forever.
+    pub repeat: u16,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+    PingPong,
+    PingPongReverse,
+}
+
+pub struct Slice {
+    pub name: String,
+    /// Each key applies from its frame until the next key.
+    pub keys: Vec<SliceKey>,
+}
+
+pub struct SliceKey {
+    pub frame: usize,
+    /// X, y, width and height in pixels from the top-left corner of the canvas.
+    pub bounds: [i32; 4],
+    /// The center part of a nine-patch slice, relative to `bounds`.
+    pub center: Option<[i32; 4]>,
+    /// Relative to `bounds`.
+    pub pivot: Option<(i32, i32)>,
+}
+
+struct Layer {
+    /// Whether the layer and all groups it is in are visible.
+    visible: bool,
+    opacity: u8,
+    /// Groups have no cels.
+    is_image: bool,
+}
+
+impl Slice {
+    /// The key that applies to `frame`, if any.
+    pub fn key(&self, frame: usize) -> Option<&SliceKey> {
+        self.keys.iter().rev().find(|k| k.frame <= frame)
+    }
+}
+
+pub fn load(path: &Path) -> Result<Aseprite, String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let mut r = Reader(&data);
+
+    let mut header = Reader(r.bytes(128)?);
+    header.u32()?;
+    if header.u16()? != 0xA5E0 {
+        return Err("not an Aseprite file".to_owned());
+    }
+    let frame_count = header.u16()?;
+    let width = header.u16()? as u32;
+    let height = header.u16()? as u32;
+    let depth = header.u16()?;
+    let layer_opacity_valid = header.u32()? & 1 != 0;
+    header.bytes(10)?;
+    let transparent_index = header.u8()?;
+    if ![8, 16, 32].contains(&depth) {
+        return Err(format!("unsupported color depth {}", depth));
+    }
+
+    let mut file = Aseprite {
+        height,
+        frames: vec![],
+        tags: vec![],
+        slices: vec![],
+    };
+    let mut layers: Vec<Layer> = vec![];
+    // The visibility of the enclosing groups of the next layer, by child level.
+    let mut groups: Vec<bool> = vec![];
+    let mut palette = vec![Rgba([0; 4]); 256];
+    // The data of every cel after its layer index, by frame and layer, for linked cels.
+    let mut cel_data: HashMap<(usize, usize), &[u8]> = HashMap::new();
+
+    for _ in 0..frame_count {
+        let size = r.u32()? as usize;
+        let mut frame = Reader(r.bytes(size.saturating_sub(4))?);
+        if frame.u16()? != 0xF1FA {
+            return Err("invalid frame header".to_owned());
+        }
+        let old_chunks = frame.u16()? as u32;
+        let duration = frame.u16()? as u32;
+        frame.bytes(2)?;
+        let chunks = match frame.u32()? {
+            0 => old_chunks,
+            n => n,
+        };
+
+        // Cels are blended in layer order once the whole frame is read.
+        let mut cels = vec![];
+        for _ in 0..chunks {
+            let size = frame.u32()? as usize;
+            let kind = frame.u16()?;
+            let mut chunk = Reader(frame.bytes(size.saturating_sub(6))?);
+            match kind {
+                // Old palette chunk, used by files without the new one.
+                0x0004 => {
+                    let mut index = 0;
+                    for _ in 0..chunk.u16()? {
+                        index += chunk.u8()? as usize;
+                        let count = match chunk.u8()? {
+                            0 => 256,
+                            n => n as usize,
+                        };
+                        for _ in 0..count {
+                            let [r, g, b] = [chunk.u8()?, chunk.u8()?, chunk.u8()?];
+                            if let Some(c) = palette.get_mut(index) {
+                                *c = Rgba([r, g, b, 255]);
+                            }
+                            index += 1;
+                        }
+                    }
+                }
+                0x2004 => {
+                    let flags = chunk.u16()?;
+                    let kind = chunk.u16()?;
+                    let level = chunk.u16()? as usize;
+                    chunk.bytes(6)?;
+                    let opacity = chunk.u8()?;
+                    groups.truncate(level);
+                    let visible = flags & 1 != 0 && groups.iter().all(|&v| v);
+                    if kind == 1 {
+                        groups.push(visible);
+                    }
+                    layers.push(Layer {
+                        // Reference layers aren't part of the image.
+                        visible: visible && flags & 64 == 0,
+                        opacity: if layer_opacity_valid { opacity } else { 255 },
+                        is_image: kind == 0,
+                    });
+                }
+                0x2005 => {
+                    let layer = chunk.u16()? as usize;
+                    let mut data = chunk.0;
+                    // Linked cels share the position, opacity and pixels of an earlier cel.
+                    if Reader(&data[5.min(data.len())..]).u16()? == 1 {
+                        let linked = Reader(&data[14.min(data.len())..]).u16()? as usize;
+                        data = *cel_data
+                            .get(&(linked, layer))
+                            .ok_or_else(|| format!("cel linked to missing frame {}", linked))?;
+                    }
+                    cel_data.insert((file.frames.len(), layer), data);
+                    cels.push((layer, data));
+                }
+                0x2018 => {
+                    let count = chunk.u16()?;
+                    chunk.bytes(8)?;
+                    for _ in 0..count {
+                        let from = chunk.u16()? as usize;
+                        let to = chunk.u16()? as usize;
+                        let direction = match chunk.u8()? {
+                            0 => Direction::Forward,
+                            1 => Direction::Reverse,
+                            2 => Direction::PingPong,
+                            3 => Direction::PingPongReverse,
+                            d => return Err(format!("unknown tag direction {}", d)),
+                        };
+                        let repeat = chunk.u16()?;
+                        chunk.bytes(10)?;
+                        let name = chunk.string()?;
+                        file.tags.push(Tag {
+                            name,
+                            from,
+                            to,
+                            direction,
+                            repeat,
+                        });
+                    }
+                }
+                0x2019 => {
+                    chunk.u32()?;
+                    let first = chunk.u32()? as usize;
+                    let last = chunk.u32()? as usize;
+                    chunk.bytes(8)?;
+                    for index in first..=last {
+                        let flags = chunk.u16()?;
+                        let color = Rgba([chunk.u8()?, chunk.u8()?, chunk.u8()?, chunk.u8()?]);
+                        if flags & 1 != 0 {
+                            chunk.string()?;
+                        }
+                        if let Some(c) = palette.get_mut(index) {
+                            *c = color;
+                        }
+                    }
+                }
+                0x2022 => {
+                    let count = chunk.u32()?;
+                    let flags = chunk.u32()?;
+                    chunk.u32()?;
+                    let name = chunk.string()?;
+                    let mut keys = vec![];
+                    for _ in 0..count {
+                        let frame = chunk.u32()? as usize;
+                        let bounds = [
+                            chunk.i32()?,
+                            chunk.i32()?,
+                            chunk.u32()? as i32,
+                            chunk.u32()? as i32,
+                        ];
+                        let center = match flags & 1 {
+                            0 => None,
+                            _ => Some([
+                                chunk.i32()?,
+                                chunk.i32()?,
+                                chunk.u32()? as i32,
+                                chunk.u32()? as i32,
+                            ]),
+                        };
+                        let pivot = match flags & 2 {
+                            0 => None,
+                            _ => Some((chunk.i32()?, chunk.i32()?)),
+                        };
+                        keys.push(SliceKey {
+                            frame,
+                            bounds,
+                            center,
+                            pivot,
+                        });
+                    }
+                    file.slices.push(Slice { name, keys });
+                }
+                _ => {}
+            }
+        }
+
+        let mut image = RgbaImage::new(width, height);
+        cels.sort_by_key(|&(layer, _)| layer);
+        for (layer_index, data) in cels {
+            let layer = layers
+                .get(layer_index)
+                .ok_or_else(|| format!("cel on missing layer {}", layer_index))?;
+            if !layer.visible || !layer.is_image {
+                continue;
+            }
+            let mut cel = Reader(data);
+            let x = cel.i16()? as i64;
+            let y = cel.i16()? as i64;
+            let opacity = cel.u8()? as u32 * layer.opacity as u32 / 255;
+            let kind = cel.u16()?;
+            cel.bytes(7)?;
+            let (w, h, pixels) = match kind {
+                0 => {
+                    let (w, h) = (cel.u16()? as usize, cel.u16()? as usize);
+                    (w, h, cel.0.to_vec())
+                }
+                2 => {
+                    let (w, h) = (cel.u16()? as usize, cel.u16()? as usize);
+                    let pixels = miniz_oxide::inflate::decompress_to_vec_zlib(cel.0)
+                        .map_err(|e| format!("invalid compressed cel: {:?}", e))?;
+                    (w, h, pixels)
+                }
+                // Tilemap cels. Linked cels were resolved when they were read.
+                _ => continue,
+            };
+
+            let bytes = depth as usize / 8;
+            if pixels.len() < w * h * bytes {
+                return Err("cel is missing pixels".to_owned());
+            }
+            for (i, pixel) in pixels.chunks(bytes).take(w * h).enumerate() {
+                let color = match *pixel {
+                    [r, g, b, a] => Rgba([r, g, b, a]),
+                    [v, a] => Rgba([v, v, v, a]),
+                    [i] if i == transparent_index => Rgba([0; 4]),
+                    [i] => palette[i as usize],
+                    _ => unreachable!(),
+                };
+                let px = x + (i % w) as i64;
+                let py = y + (i / w) as i64;
+                if px >= 0 && py >= 0 && px < width as i64 && py < height as i64 {
+                    let dst = image.get_pixel_mut(px as u32, py as u32);
+                    *dst = blend(*dst, color, opacity);
+                }
+            }
+        }
+
+        file.frames.push(Frame { image, duration });
+    }
+
+    validate(&file, width)?;
+    Ok(file)
+}
+
+/// Checks what packing the file relies on: that it has frames, that tags only cover frames it
+/// has and that nine-patch slices are on the canvas with their centers inside them.
+fn validate(file: &Aseprite, width: u32) -> Result<(), String> {
+    if file.frames.is_empty() {
+        return Err("the file has no frames".to_owned());
+    }
+    for tag in &file.tags {
+        if tag.from > tag.to || tag.to >= file.frames.len() {
+            return Err(format!(
+                "tag {:?} covers frames {} to {}, but the file has {} frames",
+                tag.name,
+                tag.from,
+                tag.to,
+                file.frames.len()
+            ));
+        }
+    }
+    for slice in &file.slices {
+        for key in &slice.keys {
+            let [x, y, w, h] = key.bounds;
+            let on_canvas = x >= 0
+                && y >= 0
+                && w > 0
+                && h > 0
+                && x as i64 + w as i64 <= width as i64
+                && y as i64 + h as i64 <= file.height as i64;
+            match key.center {
+                Some(_) if !on_canvas => {
+                    return Err(format!(
+                        "nine-patch slice {:?} reaches outside the canvas",
+                        slice.name
+                    ))
+                }
+                Some([cx, cy, cw, ch])
+                    if cx < 0
+                        || cy < 0
+                        || cw < 0
+                        || ch < 0
+                        || cx as i64 + cw as i64 > w as i64
+                        || cy as i64 + ch as i64 > h as i64 =>
+                {
+                    return Err(format!(
+                        "the center of nine-patch slice {:?} is outside the slice",
+                        slice.name
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Blends `src` over `dst` with the normal blend mode. Other blend modes are treated as normal.
+fn blend(dst: Rgba<u8>, src: Rgba<u8>, opacity: u32) -> Rgba<u8> {
+    let src_a = src.0[3] as u32 * opacity / 255;
+    let dst_a = dst.0[3] as u32;
+    let out_a = src_a + dst_a * (255 - src_a) / 255;
+    if out_a == 0 {
+        return Rgba([0; 4]);
+    }
+    let channel = |s: u8, d: u8| {
+        let value = s as u32 * src_a + d as u32 * dst_a * (255 - src_a) / 255;
+        (value / out_a) as u8
+    };
+    Rgba([
+        channel(src.0[0], dst.0[0]),
+        channel(src.0[1], dst.0[1]),
+        channel(src.0[2], dst.0[2]),
+        out_a as u8,
+    ])
+}
+
+/// Little-endian reads that fail at the end of the data.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < n {
+            return Err("unexpected end of file".to_owned());
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Everything that kept a sprite atlas from being generated.
+#[derive(Debug)]
+pub struct Error {
+    pub problems: Vec<Problem>,
+}
+
+/// Something wrong with one file.
+#[derive(Debug)]
+pub struct Problem {
+    pub path: PathBuf,
+    pub kind: ProblemKind,
+}
+
+#[derive(Debug)]
+pub enum ProblemKind {
+    /// The name isn't an identifier, optionally followed by an array index, nine-slice insets
+    /// and a scale like `@2x`.
+    InvalidName,
+    /// Another file has the same field name.
+    DuplicateName(String),
+    /// Another file has the same index in the same array.
+    DuplicateIndex {
+        array: String,
+        index: usize,
+    },
+    /// An array has a file for a later index, but not this one.
+    MissingIndex {
+        array: String,
+        index: usize,
+    },
+    /// A sprite, array of sprites or nine-slice has the same name as one of another kind.
+    MixedKinds(String),
+    /// The sprite doesn't fit on a page of the atlas.
+    TooLarge {
+        width: u32,
+        height: u32,
+        page_width: u32,
+        page_height: u32,
+    },
+    /// A variant of a sprite at another scale, like `ball@2x.png` for `ball.png`, isn't the
+    /// same sprite scaled: its size or whether it's a nine-slice differ.
+    MismatchedVariant {
+        name: String,
+        scale: u32,
+    },
+    /// A nine-slice or animation setting is invalid.
+    InvalidSettings(String),
+    /// An Aseprite file couldn't be decoded.
+    InvalidAseprite(String),
+    Io(std::io::Error),
+}
+
+impl Error {
+    pub(crate) fn single(path: impl Into<PathBuf>, kind: ProblemKind) -> Self {
+        Error {
+            problems: vec![Problem::new(path, kind)],
+        }
+    }
+
+    pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |e| Error::single(path, ProblemKind::Io(e))
+    }
+
+    /// Shows every problem as a cargo warning, so that they are listed when the build script
+    /// fails.
+    pub fn report(&self) {
+        for problem in &self.problems {
+            println!("cargo:warning={}", problem);
+        }
+    }
+
+    /// Reports the problems and exits the build script with an error, for build scripts that
+    /// have nothing else to do when sprites fail to generate.
+    ///
+    /// ```ignore
+    /// build_utils::gen_sprites("sprites", "res/generated", out_dir, 1024)
+    ///     .unwrap_or_else(|e| e.exit());
+    /// ```
+    pub fn exit(&self) -> ! {
+        self.report();
+        eprintln!("{}", self);
+        std::process::exit(1);
+    }
+}
+
+impl Problem {
+    pub(crate) fn new(path: impl Into<PathBuf>, kind: ProblemKind) -> Self {
+        Problem {
+            path: path.into(),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.problems.len() {
+            1 => write!(f, "failed to generate sprites:")?,
+            n => write!(f, "failed to generate sprites ({} problems):", n)?,
+        }
+        for problem in &self.problems {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.kind)
+    }
+}
+
+impl fmt::Display for ProblemKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProblemKind::InvalidName => write!(
+                f,
+                "invalid name, expected an identifier followed by an optional array index, \
+                 nine-slice insets, scale and extension, like `walk.3.png`, `button.9-4.png` \
+                 or `ball@2x.png`"
+            ),
+            ProblemKind::DuplicateName(name) => {
+                write!(f, "there's already a sprite called {}", name)
+            }
+            ProblemKind::DuplicateIndex { array, index } => {
+                write!(
+                    f,
+                    "there's already a sprite at index {} of {}",
+                    index, array
+                )
+            }
+            ProblemKind::MissingIndex { array, index } => {
+                write!(f, "index {} of sprite array {} is missing", index, array)
+            }
+            ProblemKind::MixedKinds(name) => write!(
+                f,
+                "{} mixes sprites, arrays of sprites or nine-slices with the same name",
+                name
+            ),
+            ProblemKind::TooLarge {
+                width,
+                height,
+                page_width,
+                page_height,
+            } => write!(
+                f,
+                "sprite is {}x{} pixels, which doesn't fit on {}x{} atlas pages",
+                width, height, page_width, page_height
+            ),
+            ProblemKind::MismatchedVariant { name, scale } => write!(
+                f,
+                "the @{}x variant of {} isn't the same size as its other variants once scaled, \
+                 or only some of them are nine-slices",
+                scale, name
+            ),
+            ProblemKind::InvalidSettings(message) => write!(f, "{}", message),
+            ProblemKind::InvalidAseprite(message) => {
+                write!(f, "invalid Aseprite file: {}", message)
+            }
+            ProblemKind::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
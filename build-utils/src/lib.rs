@@ -1,2 +1,11 @@
+mod aseprite;
+mod error;
+mod manifest;
+mod packer;
 mod sprite_pack;
-pub use crate::sprite_pack::gen_sprites;
+pub use crate::error::{Error, Problem, ProblemKind};
+pub use crate::manifest::ManifestFormat;
+pub use crate::packer::Heuristic;
+pub use crate::sprite_pack::{
+    gen_premultiplied_sprites, gen_sprites, AtlasBuilder, PageReport, Report,
+};
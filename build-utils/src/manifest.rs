@@ -0,0 +1,84 @@
+//! The atlas manifest read by `game_util::atlas::SpriteAtlas`. The types mirror those of
+//! `game_util::atlas` field for field, since bincode relies on the order.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The format of the manifest written by `AtlasBuilder::manifest`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ManifestFormat {
+    /// `sprites.json`, easy to read and edit by hand.
+    Json,
+    /// `sprites.bin`, smaller and faster to load.
+    Bincode,
+}
+
+impl ManifestFormat {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            ManifestFormat::Json => "json",
+            ManifestFormat::Bincode => "bin",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct Manifest {
+    pub page_width: u32,
+    pub page_height: u32,
+    pub pages: Vec<String>,
+    pub premultiplied_alpha: bool,
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Serialize)]
+pub(crate) enum ManifestEntry {
+    Sprite(ManifestSprite),
+    Array(Vec<ManifestSprite>),
+    Animation {
+        frames: Vec<ManifestFrame>,
+        mode: crate::sprite_pack::PlaybackMode,
+    },
+}
+
+#[derive(Serialize)]
+pub(crate) struct ManifestSprite {
+    pub rect: [u32; 4],
+    pub layer: u32,
+    pub rotated: bool,
+    pub trim_offset: [u32; 2],
+    pub real_size: [u32; 2],
+    pub scale: u32,
+    pub nine_slice: Option<ManifestNineSlice>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ManifestNineSlice {
+    pub insets: [u32; 4],
+    pub edges: crate::sprite_pack::SliceMode,
+    pub center: crate::sprite_pack::SliceMode,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ManifestFrame {
+    pub sprite: ManifestSprite,
+    pub duration: f32,
+    pub event: Option<String>,
+    pub slices: Vec<ManifestSlice>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ManifestSlice {
+    pub name: String,
+    pub rect: [i32; 4],
+    pub pivot: Option<[i32; 2]>,
+}
+
+impl Manifest {
+    pub fn encode(&self, format: ManifestFormat) -> Vec<u8> {
+        match format {
+            ManifestFormat::Json => serde_json::to_vec_pretty(self).unwrap(),
+            ManifestFormat::Bincode => bincode::serialize(self).unwrap(),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! A MaxRects rectangle packer. Every page keeps a list of the largest free rectangles, which
+//! may overlap, and each rectangle is put in the free rectangle chosen by the `Heuristic`.
+
+/// How the packer chooses where to put each sprite.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Heuristic {
+    /// Picks the free space where the shorter leftover side is smallest. Works well for most
+    /// sets of sprites, and is the default.
+    BestShortSideFit,
+    /// Picks the free space where the longer leftover side is smallest.
+    BestLongSideFit,
+    /// Picks the smallest free space the sprite fits in.
+    BestAreaFit,
+    /// Puts sprites as close to the top of the page as possible, then as far left, like a
+    /// Tetris player would.
+    BottomLeft,
+    /// Picks the spot where the sprite touches the most of the page edges and other sprites.
+    /// Slower than the others, but often packs tightest.
+    ContactPoint,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+/// Where a rectangle was packed.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Placement {
+    pub page: usize,
+    /// The rectangle on the page, turned if `rotated`.
+    pub rect: Rect,
+    /// Whether the rectangle was turned 90 degrees to fit better.
+    pub rotated: bool,
+}
+
+pub(crate) struct Packer {
+    width: u32,
+    height: u32,
+    padding: u32,
+    heuristic: Heuristic,
+    allow_rotation: bool,
+    pages: Vec<Page>,
+}
+
+struct Page {
+    free: Vec<Rect>,
+    /// For `Heuristic::ContactPoint`.
+    used: Vec<Rect>,
+}
+
+impl Rect {
+    fn right(&self) -> u32 {
+        self.x + self.w
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.h
+    }
+
+    fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    fn contains(&self, other: &Rect) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+}
+
+impl Packer {
+    /// Rectangles are kept `padding` pixels apart, but may touch the page edges.
+    pub fn new(
+        width: u32,
+        height: u32,
+        padding: u32,
+        heuristic: Heuristic,
+        allow_rotation: bool,
+    ) -> Self {
+        Packer {
+            width,
+            height,
+            padding,
+            heuristic,
+            allow_rotation,
+            pages: vec![],
+        }
+    }
+
+    /// Whether a rectangle fits on an empty page.
+    pub fn fits(&self, w: u32, h: u32) -> bool {
+        w <= self.width && h <= self.height
+            || self.allow_rotation && h <= self.width && w <= self.height
+    }
+
+    /// Packs a rectangle on the first page it fits on, starting a new page if none has room.
+    /// Returns `None` if it doesn't fit on an empty page.
+    pub fn pack(&mut self, w: u32, h: u32) -> Option<Placement> {
+        if !self.fits(w, h) {
+            return None;
+        }
+        // Padding is added to the right and bottom of every rectangle, and to the page so that
+        // rectangles can still reach its edges.
+        let (w, h) = (w + self.padding, h + self.padding);
+        let (page_w, page_h) = (self.width + self.padding, self.height + self.padding);
+        for (i, page) in self.pages.iter_mut().enumerate() {
+            if let Some((rect, rotated)) =
+                page.find(w, h, self.heuristic, self.allow_rotation, page_w, page_h)
+            {
+                page.place(rect);
+                return Some(self.placement(i, rect, rotated));
+            }
+        }
+
+        let mut page = Page {
+            free: vec![Rect {
+                x: 0,
+                y: 0,
+                w: page_w,
+                h: page_h,
+            }],
+            used: vec![],
+        };
+        let (rect, rotated) = page
+            .find(w, h, self.heuristic, self.allow_rotation, page_w, page_h)
+            .unwrap();
+        page.place(rect);
+        self.pages.push(page);
+        Some(self.placement(self.pages.len() - 1, rect, rotated))
+    }
+
+    fn placement(&self, page: usize, rect: Rect, rotated: bool) -> Placement {
+        Placement {
+            page,
+            rect: Rect {
+                w: rect.w - self.padding,
+                h: rect.h - self.padding,
+                ..rect
+            },
+            rotated,
+        }
+    }
+}
+
+impl Page {
+    /// Finds the best spot for a `w` by `h` rectangle, which is lower scores.
+    fn find(
+        &self,
+        w: u32,
+        h: u32,
+        heuristic: Heuristic,
+        allow_rotation: bool,
+        page_w: u32,
+        page_h: u32,
+    ) -> Option<(Rect, bool)> {
+        let mut best: Option<((i64, i64), Rect, bool)> = None;
+        for free in &self.free {
+            for &(w, h, rotated) in &[(w, h, false), (h, w, true)] {
+                if rotated && (!allow_rotation || w == h) || w > free.w || h > free.h {
+                    continue;
+                }
+                let rect = Rect {
+                    x: free.x,
+                    y: free.y,
+                    w,
+                    h,
+                };
+                let short = (free.w - w).min(free.h - h) as i64;
+                let long = (free.w - w).max(free.h - h) as i64;
+                let score = match heuristic {
+                    Heuristic::BestShortSideFit => (short, long),
+                    Heuristic::BestLongSideFit => (long, short),
+                    Heuristic::BestAreaFit => {
+                        (free.w as i64 * free.h as i64 - (w * h) as i64, short)
+                    }
+                    Heuristic::BottomLeft => (rect.bottom() as i64, rect.x as i64),
+                    Heuristic::ContactPoint => (-self.contact(&rect, page_w, page_h), 0),
+                };
+                match best {
+                    Some((best, _, _)) if best <= score => {}
+                    _ => best = Some((score, rect, rotated)),
+                }
+            }
+        }
+        best.map(|(_, rect, rotated)| (rect, rotated))
+    }
+
+    /// The length of the edges of `rect` touching the page edges or used rectangles.
+    fn contact(&self, rect: &Rect, page_w: u32, page_h: u32) -> i64 {
+        fn overlap(a0: u32, a1: u32, b0: u32, b1: u32) -> i64 {
+            (a1.min(b1) as i64 - a0.max(b0) as i64).max(0)
+        }
+
+        let mut contact = 0;
+        if rect.x == 0 || rect.right() == page_w {
+            contact += rect.h as i64;
+        }
+        if rect.y == 0 || rect.bottom() == page_h {
+            contact += rect.w as i64;
+        }
+        for used in &self.used {
+            if used.x == rect.right() || used.right() == rect.x {
+                contact += overlap(used.y, used.bottom(), rect.y, rect.bottom());
+            }
+            if used.y == rect.bottom() || used.bottom() == rect.y {
+                contact += overlap(used.x, used.right(), rect.x, rect.right());
+            }
+        }
+        contact
+    }
+
+    /// Splits the free rectangles overlapping `rect` into the parts around it.
+    fn place(&mut self, rect: Rect) {
+        let mut split = vec![];
+        self.free.retain(|free| {
+            if !free.intersects(&rect) {
+                return true;
+            }
+            if rect.x > free.x {
+                split.push(Rect {
+                    w: rect.x - free.x,
+                    ..*free
+                });
+            }
+            if rect.right() < free.right() {
+                split.push(Rect {
+                    x: rect.right(),
+                    w: free.right() - rect.right(),
+                    ..*free
+                });
+            }
+            if rect.y > free.y {
+                split.push(Rect {
+                    h: rect.y - free.y,
+                    ..*free
+                });
+            }
+            if rect.bottom() < free.bottom() {
+                split.push(Rect {
+                    y: rect.bottom(),
+                    h: free.bottom() - rect.bottom(),
+                    ..*free
+                });
+            }
+            false
+        });
+        self.free.extend(split);
+
+        // Drops free rectangles inside others, keeping the first of identical ones.
+        let mut i = 0;
+        while i < self.free.len() {
+            let free = self.free[i];
+            let redundant = self
+                .free
+                .iter()
+                .enumerate()
+                .any(|(j, other)| j != i && other.contains(&free) && (other != &free || j < i));
+            if redundant {
+                self.free.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        self.used.push(rect);
+    }
+}
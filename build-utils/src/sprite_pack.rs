@@ -1,209 +1,1183 @@
+use crate::aseprite;
+use crate::error::{Error, Problem, ProblemKind};
+use crate::manifest::{
+    Manifest, ManifestEntry, ManifestFormat, ManifestFrame, ManifestNineSlice, ManifestSlice,
+    ManifestSprite,
+};
+use crate::packer::{Heuristic, Packer, Placement, Rect};
 use image::{GenericImage, RgbaImage};
 use regex::Regex;
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::ops::Deref;
-use std::path::Path;
-use texture_packer::exporter::ImageExporter;
-use texture_packer::importer::ImageImporter;
-use texture_packer::{MultiTexturePacker, Rect, TexturePackerConfig};
+use std::path::{Path, PathBuf};
 
+/// Bumped whenever the code, pages or manifests generated from the same inputs change, so that
+/// output left in `OUT_DIR` by an older version is regenerated. Path and git dependencies
+/// change without their crate version changing.
+const GENERATOR_VERSION: u32 = 2;
+
+/// Configures and generates a sprite atlas.
+///
+/// The images in the sprite folder are packed into atlas pages saved to the image directory as
+/// `0.png`, `1.png` and so on, and the `Sprites` struct describing them is written to
+/// `sprites.rs` in the code directory, usually `OUT_DIR`.
+///
+/// The output only depends on the names and contents of the input files, so it doesn't change
+/// between builds. Nothing is repacked if the inputs haven't changed since the last run that
+/// wrote to the same directories.
+///
+/// Images named like `name.3.png` become arrays of sprites, and images in folders get the name
+/// of the folder as a prefix. Images can be made into `NineSlice`s by putting their insets in
+/// pixels in the file name, CSS style: `button.9-4.png` uses 4 on all sides, `button.9-4-8.png`
+/// 4 at the top and bottom and 8 on the left and right, and `button.9-4-8-6-8.png` gives the
+/// top, right, bottom and left insets. Alternatively, a sidecar file with the image's name and
+/// the extension `slice`, such as `button.slice`, can hold them:
+///
+/// ```text
+/// insets = 4 8 6 8
+/// # `stretch` (the default) or `tile`
+/// edges = tile
+/// center = stretch
+/// ```
+///
+/// Arrays of sprites become `Animation`s if they have a sidecar timing file named after the
+/// array, such as `walk.anim` for `walk.0.png`, `walk.1.png` and so on. Durations are in
+/// milliseconds:
+///
+/// ```text
+/// # `loop` (the default), `ping_pong` or `once`
+/// mode = ping_pong
+/// # the duration of every frame, 100 by default
+/// duration = 80
+/// # overrides for frame 3
+/// duration.3 = 200
+/// event.3 = footstep
+/// ```
+///
+/// Sprites can have variants drawn at higher resolutions for HiDPI displays, named like
+/// `ball@2x.png` or `walk.3@3x.png`. A set of atlas pages is packed for every scale, named like
+/// `0@2x.png`, with sprites that lack a variant at that scale using the closest one. The
+/// generated `Sprites::load` picks the set for the window's scale factor, and sizes are divided
+/// by the scale of the variant, so they are the same in every set.
+///
+/// Aseprite files (`.aseprite` or `.ase`) are read directly, blending their visible layers
+/// together. Each tag becomes an animation named `<file>_<tag>` with the durations set in
+/// Aseprite; files without tags become an animation or, with one frame, a sprite. Nine-patch
+/// slices become nine-slices named `<file>_<slice>`, and other slices are attached to the
+/// frames of animations as `game_util::animation::Slice`s, for hitboxes and such.
+///
+/// Files starting with `.` are ignored, and files that aren't images are skipped with a
+/// warning.
+///
+/// ```ignore
+/// let report = AtlasBuilder::new("sprites", "res/generated", env::var("OUT_DIR").unwrap())
+///     .page_size(2048, 1024)
+///     .padding(4)
+///     .extrusion(2)
+///     .premultiplied_alpha(true)
+///     .generate()
+///     .unwrap_or_else(|e| e.exit());
+/// for line in report.to_string().lines() {
+///     println!("cargo:warning=sprite atlas {}", line);
+/// }
+/// ```
+pub struct AtlasBuilder {
+    sprite_folder: PathBuf,
+    out_images: PathBuf,
+    out_code: PathBuf,
+    width: u32,
+    height: u32,
+    padding: u32,
+    extrusion: u32,
+    heuristic: Heuristic,
+    allow_rotation: bool,
+    premultiply: bool,
+    manifest: Option<ManifestFormat>,
+}
+
+/// How full the pages of a generated atlas are.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Report {
+    pub pages: Vec<PageReport>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PageReport {
+    /// The scale of the set of pages this one belongs to, 1 unless sprites have variants like
+    /// `@2x`.
+    pub scale: u32,
+    pub width: u32,
+    pub height: u32,
+    pub sprites: usize,
+    /// The pixels taken up by sprites, not counting padding and extrusion.
+    pub used_pixels: u64,
+}
+
+impl AtlasBuilder {
+    pub fn new(
+        sprite_folder: impl Into<PathBuf>,
+        out_images: impl Into<PathBuf>,
+        out_code: impl Into<PathBuf>,
+    ) -> Self {
+        AtlasBuilder {
+            sprite_folder: sprite_folder.into(),
+            out_images: out_images.into(),
+            out_code: out_code.into(),
+            width: 1024,
+            height: 1024,
+            padding: 1,
+            extrusion: 1,
+            heuristic: Heuristic::BestShortSideFit,
+            allow_rotation: true,
+            premultiply: false,
+            manifest: None,
+        }
+    }
+
+    /// Makes the atlas pages square, `size` by `size` pixels. Defaults to 1024.
+    pub fn size(self, size: u32) -> Self {
+        self.page_size(size, size)
+    }
+
+    /// The width and height of the atlas pages in pixels. Defaults to 1024 by 1024.
+    pub fn page_size(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// The number of transparent pixels between sprites, not counting extrusion. Defaults to 1.
+    ///
+    /// Mipmapped atlases need more padding so that sprites don't bleed into each other at lower
+    /// mip levels: at least `2^(levels - 1)` pixels for the sprites to stay apart in the
+    /// smallest level used.
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// How many times the edge pixels of each sprite are repeated around it, so that filtering
+    /// at the edges of a sprite doesn't pick up transparent pixels or other sprites. Defaults
+    /// to 1, which is enough for sprites drawn at their pixel size; scaled or mipmapped sprites
+    /// need more.
+    pub fn extrusion(mut self, extrusion: u32) -> Self {
+        self.extrusion = extrusion;
+        self
+    }
+
+    /// How the packer chooses where each sprite goes. Defaults to
+    /// `Heuristic::BestShortSideFit`.
+    pub fn heuristic(mut self, heuristic: Heuristic) -> Self {
+        self.heuristic = heuristic;
+        self
+    }
+
+    /// Whether sprites may be stored turned 90 degrees to pack tighter. Drawing is the same
+    /// either way. Defaults to `true`.
+    pub fn allow_rotation(mut self, allow_rotation: bool) -> Self {
+        self.allow_rotation = allow_rotation;
+        self
+    }
+
+    /// Whether the atlas images are stored with premultiplied alpha, which avoids dark fringes
+    /// where transparent pixels are filtered into visible ones. Defaults to `false`.
+    ///
+    /// The generated `Sprites::BLEND_MODE` is `BlendMode::PremultipliedAlpha` if set, which
+    /// sprites from the atlas should be drawn with.
+    pub fn premultiplied_alpha(mut self, premultiply: bool) -> Self {
+        self.premultiply = premultiply;
+        self
+    }
+
+    /// Also writes a manifest describing the atlas to the image directory, which
+    /// `game_util::atlas::SpriteAtlas` loads at runtime. Useful for sprites that are added
+    /// without recompiling, such as those of mods.
+    pub fn manifest(mut self, format: ManifestFormat) -> Self {
+        self.manifest = Some(format);
+        self
+    }
+
+    /// Packs the sprites and writes the atlas images and code, returning how full the pages
+    /// are. If nothing changed since the last run, the report of that run is returned.
+    ///
+    /// Fails with every problem found in the input files, or with the first error writing the
+    /// output.
+    pub fn generate(&self) -> Result<Report, Error> {
+        let root = &self.sprite_folder;
+        let target = &self.out_images;
+        let code_path = self.out_code.join("sprites.rs");
+        let hash_path = self.out_code.join("sprites.hash");
+
+        let mut hash = Fnv1a::new();
+        hash.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hash.write(&GENERATOR_VERSION.to_le_bytes());
+        for value in &[self.width, self.height, self.padding, self.extrusion] {
+            hash.write(&value.to_le_bytes());
+        }
+        hash.write(format!("{:?}", self.heuristic).as_bytes());
+        hash.write(&[self.allow_rotation as u8, self.premultiply as u8]);
+        hash.write(format!("{:?}", self.manifest).as_bytes());
+        println!("cargo:rerun-if-changed={}", root.display());
+        hash_dir(&mut hash, root, root)?;
+        let hash = format!("{:016x}", hash.0);
+        if let Some(report) = self.previous_report(&hash, &hash_path, &code_path) {
+            return Ok(report);
+        }
+
+        let mut entries = BTreeMap::new();
+        let mut timings = BTreeMap::new();
+        let mut problems = vec![];
+        let mut images = vec![];
+
+        process_dir(
+            &mut Input {
+                entries: &mut entries,
+                timings: &mut timings,
+                problems: &mut problems,
+                images: &mut images,
+                atlas: self,
+            },
+            root,
+            None,
+        )
+        .map_err(Error::io(root))?;
+        check_entries(&entries, &timings, &mut problems);
+        if !problems.is_empty() {
+            return Err(Error { problems });
+        }
+
+        // A set of pages for every scale there are variants at, each using the variant of every
+        // sprite closest to it.
+        let mut scales: BTreeSet<u32> = entries
+            .values()
+            .flat_map(Kind::sprites)
+            .flat_map(|variants| variants.0.keys().copied())
+            .collect();
+        if scales.is_empty() {
+            scales.insert(1);
+        }
+        std::fs::create_dir_all(target).map_err(Error::io(target))?;
+        let mut report = Report { pages: vec![] };
+        let mut atlases = vec![];
+        for scale in scales {
+            let mut used: Vec<usize> = entries
+                .values()
+                .flat_map(Kind::sprites)
+                .map(|variants| variants.get(scale).1.image)
+                .collect();
+            used.sort_unstable();
+            used.dedup();
+            let (placements, pages) = self.pack(&images, used, scale, &mut report)?;
+
+            let atlas = Atlas {
+                scale,
+                placements,
+                width: self.width,
+                height: self.height,
+                extrusion: self.extrusion,
+                pages,
+                premultiply: self.premultiply,
+            };
+            if let Some(format) = self.manifest {
+                let path = target.join(scaled_name("sprites", scale, format.extension()));
+                let manifest = manifest(&entries, &timings, &atlas);
+                std::fs::write(&path, manifest.encode(format)).map_err(Error::io(&path))?;
+            }
+            atlases.push(atlas);
+        }
+        File::create(&code_path)
+            .and_then(|file| write_code(&mut BufWriter::new(file), &entries, &timings, &atlases))
+            .map_err(Error::io(&code_path))?;
+
+        // Written last, so an interrupted run is redone.
+        let mut record = hash;
+        for page in &report.pages {
+            record += &format!("\n{} {} {}", page.scale, page.sprites, page.used_pixels);
+        }
+        std::fs::write(&hash_path, record).map_err(Error::io(&hash_path))?;
+        Ok(report)
+    }
+
+    /// Packs the `used` images into the pages of the set for `scale`, saves them and adds them
+    /// to the report. Returns where each image went and the number of pages.
+    fn pack(
+        &self,
+        images: &[RgbaImage],
+        mut used: Vec<usize>,
+        scale: u32,
+        report: &mut Report,
+    ) -> Result<(Vec<Option<Placement>>, usize), Error> {
+        // Packing the biggest sprites first packs tighter than packing them as they're read.
+        // The sort is stable, so sprites of the same size stay in the order they were read.
+        used.sort_by_key(|&i| {
+            let (w, h) = images[i].dimensions();
+            (Reverse(w.max(h)), Reverse(w * h))
+        });
+        let mut packer = Packer::new(
+            self.width,
+            self.height,
+            self.padding,
+            self.heuristic,
+            self.allow_rotation,
+        );
+        let mut placements = vec![None; images.len()];
+        for &i in &used {
+            let (w, h) = images[i].dimensions();
+            // Sprites that don't fit on a page were reported when they were read.
+            placements[i] = packer.pack(w, h);
+        }
+
+        let page_count = placements
+            .iter()
+            .flatten()
+            .map(|p| p.page + 1)
+            .max()
+            .unwrap_or(0);
+        let first_page = report.pages.len();
+        report.pages.resize(
+            first_page + page_count,
+            PageReport {
+                scale,
+                width: self.width,
+                height: self.height,
+                sprites: 0,
+                used_pixels: 0,
+            },
+        );
+        // Pages are only as large as the space used on them. The generated code clears the
+        // rest of the layers they are loaded into.
+        let mut page_sizes = vec![(0, 0); page_count];
+        for &i in &used {
+            let (w, h) = images[i].dimensions();
+            let placement = placements[i].unwrap();
+            let page = &mut report.pages[first_page + placement.page];
+            page.sprites += 1;
+            page.used_pixels += (w - 2 * self.extrusion) as u64 * (h - 2 * self.extrusion) as u64;
+            let size = &mut page_sizes[placement.page];
+            size.0 = u32::max(size.0, placement.rect.x + placement.rect.w);
+            size.1 = u32::max(size.1, placement.rect.y + placement.rect.h);
+        }
+        let mut pages: Vec<RgbaImage> = page_sizes
+            .into_iter()
+            .map(|(w, h)| RgbaImage::new(w, h))
+            .collect();
+        for &i in &used {
+            let placement = placements[i].unwrap();
+            let page = &mut pages[placement.page];
+            let (x, y) = (placement.rect.x, placement.rect.y);
+            if placement.rotated {
+                page.copy_from(&image::imageops::rotate90(&images[i]), x, y)
+            } else {
+                page.copy_from(&images[i], x, y)
+            }
+            .unwrap();
+        }
+
+        for (i, page) in pages.iter_mut().enumerate() {
+            let path = self.out_images.join(scaled_name(i, scale, "png"));
+            if self.premultiply {
+                premultiply_alpha(page);
+            }
+            // `std::io::Error::other` needs Rust 1.74.
+            #[allow(clippy::io_other_error)]
+            let io_error = |e| std::io::Error::new(std::io::ErrorKind::Other, e);
+            page.save(&path)
+                .map_err(|e| Error::single(&path, ProblemKind::Io(io_error(e))))?;
+        }
+        Ok((placements, page_count))
+    }
+
+    /// The report of the last run, if it had the same inputs and its output is still there.
+    fn previous_report(&self, hash: &str, hash_path: &Path, code_path: &Path) -> Option<Report> {
+        let recorded = std::fs::read_to_string(hash_path).ok()?;
+        let mut lines = recorded.lines();
+        if lines.next() != Some(hash) || !code_path.exists() {
+            return None;
+        }
+        let mut report = Report { pages: vec![] };
+        for line in lines {
+            let mut parts = line.split(' ');
+            report.pages.push(PageReport {
+                scale: parts.next()?.parse().ok()?,
+                width: self.width,
+                height: self.height,
+                sprites: parts.next()?.parse().ok()?,
+                used_pixels: parts.next()?.parse().ok()?,
+            });
+        }
+        let images = &self.out_images;
+        let mut scales: BTreeSet<u32> = report.pages.iter().map(|page| page.scale).collect();
+        if scales.is_empty() {
+            scales.insert(1);
+        }
+        let has_manifests = match self.manifest {
+            Some(format) => scales.iter().all(|&scale| {
+                images
+                    .join(scaled_name("sprites", scale, format.extension()))
+                    .exists()
+            }),
+            None => true,
+        };
+        let has_pages = report
+            .numbered_pages()
+            .all(|(i, page)| images.join(scaled_name(i, page.scale, "png")).exists());
+        if has_manifests && has_pages {
+            Some(report)
+        } else {
+            None
+        }
+    }
+}
+
+impl Report {
+    /// The pages with their number in the set of their scale.
+    fn numbered_pages(&self) -> impl Iterator<Item = (usize, &PageReport)> {
+        self.pages.iter().enumerate().map(move |(i, page)| {
+            let first = self
+                .pages
+                .iter()
+                .position(|p| p.scale == page.scale)
+                .unwrap();
+            (i - first, page)
+        })
+    }
+}
+
+impl PageReport {
+    /// The fraction of the page taken up by sprites.
+    pub fn utilization(&self) -> f32 {
+        self.used_pixels as f32 / (self.width as f32 * self.height as f32)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (n, (i, page)) in self.numbered_pages().enumerate() {
+            if n != 0 {
+                writeln!(f)?;
+            }
+            match page.scale {
+                1 => write!(f, "page {}: {}", i, page)?,
+                scale => write!(f, "page {}@{}x: {}", i, scale, page)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} sprites, {:.1}% of {}x{} used",
+            self.sprites,
+            self.utilization() * 100.0,
+            self.width,
+            self.height
+        )
+    }
+}
+
+/// Packs the images in `sprite_folder` into atlases of `size` by `size` pixels saved to
+/// `out_images`, and writes the `Sprites` struct to `sprites.rs` in `out_code`. See
+/// `AtlasBuilder` for how sprites are named and configured.
 pub fn gen_sprites(
     sprite_folder: impl AsRef<Path>,
     out_images: impl AsRef<Path>,
     out_code: impl AsRef<Path>,
     size: u32,
-) {
-    let mut packer = MultiTexturePacker::new_skyline(TexturePackerConfig {
-        max_width: size,
-        max_height: size,
-        ..Default::default()
-    });
+) -> Result<(), Error> {
+    AtlasBuilder::new(
+        sprite_folder.as_ref(),
+        out_images.as_ref(),
+        out_code.as_ref(),
+    )
+    .size(size)
+    .generate()
+    .map(|_| ())
+}
+
+/// Like `gen_sprites`, but the atlas images are stored with premultiplied alpha. See
+/// `AtlasBuilder::premultiplied_alpha`.
+pub fn gen_premultiplied_sprites(
+    sprite_folder: impl AsRef<Path>,
+    out_images: impl AsRef<Path>,
+    out_code: impl AsRef<Path>,
+    size: u32,
+) -> Result<(), Error> {
+    AtlasBuilder::new(
+        sprite_folder.as_ref(),
+        out_images.as_ref(),
+        out_code.as_ref(),
+    )
+    .size(size)
+    .premultiplied_alpha(true)
+    .generate()
+    .map(|_| ())
+}
 
-    let mut entries = HashMap::new();
+/// The state built up while reading the sprite folder.
+struct Input<'a> {
+    entries: &'a mut BTreeMap<String, Kind>,
+    timings: &'a mut BTreeMap<String, Timing>,
+    problems: &'a mut Vec<Problem>,
+    /// The trimmed and extruded images to pack, which `Data::image` indexes.
+    images: &'a mut Vec<RgbaImage>,
+    atlas: &'a AtlasBuilder,
+}
 
-    let root = sprite_folder.as_ref();
-    println!("cargo:rerun-if-changed={}", root.display());
-    process_dir(&mut entries, &mut packer, root, None);
+/// Where the images of the set of pages for one scale ended up, for writing the code.
+struct Atlas {
+    scale: u32,
+    /// Indexed like `Input::images`, with `None` for the images of other scales.
+    placements: Vec<Option<Placement>>,
+    width: u32,
+    height: u32,
+    extrusion: u32,
+    pages: usize,
+    premultiply: bool,
+}
 
-    let target = out_images.as_ref();
-    std::fs::create_dir_all(target).unwrap();
-    for (i, page) in packer.get_pages().iter().enumerate() {
-        let img = ImageExporter::export(page).unwrap();
-        img.save(target.join(&format!("{}.png", i))).unwrap();
+impl Atlas {
+    /// Where a sprite is on which page, leaving out the extruded pixels, and whether it's turned.
+    fn packed(&self, data: &Data) -> (Rect, usize, bool) {
+        let placement = self.placements[data.image].unwrap();
+        let e = self.extrusion;
+        let tex = Rect {
+            x: placement.rect.x + e,
+            y: placement.rect.y + e,
+            w: placement.rect.w - 2 * e,
+            h: placement.rect.h - 2 * e,
+        };
+        (tex, placement.page, placement.rotated)
     }
+}
 
-    for (name, k) in &entries {
+/// Finds the problems that are only apparent once every file is read.
+fn check_entries(
+    entries: &BTreeMap<String, Kind>,
+    timings: &BTreeMap<String, Timing>,
+    problems: &mut Vec<Problem>,
+) {
+    for (name, k) in entries {
         match k {
+            Kind::Just(variants) => check_variants(name, variants, problems),
             Kind::Array(v) => {
+                let first = v.iter().flatten().next().unwrap().first();
                 for (i, o) in v.iter().enumerate() {
-                    if o.is_none() {
-                        panic!("index {} of sprite array {} is missing", i, name);
+                    match o {
+                        None => problems.push(Problem::new(
+                            &first.path,
+                            ProblemKind::MissingIndex {
+                                array: name.clone(),
+                                index: i,
+                            },
+                        )),
+                        Some(variants) => {
+                            let data = variants.first();
+                            if data.slice.is_some() != first.slice.is_some() {
+                                problems.push(Problem::new(
+                                    &data.path,
+                                    ProblemKind::MixedKinds(name.clone()),
+                                ));
+                            }
+                            check_variants(&format!("{}.{}", name, i), variants, problems);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    for (name, timing) in timings {
+        let invalid =
+            |message: String| Problem::new(&timing.path, ProblemKind::InvalidSettings(message));
+        match entries.get(name) {
+            Some(Kind::Array(v)) => {
+                if v.iter()
+                    .flatten()
+                    .any(|variants| variants.first().slice.is_some())
+                {
+                    problems.push(invalid(format!(
+                        "nine-slice array {} can't be animated",
+                        name
+                    )));
+                }
+                for &i in timing.durations.keys().chain(timing.events.keys()) {
+                    if i >= v.len() {
+                        problems.push(invalid(format!("animation {} has no frame {}", name, i)));
                     }
                 }
             }
-            Kind::Just(_) => {}
+            _ => problems.push(invalid(format!(
+                "there's no sprite array called {} to animate",
+                name
+            ))),
         }
     }
+}
 
-    let mut sprites = BufWriter::new(File::create(out_code.as_ref().join("sprites.rs")).unwrap());
+/// Checks that the variants of a sprite at other scales are the same sprite, scaled.
+fn check_variants(name: &str, variants: &Variants, problems: &mut Vec<Problem>) {
+    let (&first_scale, first) = variants.0.iter().next().unwrap();
+    for (&scale, data) in &variants.0 {
+        // Compared at the product of the scales, so that sizes that aren't divisible by the
+        // scale still compare exactly.
+        let (w, h) = data.real_size;
+        let (first_w, first_h) = first.real_size;
+        let slices_match = match (&data.slice, &first.slice) {
+            (Some(slice), Some(first)) => {
+                (0..4).all(|i| slice.insets[i] * first_scale == first.insets[i] * scale)
+                    && slice.edges == first.edges
+                    && slice.center == first.center
+            }
+            (slice, first) => slice.is_some() == first.is_some(),
+        };
+        if w * first_scale != first_w * scale || h * first_scale != first_h * scale || !slices_match
+        {
+            problems.push(Problem::new(
+                &data.path,
+                ProblemKind::MismatchedVariant {
+                    name: name.to_owned(),
+                    scale,
+                },
+            ));
+        }
+    }
+}
 
+fn write_code(
+    sprites: &mut impl Write,
+    entries: &BTreeMap<String, Kind>,
+    timings: &BTreeMap<String, Timing>,
+    atlases: &[Atlas],
+) -> std::io::Result<()> {
     write!(
         sprites,
         "mod sprites {{
+        use game_util::material::BlendMode;
         use game_util::sprite::Sprite;
+        use game_util::texture::{{TextureArray, TextureFormat, TextureOptions}};
         use game_util::prelude::*;
         pub struct Sprites {{"
-    )
-    .unwrap();
+    )?;
 
-    for (name, kind) in &entries {
+    for (name, kind) in entries {
         match kind {
-            Kind::Just(_) => write!(sprites, "pub {}: Sprite,", name).unwrap(),
-            Kind::Array(v) => write!(sprites, "pub {}: [Sprite; {}],", name, v.len()).unwrap(),
+            Kind::Just(variants) => {
+                write!(sprites, "pub {}: {},", name, variants.first().type_name())?
+            }
+            Kind::Array(_) if timings.contains_key(name) => {
+                write!(sprites, "pub {}: game_util::animation::Animation,", name)?
+            }
+            Kind::Array(v) => write!(
+                sprites,
+                "pub {}: [{}; {}],",
+                name,
+                v[0].as_ref().unwrap().first().type_name(),
+                v.len()
+            )?,
         }
     }
 
-    writeln!(sprites, "}}").unwrap();
+    writeln!(sprites, "}}")?;
 
+    let scales: Vec<u32> = atlases.iter().map(|atlas| atlas.scale).collect();
     write!(
         sprites,
         r#"
         impl Sprites {{
-            pub async fn load(gl: &Gl, base: &str) -> Result<(Self, glow::Texture), String> {{
-                let tex;
-                unsafe {{
-                    tex = gl.create_texture()?;
-                    gl.bind_texture(glow::TEXTURE_2D_ARRAY, Some(tex));
-                    gl.tex_image_3d(
-                        glow::TEXTURE_2D_ARRAY,
-                        0,
-                        glow::RGBA8 as _,
-                        {}, {0}, {},
-                        0,
-                        glow::RGBA,
-                        glow::UNSIGNED_BYTE,
-                        None
-                    );
-                    gl.tex_parameter_i32(
-                        glow::TEXTURE_2D_ARRAY, glow::TEXTURE_MIN_FILTER, glow::LINEAR as _
-                    );
+            /// The blend mode to draw sprites from this atlas with.
+            pub const BLEND_MODE: BlendMode = BlendMode::{};
+
+            /// The scales there are sets of atlas pages for, from `@2x` sprite variants and the
+            /// like.
+            pub const SCALES: &[u32] = &{:?};
+
+            /// Loads the set of atlas pages for `scale_factor`, usually `Window::scale_factor()`:
+            /// the smallest scale at least as large, or the largest. Sizes are divided by the
+            /// scale of each sprite, so they are the same whichever set is loaded.
+            #[allow(clippy::match_single_binding)]
+            pub async fn load(
+                gl: &Gl,
+                base: &str,
+                scale_factor: f64,
+            ) -> Result<(Self, TextureArray), game_util::Error> {{
+                let scale = Self::SCALES
+                    .iter()
+                    .copied()
+                    .find(|&scale| scale as f64 >= scale_factor)
+                    .unwrap_or({});
+                match scale {{
         "#,
-        size,
-        packer.get_pages().len()
-    )
-    .unwrap();
+        if atlases[0].premultiply {
+            "PremultipliedAlpha"
+        } else {
+            "Alpha"
+        },
+        scales,
+        scales[scales.len() - 1]
+    )?;
+    for (i, &scale) in scales.iter().enumerate() {
+        if i + 1 == scales.len() {
+            write!(sprites, "_ => ")?;
+        } else {
+            write!(sprites, "{} => ", scale)?;
+        }
+        writeln!(sprites, "Self::load_{}x(gl, base).await,", scale)?;
+    }
+    writeln!(sprites, "}}}}")?;
+
+    for atlas in atlases {
+        write_load(sprites, entries, timings, atlas)?;
+    }
+
+    write!(sprites, "}}}}")?;
+    sprites.flush()
+}
 
-    writeln!(sprites, "game_util::futures::try_join!(").unwrap();
-    for i in 0..packer.get_pages().len() {
+/// Writes the function loading the set of pages of one scale.
+fn write_load(
+    sprites: &mut impl Write,
+    entries: &BTreeMap<String, Kind>,
+    timings: &BTreeMap<String, Timing>,
+    atlas: &Atlas,
+) -> std::io::Result<()> {
+    write!(
+        sprites,
+        r#"
+        async fn load_{}x(gl: &Gl, base: &str) -> Result<(Self, TextureArray), game_util::Error> {{
+            let tex = TextureArray::new_cleared(
+                gl,
+                {}, {}, {},
+                TextureFormat::Rgba8,
+                TextureOptions::default(),
+            )?;
+        "#,
+        atlas.scale, atlas.width, atlas.height, atlas.pages
+    )?;
+
+    writeln!(sprites, "game_util::futures::try_join!(")?;
+    for i in 0..atlas.pages {
         writeln!(
             sprites,
-            "async {{ game_util::glutil::load_texture_layer(
-                gl, &(base.to_owned() + \"/{}.png\"), tex, {0}
-            ).await }},",
+            "async {{ tex.load_layer(&(base.to_owned() + \"/{}\"), {}).await }},",
+            scaled_name(i, atlas.scale, "png"),
             i
-        )
-        .unwrap();
+        )?;
     }
-    writeln!(sprites, ")?;").unwrap();
+    writeln!(sprites, ")?;")?;
 
-    write!(sprites, "}} Ok((Sprites {{").unwrap();
+    write!(sprites, "Ok((Sprites {{")?;
 
-    for (name, kind) in &entries {
-        write!(sprites, "{}: ", name).unwrap();
+    for (name, kind) in entries {
+        write!(sprites, "{}: ", name)?;
         match kind {
-            Kind::Just(data) => write_sprite(&mut sprites, data, size),
+            Kind::Just(variants) => write_sprite(sprites, variants, atlas)?,
+            Kind::Array(v) if timings.contains_key(name) => {
+                let timing = &timings[name];
+                write!(sprites, "game_util::animation::Animation {{ frames: vec![")?;
+                for (i, variants) in v.iter().enumerate() {
+                    write!(sprites, "game_util::animation::Frame {{ sprite: ")?;
+                    write_sprite(sprites, variants.as_ref().unwrap(), atlas)?;
+                    let duration = timing.durations.get(&i).unwrap_or(&timing.duration);
+                    let event = match timing.events.get(&i) {
+                        Some(event) => format!("Some({:?}.to_owned())", event),
+                        None => "None".to_owned(),
+                    };
+                    write!(
+                        sprites,
+                        "duration: {:?}, event: {}, slices: vec![",
+                        *duration as f32 / 1000.0,
+                        event
+                    )?;
+                    for slice in timing.slices.get(&i).into_iter().flatten() {
+                        let [x, y, w, h] = slice.rect;
+                        write!(
+                            sprites,
+                            "game_util::animation::Slice {{\
+                                name: {:?}.to_owned(),\
+                                rect: rect({}.0, {}.0, {}.0, {}.0),\
+                                pivot: {},\
+                            }},",
+                            slice.name,
+                            x,
+                            y,
+                            w,
+                            h,
+                            match slice.pivot {
+                                Some((x, y)) => format!("Some(point2({}.0, {}.0))", x, y),
+                                None => "None".to_owned(),
+                            }
+                        )?;
+                    }
+                    write!(sprites, "] }},")?;
+                }
+                write!(
+                    sprites,
+                    "], mode: game_util::animation::PlaybackMode::{:?} }},",
+                    timing.mode
+                )?;
+            }
             Kind::Array(v) => {
-                write!(sprites, "[").unwrap();
-                for data in v {
-                    write_sprite(&mut sprites, data.as_ref().unwrap(), size);
+                write!(sprites, "[")?;
+                for variants in v {
+                    write_sprite(sprites, variants.as_ref().unwrap(), atlas)?;
                 }
-                write!(sprites, "],").unwrap();
+                write!(sprites, "],")?;
             }
         }
 
-        fn write_sprite(sprites: &mut impl Write, data: &Data, size: u32) {
+        fn write_sprite(
+            sprites: &mut impl Write,
+            variants: &Variants,
+            atlas: &Atlas,
+        ) -> std::io::Result<()> {
+            let (scale, data) = variants.get(atlas.scale);
+            let (tex, layer, rotated) = atlas.packed(data);
+            let scaled = |pixels: u32| pixels as f32 / scale as f32;
+            if data.slice.is_some() {
+                write!(sprites, "game_util::sprite::NineSlice {{ sprite: ")?;
+            }
             write!(
                 sprites,
                 "Sprite {{\
-                    tex: rect({}.0 / {}.0, {}.0 / {1}.0, {}.0 / {1}.0, {}.0 / {1}.0),\
-                    trimmed_size: size2({}.0, {}.0),\
-                    real_size: size2({}.0, {}.0),\
+                    tex: rect({}.0 / {}.0, {}.0 / {}.0, {}.0 / {1}.0, {}.0 / {3}.0),\
+                    trimmed_size: size2({:?}, {:?}),\
+                    trim_offset: vec2({:?}, {:?}),\
+                    real_size: size2({:?}, {:?}),\
                     layer: {}.0,\
                     rotated: {}\
                 }},",
-                data.tex.x,
-                size,
-                data.tex.y,
-                data.tex.w,
-                data.tex.h,
-                if data.rotated { data.tex.h } else { data.tex.w },
-                if data.rotated { data.tex.w } else { data.tex.h },
-                data.real_size.0,
-                data.real_size.1,
-                data.layer,
-                data.rotated
-            )
-            .unwrap();
+                tex.x,
+                atlas.width,
+                tex.y,
+                atlas.height,
+                tex.w,
+                tex.h,
+                scaled(if rotated { tex.h } else { tex.w }),
+                scaled(if rotated { tex.w } else { tex.h }),
+                scaled(data.trim_offset.0),
+                scaled(data.trim_offset.1),
+                scaled(data.real_size.0),
+                scaled(data.real_size.1),
+                layer,
+                rotated
+            )?;
+            if let Some(slice) = &data.slice {
+                let [top, right, bottom, left] = slice.insets;
+                write!(
+                    sprites,
+                    "insets: game_util::euclid::SideOffsets2D::new({:?}, {:?}, {:?}, {:?}),\
+                    edges: game_util::sprite::SliceMode::{:?},\
+                    center: game_util::sprite::SliceMode::{:?},\
+                    }},",
+                    scaled(top),
+                    scaled(right),
+                    scaled(bottom),
+                    scaled(left),
+                    slice.edges,
+                    slice.center
+                )?;
+            }
+            Ok(())
         }
     }
 
-    write!(sprites, "}}, tex))}}}}}}").unwrap();
+    write!(sprites, "}}, tex))}}")
 }
 
-fn process_dir(
-    entries: &mut HashMap<String, Kind>,
-    packer: &mut MultiTexturePacker<RgbaImage>,
-    path: &Path,
-    field_name: Option<String>,
-) {
-    for entry in std::fs::read_dir(path).unwrap() {
-        let entry = entry.unwrap();
-        println!("cargo:rerun-if-changed={}", entry.path().display());
-        let t = entry.file_type().unwrap();
+/// Describes the same sprites as `write_code`.
+fn manifest(
+    entries: &BTreeMap<String, Kind>,
+    timings: &BTreeMap<String, Timing>,
+    atlas: &Atlas,
+) -> Manifest {
+    let sprite = |variants: &Variants| {
+        let (scale, data) = variants.get(atlas.scale);
+        let (tex, layer, rotated) = atlas.packed(data);
+        ManifestSprite {
+            rect: [tex.x, tex.y, tex.w, tex.h],
+            layer: layer as u32,
+            rotated,
+            trim_offset: [data.trim_offset.0, data.trim_offset.1],
+            real_size: [data.real_size.0, data.real_size.1],
+            scale,
+            nine_slice: data.slice.as_ref().map(|slice| ManifestNineSlice {
+                insets: slice.insets,
+                edges: slice.edges,
+                center: slice.center,
+            }),
+        }
+    };
+
+    let mut manifest = Manifest {
+        page_width: atlas.width,
+        page_height: atlas.height,
+        pages: (0..atlas.pages)
+            .map(|i| scaled_name(i, atlas.scale, "png"))
+            .collect(),
+        premultiplied_alpha: atlas.premultiply,
+        entries: BTreeMap::new(),
+    };
+    for (name, kind) in entries {
+        let entry = match kind {
+            Kind::Just(variants) => ManifestEntry::Sprite(sprite(variants)),
+            Kind::Array(v) => match timings.get(name) {
+                Some(timing) => ManifestEntry::Animation {
+                    frames: v
+                        .iter()
+                        .enumerate()
+                        .map(|(i, variants)| ManifestFrame {
+                            sprite: sprite(variants.as_ref().unwrap()),
+                            duration: *timing.durations.get(&i).unwrap_or(&timing.duration) as f32
+                                / 1000.0,
+                            event: timing.events.get(&i).cloned(),
+                            slices: timing
+                                .slices
+                                .get(&i)
+                                .into_iter()
+                                .flatten()
+                                .map(|slice| ManifestSlice {
+                                    name: slice.name.clone(),
+                                    rect: slice.rect,
+                                    pivot: slice.pivot.map(|(x, y)| [x, y]),
+                                })
+                                .collect(),
+                        })
+                        .collect(),
+                    mode: timing.mode,
+                },
+                None => ManifestEntry::Array(
+                    v.iter()
+                        .map(|variants| sprite(variants.as_ref().unwrap()))
+                        .collect(),
+                ),
+            },
+        };
+        manifest.entries.insert(name.clone(), entry);
+    }
+    manifest
+}
+
+/// Names an output file like the input files of its scale: `name.extension` at scale 1, and
+/// `name@2x.extension` and so on otherwise.
+fn scaled_name(name: impl fmt::Display, scale: u32, extension: &str) -> String {
+    match scale {
+        1 => format!("{}.{}", name, extension),
+        _ => format!("{}@{}x.{}", name, scale, extension),
+    }
+}
+
+/// A stable 64-bit FNV-1a hash, so that the hash of the inputs doesn't depend on the compiler.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// Sorts the entries of a directory by name, so that the output doesn't depend on the order
+/// the file system lists them in. Hidden files are left out.
+fn sorted_dir(path: &Path) -> std::io::Result<Vec<std::fs::DirEntry>> {
+    let mut entries = vec![];
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with('.') {
+            entries.push(entry);
+        }
+    }
+    entries.sort_by_key(|e| e.file_name());
+    Ok(entries)
+}
+
+/// Hashes the paths relative to `root` and the contents of all files in `path`, and tells cargo
+/// to rerun the build script if any of them change.
+fn hash_dir(hash: &mut Fnv1a, root: &Path, path: &Path) -> Result<(), Error> {
+    for entry in sorted_dir(path).map_err(Error::io(path))? {
+        let path = entry.path();
+        println!("cargo:rerun-if-changed={}", path.display());
+        let relative = path.strip_prefix(root).unwrap().to_string_lossy();
+        hash.write(&(relative.len() as u64).to_le_bytes());
+        hash.write(relative.as_bytes());
+        if entry.file_type().map_err(Error::io(&path))?.is_dir() {
+            hash_dir(hash, root, &path)?;
+        } else {
+            let contents = std::fs::read(&path).map_err(Error::io(&path))?;
+            hash.write(&(contents.len() as u64).to_le_bytes());
+            hash.write(&contents);
+        }
+    }
+    Ok(())
+}
+
+fn premultiply_alpha(img: &mut RgbaImage) {
+    for pixel in img.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let scale = |c: u8| ((c as u32 * a as u32 + 127) / 255) as u8;
+        pixel.0 = [scale(r), scale(g), scale(b), a];
+    }
+}
+
+/// Reads the sprites in a directory. Problems with individual files are collected in `input`;
+/// the error is for failing to read the directory itself.
+fn process_dir(input: &mut Input, path: &Path, field_name: Option<String>) -> std::io::Result<()> {
+    for entry in sorted_dir(path)? {
+        let path = entry.path();
+        let t = entry.file_type()?;
+        if t.is_file() && path.extension() == Some("slice".as_ref()) {
+            // Read along with the image it belongs to.
+            continue;
+        }
         let file_name = entry.file_name();
-        let (name, array) = process_name(
-            field_name.as_ref().map(Deref::deref),
-            &file_name.to_string_lossy(),
-        );
+        let NameParts {
+            name,
+            index: array,
+            insets,
+            scale,
+        } = match process_name(field_name.as_deref(), &file_name.to_string_lossy()) {
+            Ok(parts) => parts,
+            Err(kind) => {
+                input.problems.push(Problem::new(&path, kind));
+                continue;
+            }
+        };
 
-        if t.is_dir() {
-            process_dir(entries, packer, &entry.path(), Some(name));
+        if scale.is_some() && !t.is_file() {
+            input
+                .problems
+                .push(Problem::new(&path, ProblemKind::InvalidName));
+        } else if t.is_dir() {
+            process_dir(input, &path, Some(name))?;
+        } else if t.is_file()
+            && ["aseprite", "ase"]
+                .iter()
+                .any(|&e| path.extension() == Some(e.as_ref()))
+        {
+            if array.is_some() || insets.is_some() || scale.is_some() {
+                input
+                    .problems
+                    .push(Problem::new(&path, ProblemKind::InvalidName));
+                continue;
+            }
+            process_aseprite(input, &path, &name);
+        } else if t.is_file() && path.extension() == Some("anim".as_ref()) {
+            if scale.is_some() {
+                input
+                    .problems
+                    .push(Problem::new(&path, ProblemKind::InvalidName));
+                continue;
+            }
+            match process_timing(&path) {
+                Ok(timing) => match input.timings.entry(name) {
+                    Entry::Occupied(e) => input.problems.push(Problem::new(
+                        &path,
+                        ProblemKind::InvalidSettings(format!(
+                            "there's already an animation timing for {}",
+                            e.key()
+                        )),
+                    )),
+                    Entry::Vacant(e) => {
+                        e.insert(timing);
+                    }
+                },
+                Err(kind) => input.problems.push(Problem::new(&path, kind)),
+            }
         } else if t.is_file() {
-            let key = match array {
-                Some(i) => format!("{}[{}]", name, i),
-                None => name.clone(),
+            let img = match image::open(&path) {
+                Ok(img) => img.to_rgba8(),
+                Err(e) => {
+                    println!("cargo:warning=skipping {}: {}", path.display(), e);
+                    continue;
+                }
             };
-            let data = match process_img(packer, &key, &entry.path()) {
-                Some(v) => v,
+            let slice = match process_slice(&path, insets) {
+                Ok(slice) => slice,
+                Err(kind) => {
+                    input.problems.push(Problem::new(&path, kind));
+                    continue;
+                }
+            };
+            let mut data = match add_img(input, &path, img) {
+                Some(data) => data,
                 None => continue,
             };
+            data.slice = slice;
+            input.add_variant(name, array, scale.unwrap_or(1), data);
+        }
+    }
+    Ok(())
+}
 
-            if let Some(i) = array {
-                let v = entries.entry(name.clone()).or_insert(Kind::Array(vec![]));
-                match v {
-                    Kind::Array(v) => {
-                        while v.len() <= i {
-                            v.push(None);
-                        }
-                        if v[i].is_some() {
-                            panic!("??? two of the same index?");
-                        }
-                        v[i] = Some(data);
-                    }
-                    Kind::Just(_) => panic!("mixing sprite and array of sprites at {}", name),
-                }
-            } else {
-                match entries.entry(name.clone()) {
-                    Entry::Occupied(_) => {
-                        panic!("there's already a sprite called {}", name);
-                    }
-                    Entry::Vacant(e) => {
-                        e.insert(Kind::Just(data));
-                    }
+impl Input<'_> {
+    /// Adds a sprite or array of sprites that can't be added to.
+    fn insert(&mut self, name: String, kind: Kind) {
+        match self.entries.entry(name) {
+            Entry::Occupied(e) => {
+                let path = match &kind {
+                    Kind::Just(variants) => &variants.first().path,
+                    Kind::Array(v) => &v.iter().flatten().next().unwrap().first().path,
+                };
+                let problem = match e.get() {
+                    Kind::Just(_) => ProblemKind::DuplicateName(e.key().clone()),
+                    Kind::Array(_) => ProblemKind::MixedKinds(e.key().clone()),
+                };
+                self.problems.push(Problem::new(path, problem));
+            }
+            Entry::Vacant(e) => {
+                e.insert(kind);
+            }
+        }
+    }
+
+    /// Adds an image file as the variant at `scale` of a sprite, or of the sprite at `index` of
+    /// an array.
+    fn add_variant(&mut self, name: String, index: Option<usize>, scale: u32, data: Data) {
+        let path = data.path.clone();
+        let kind = self
+            .entries
+            .entry(name.clone())
+            .or_insert_with(|| match index {
+                Some(_) => Kind::Array(vec![]),
+                None => Kind::Just(Variants(BTreeMap::new())),
+            });
+        let variants = match (kind, index) {
+            (Kind::Just(variants), None) => variants,
+            (Kind::Array(v), Some(i)) => {
+                if v.len() <= i {
+                    v.resize(i + 1, None);
                 }
+                v[i].get_or_insert_with(|| Variants(BTreeMap::new()))
+            }
+            _ => {
+                self.problems
+                    .push(Problem::new(&path, ProblemKind::MixedKinds(name)));
+                return;
+            }
+        };
+        match variants.0.entry(scale) {
+            Entry::Occupied(_) => self.problems.push(Problem::new(
+                &path,
+                match index {
+                    Some(index) => ProblemKind::DuplicateIndex { array: name, index },
+                    None => ProblemKind::DuplicateName(name),
+                },
+            )),
+            Entry::Vacant(e) => {
+                e.insert(data);
             }
         }
     }
@@ -211,147 +1185,508 @@ fn process_dir(
 
 #[derive(Debug)]
 enum Kind {
-    Just(Data),
-    Array(Vec<Option<Data>>),
+    Just(Variants),
+    Array(Vec<Option<Variants>>),
 }
 
-#[derive(Debug)]
+impl Kind {
+    /// The sprite or the sprites of the array.
+    fn sprites(&self) -> Vec<&Variants> {
+        match self {
+            Kind::Just(variants) => vec![variants],
+            Kind::Array(v) => v.iter().flatten().collect(),
+        }
+    }
+}
+
+/// A sprite at every scale it was drawn at, which is 1 unless it has variants like `@2x`.
+#[derive(Clone, Debug)]
+struct Variants(BTreeMap<u32, Data>);
+
+impl Variants {
+    /// A sprite that only has one scale.
+    fn one(data: Data) -> Self {
+        let mut variants = BTreeMap::new();
+        variants.insert(1, data);
+        Variants(variants)
+    }
+
+    /// The variant at the lowest scale.
+    fn first(&self) -> &Data {
+        self.0.values().next().unwrap()
+    }
+
+    /// The variant to use for `scale`: the one at `scale` or the closest higher one, so that
+    /// sprites are scaled down rather than up, or otherwise the highest. Returns its scale.
+    fn get(&self, scale: u32) -> (u32, &Data) {
+        let (&scale, data) = self
+            .0
+            .range(scale..)
+            .next()
+            .or_else(|| self.0.iter().next_back())
+            .unwrap();
+        (scale, data)
+    }
+}
+
+#[derive(Clone, Debug)]
 struct Data {
-    tex: Rect,
+    /// The file the sprite is from, for error messages.
+    path: PathBuf,
+    /// The index of the packed image in `Input::images`. Frames of Aseprite files used by
+    /// several animations share one.
+    image: usize,
+    /// From the bottom-left corner of the source image to that of the packed part.
+    trim_offset: (u32, u32),
     real_size: (u32, u32),
-    layer: usize,
-    rotated: bool,
+    slice: Option<Slice>,
 }
 
-fn process_name(parent_name: Option<&str>, name: &str) -> (String, Option<usize>) {
-    lazy_static::lazy_static! {
-        static ref REGEX: Regex = Regex::new(r"^([_a-zA-Z][_\w]*)(?:.(\d+))?\.\w+$").unwrap();
-    };
-
-    match REGEX.captures(name) {
-        Some(caps) => {
-            let name = caps.get(1).unwrap().as_str();
-            let name = match parent_name {
-                Some(p) => format!("{}_{}", p, name),
-                None => name.to_owned(),
-            };
-            let index = caps.get(2).map(|m| m.as_str().parse().unwrap());
-            (name, index)
+impl Data {
+    fn type_name(&self) -> &'static str {
+        match self.slice {
+            Some(_) => "game_util::sprite::NineSlice",
+            None => "Sprite",
         }
-        None => panic!("invalid name: {}", name),
     }
 }
 
-fn process_img(packer: &mut MultiTexturePacker<RgbaImage>, key: &str, path: &Path) -> Option<Data> {
-    let mut img = ImageImporter::import_from_file(path).ok()?.to_rgba8();
+/// Frame durations in milliseconds, events and slices of an animation.
+#[derive(Debug)]
+struct Timing {
+    /// The timing file or Aseprite file, for error messages.
+    path: PathBuf,
+    mode: PlaybackMode,
+    duration: u32,
+    durations: HashMap<usize, u32>,
+    events: HashMap<usize, String>,
+    slices: HashMap<usize, Vec<FrameSlice>>,
+}
 
-    let width = img.width();
-    let height = img.height();
+/// A named rectangle on an animation frame, in pixels from the bottom-left corner.
+#[derive(Debug)]
+struct FrameSlice {
+    name: String,
+    rect: [i32; 4],
+    pivot: Option<(i32, i32)>,
+}
 
-    let mut add_top_border = false;
-    let mut add_bottom_border = false;
-    for x in 0..width {
-        if img.get_pixel(x, 0).0[3] != 0 {
-            add_top_border = true;
-        }
-        if img.get_pixel(x, height - 1).0[3] != 0 {
-            add_bottom_border = true;
-        }
-    }
+/// Written to the generated code with `Debug`, like `SliceMode`.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub(crate) enum PlaybackMode {
+    Loop,
+    PingPong,
+    Once,
+}
 
-    let mut add_left_border = false;
-    let mut add_right_border = false;
-    for y in 0..height {
-        if img.get_pixel(0, y).0[3] != 0 {
-            add_left_border = true;
-        }
-        if img.get_pixel(width - 1, y).0[3] != 0 {
-            add_right_border = true;
+#[derive(Clone, Debug)]
+struct Slice {
+    /// Top, right, bottom and left.
+    insets: [u32; 4],
+    edges: SliceMode,
+    center: SliceMode,
+}
+
+/// Written to the generated code with `Debug`, so the names match `game_util::sprite::SliceMode`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub(crate) enum SliceMode {
+    Stretch,
+    Tile,
+}
+
+/// The parts of a file or directory name.
+struct NameParts {
+    /// The field name, with the names of the parent directories.
+    name: String,
+    index: Option<usize>,
+    /// Nine-slice insets.
+    insets: Option<[u32; 4]>,
+    /// From a suffix like `@2x`.
+    scale: Option<u32>,
+}
+
+/// Splits a file or directory name into its parts. Directories don't need an extension.
+fn process_name(parent_name: Option<&str>, name: &str) -> Result<NameParts, ProblemKind> {
+    lazy_static::lazy_static! {
+        static ref REGEX: Regex =
+            Regex::new(r"^([_a-zA-Z][_\w]*)(?:.(\d+))?(?:\.9((?:-\d+)+))?(?:@(\d+)x)?(?:\.\w+)?$")
+                .unwrap();
+    };
+
+    let caps = REGEX.captures(name).ok_or(ProblemKind::InvalidName)?;
+    let name = caps.get(1).unwrap().as_str();
+    let name = match parent_name {
+        Some(p) => format!("{}_{}", p, name),
+        None => name.to_owned(),
+    };
+    let index = match caps.get(2) {
+        Some(m) => Some(m.as_str().parse().map_err(|_| ProblemKind::InvalidName)?),
+        None => None,
+    };
+    let insets = match caps.get(3) {
+        Some(m) => {
+            Some(parse_insets(m.as_str()[1..].split('-')).map_err(ProblemKind::InvalidSettings)?)
         }
-    }
+        None => None,
+    };
+    let scale = match caps.get(4) {
+        Some(m) => match m.as_str().parse() {
+            Ok(0) | Err(_) => return Err(ProblemKind::InvalidName),
+            Ok(scale) => Some(scale),
+        },
+        None => None,
+    };
+    Ok(NameParts {
+        name,
+        index,
+        insets,
+        scale,
+    })
+}
 
-    if add_right_border || add_left_border || add_top_border || add_bottom_border {
-        let new_w = add_left_border as u32 + add_right_border as u32 + width;
-        let new_h = add_top_border as u32 + add_bottom_border as u32 + height;
-        let offset_x = add_left_border as u32;
-        let offset_y = add_top_border as u32;
+/// Parses one, two or four insets, in the order of CSS margins.
+fn parse_insets<'a>(values: impl Iterator<Item = &'a str>) -> Result<[u32; 4], String> {
+    let values = values
+        .map(|v| {
+            v.parse()
+                .map_err(|_| format!("invalid nine-slice inset {:?}", v))
+        })
+        .collect::<Result<Vec<u32>, _>>()?;
+    match values[..] {
+        [all] => Ok([all; 4]),
+        [vertical, horizontal] => Ok([vertical, horizontal, vertical, horizontal]),
+        [top, right, bottom, left] => Ok([top, right, bottom, left]),
+        _ => Err(format!(
+            "expected 1, 2 or 4 nine-slice insets, not {}",
+            values.len()
+        )),
+    }
+}
 
-        let base = img;
-        img = RgbaImage::new(new_w, new_h);
+/// Reads `key = value` lines from a sidecar file, skipping empty lines and `#` comments.
+fn read_settings(path: &Path) -> Result<Vec<(String, String)>, ProblemKind> {
+    let text = std::fs::read_to_string(path).map_err(ProblemKind::Io)?;
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.find('=') {
+            Some(i) => Ok((line[..i].trim().to_owned(), line[i + 1..].trim().to_owned())),
+            None => Err(ProblemKind::InvalidSettings(format!(
+                "expected `key = value` in {}: {}",
+                path.display(),
+                line
+            ))),
+        })
+        .collect()
+}
 
-        img.copy_from(&base, offset_x, offset_y).unwrap();
+/// Reads the nine-slice settings of the image at `path` from its sidecar file, if it has one,
+/// or uses the insets from its file name.
+fn process_slice(path: &Path, insets: Option<[u32; 4]>) -> Result<Option<Slice>, ProblemKind> {
+    let sidecar = path.with_extension("slice");
+    if !sidecar.exists() {
+        return Ok(insets.map(|insets| Slice {
+            insets,
+            edges: SliceMode::Stretch,
+            center: SliceMode::Stretch,
+        }));
+    }
 
-        for x in 0..width {
-            if add_top_border {
-                img.put_pixel(offset_x + x, 0, *base.get_pixel(x, 0));
-            }
-            if add_bottom_border {
-                img.put_pixel(
-                    offset_x + x,
-                    img.height() - 1,
-                    *base.get_pixel(x, height - 1),
-                );
+    let name = sidecar.display();
+    let invalid =
+        |message: String| ProblemKind::InvalidSettings(format!("{} in {}", message, name));
+    let mut slice = Slice {
+        insets: insets.unwrap_or([0; 4]),
+        edges: SliceMode::Stretch,
+        center: SliceMode::Stretch,
+    };
+    let mut has_insets = insets.is_some();
+    for (key, value) in read_settings(&sidecar)? {
+        let mode = || match &value[..] {
+            "stretch" => Ok(SliceMode::Stretch),
+            "tile" => Ok(SliceMode::Tile),
+            _ => Err(invalid(format!("unknown nine-slice mode {:?}", value))),
+        };
+        match &key[..] {
+            "insets" => {
+                slice.insets = parse_insets(value.split_whitespace()).map_err(invalid)?;
+                has_insets = true;
             }
+            "edges" => slice.edges = mode()?,
+            "center" => slice.center = mode()?,
+            _ => return Err(invalid(format!("unknown nine-slice setting {:?}", key))),
         }
+    }
+    if !has_insets {
+        return Err(invalid("no insets".to_owned()));
+    }
+    Ok(Some(slice))
+}
 
-        for y in 0..height {
-            if add_left_border {
-                img.put_pixel(0, offset_y + y, *base.get_pixel(0, y));
+/// Reads an animation timing file.
+fn process_timing(path: &Path) -> Result<Timing, ProblemKind> {
+    let invalid = |message: String| ProblemKind::InvalidSettings(message);
+    let parse = |v: &str| -> Result<u32, ProblemKind> {
+        v.parse()
+            .map_err(|_| invalid(format!("invalid number {:?}", v)))
+    };
+    let mut timing = Timing {
+        path: path.to_owned(),
+        mode: PlaybackMode::Loop,
+        duration: 100,
+        durations: HashMap::new(),
+        events: HashMap::new(),
+        slices: HashMap::new(),
+    };
+    for (key, value) in read_settings(path)? {
+        let (key, frame) = match key.find('.') {
+            Some(i) => (&key[..i], Some(parse(&key[i + 1..])? as usize)),
+            None => (&key[..], None),
+        };
+        match (key, frame) {
+            ("mode", None) => {
+                timing.mode = match &value[..] {
+                    "loop" => PlaybackMode::Loop,
+                    "ping_pong" => PlaybackMode::PingPong,
+                    "once" => PlaybackMode::Once,
+                    _ => return Err(invalid(format!("unknown animation mode {:?}", value))),
+                }
+            }
+            ("duration", None) => timing.duration = parse(&value)?,
+            ("duration", Some(i)) => {
+                timing.durations.insert(i, parse(&value)?);
             }
-            if add_right_border {
-                img.put_pixel(img.width() - 1, offset_y + y, *base.get_pixel(width - 1, y));
+            ("event", Some(i)) => {
+                timing.events.insert(i, value);
             }
+            _ => return Err(invalid(format!("unknown animation setting {:?}", key))),
         }
+    }
+    Ok(timing)
+}
 
-        if add_left_border && add_top_border {
-            img.put_pixel(0, 0, *base.get_pixel(0, 0));
+/// Packs the frames of an Aseprite file.
+///
+/// Each tag becomes an animation named `<name>_<tag>`. Without tags, a file with several frames
+/// becomes an animation and a file with one frame a sprite. Each nine-patch slice becomes a
+/// nine-slice named `<name>_<slice>`, cut from the first frame; other slices are attached to
+/// the frames of animations, for hitboxes and the like.
+fn process_aseprite(input: &mut Input, path: &Path, name: &str) {
+    let file = match aseprite::load(path) {
+        Ok(file) => file,
+        Err(e) => {
+            input
+                .problems
+                .push(Problem::new(path, ProblemKind::InvalidAseprite(e)));
+            return;
         }
-        if add_left_border && add_bottom_border {
-            img.put_pixel(0, img.height() - 1, *base.get_pixel(0, height - 1));
-        }
-        if add_right_border && add_top_border {
-            img.put_pixel(img.width() - 1, 0, *base.get_pixel(width - 1, 0));
+    };
+
+    let mut has_nine_slices = false;
+    for slice in &file.slices {
+        let (key, center) = match slice.key(0) {
+            Some(key) => match key.center {
+                Some(center) => (key, center),
+                None => continue,
+            },
+            None => continue,
+        };
+        has_nine_slices = true;
+        let field = format!("{}_{}", name, field_name(&slice.name));
+        // Nine-patch slices were checked to be on the canvas, with their centers inside them.
+        let [x, y, w, h] = key.bounds;
+        let img = image::imageops::crop_imm(
+            &file.frames[0].image,
+            x as u32,
+            y as u32,
+            w as u32,
+            h as u32,
+        )
+        .to_image();
+        if let Some(mut data) = add_img(input, path, img) {
+            let [cx, cy, cw, ch] = center;
+            data.slice = Some(Slice {
+                insets: [
+                    cy as u32,
+                    (w - cx - cw) as u32,
+                    (h - cy - ch) as u32,
+                    cx as u32,
+                ],
+                edges: SliceMode::Stretch,
+                center: SliceMode::Stretch,
+            });
+            input.insert(field, Kind::Just(Variants::one(data)));
         }
-        if add_right_border && add_bottom_border {
-            img.put_pixel(
-                img.width() - 1,
-                img.height() - 1,
-                *base.get_pixel(width - 1, height - 1),
-            );
+    }
+
+    // Frames are packed once, even if several tags use them.
+    let mut packed: Vec<Option<Option<Data>>> = vec![None; file.frames.len()];
+    let mut frame = |input: &mut Input, i: usize| {
+        packed[i]
+            .get_or_insert_with(|| add_img(input, path, file.frames[i].image.clone()))
+            .clone()
+    };
+
+    let mut animations = vec![];
+    if file.tags.is_empty() {
+        if file.frames.len() > 1 {
+            animations.push((
+                name.to_owned(),
+                (0..file.frames.len()).collect(),
+                PlaybackMode::Loop,
+            ));
+        } else if !has_nine_slices {
+            if let Some(data) = frame(input, 0) {
+                input.insert(name.to_owned(), Kind::Just(Variants::one(data)));
+            }
         }
     }
+    for tag in &file.tags {
+        // Tag ranges were checked when the file was read.
+        let mut frames: Vec<usize> = (tag.from..=tag.to).collect();
+        let mode = match tag.direction {
+            aseprite::Direction::Forward => PlaybackMode::Loop,
+            aseprite::Direction::Reverse => {
+                frames.reverse();
+                PlaybackMode::Loop
+            }
+            aseprite::Direction::PingPong => PlaybackMode::PingPong,
+            aseprite::Direction::PingPongReverse => {
+                frames.reverse();
+                PlaybackMode::PingPong
+            }
+        };
+        // Tags that repeat a limited number of times are approximated by playing once.
+        let mode = match (mode, tag.repeat) {
+            (PlaybackMode::Loop, 0) => PlaybackMode::Loop,
+            (PlaybackMode::Loop, _) => PlaybackMode::Once,
+            (mode, _) => mode,
+        };
+        animations.push((format!("{}_{}", name, field_name(&tag.name)), frames, mode));
+    }
 
-    packer.pack_own(key.to_string(), img).unwrap();
-    let mut frame = None;
-    for (i, page) in packer.get_pages().iter().enumerate() {
-        if let Some(f) = page.get_frame(&key) {
-            frame = Some((i, f));
+    for (field, frames, mode) in animations {
+        let mut timing = Timing {
+            path: path.to_owned(),
+            mode,
+            duration: 100,
+            durations: HashMap::new(),
+            events: HashMap::new(),
+            slices: HashMap::new(),
+        };
+        let mut sprites = vec![];
+        for (i, &f) in frames.iter().enumerate() {
+            match frame(input, f) {
+                Some(data) => sprites.push(Some(Variants::one(data))),
+                // Already reported.
+                None => return,
+            }
+            timing.durations.insert(i, file.frames[f].duration);
+            let slices = file
+                .slices
+                .iter()
+                .filter_map(|slice| {
+                    let key = slice.key(f).filter(|k| k.center.is_none())?;
+                    let [x, y, w, h] = key.bounds;
+                    if w == 0 || h == 0 {
+                        return None;
+                    }
+                    // Flip y to point up, like sprite pivots.
+                    let bottom = file.height as i32 - y - h;
+                    Some(FrameSlice {
+                        name: slice.name.clone(),
+                        rect: [x, bottom, w, h],
+                        pivot: key
+                            .pivot
+                            .map(|(px, py)| (x + px, file.height as i32 - y - py)),
+                    })
+                })
+                .collect();
+            timing.slices.insert(i, slices);
         }
+        input.insert(field.clone(), Kind::Array(sprites));
+        input.timings.insert(field, timing);
     }
-    let (layer, frame) = frame.unwrap();
+}
 
-    let mut data = Data {
-        tex: frame.frame,
-        real_size: (width, height),
-        layer,
-        rotated: frame.rotated,
-    };
+/// Turns a tag or slice name into a field name.
+fn field_name(name: &str) -> String {
+    let mut field: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect();
+    if field.starts_with(|c: char| c.is_ascii_digit()) {
+        field.insert(0, '_');
+    }
+    field
+}
 
-    if add_top_border {
-        data.tex.h -= 1;
-        data.tex.y += 1;
+/// Finds the smallest rectangle containing all pixels that aren't fully transparent, as x, y,
+/// width and height from the top-left corner. Fully transparent images keep one pixel, since
+/// empty images can't be packed.
+fn opaque_bounds(img: &RgbaImage) -> (u32, u32, u32, u32) {
+    let (mut min_x, mut min_y) = (u32::MAX, u32::MAX);
+    let (mut max_x, mut max_y) = (0, 0);
+    for (x, y, pixel) in img.enumerate_pixels() {
+        if pixel.0[3] != 0 {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
     }
-    if add_bottom_border {
-        data.tex.h -= 1;
+    if min_x > max_x {
+        return (0, 0, 1, 1);
     }
+    (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1)
+}
 
-    if add_left_border {
-        data.tex.w -= 1;
-        data.tex.x += 1;
-    }
-    if add_right_border {
-        data.tex.w -= 1;
+/// Trims and extrudes an image from `path` and adds it to the images to pack, reporting it if
+/// it doesn't fit on a page.
+fn add_img(input: &mut Input, path: &Path, img: RgbaImage) -> Option<Data> {
+    let atlas = input.atlas;
+    let real_size = img.dimensions();
+    let (trim_x, trim_y, width, height) = opaque_bounds(&img);
+    let trim_offset = (trim_x, real_size.1 - trim_y - height);
+
+    let e = atlas.extrusion;
+    let packer = Packer::new(
+        atlas.width,
+        atlas.height,
+        0,
+        atlas.heuristic,
+        atlas.allow_rotation,
+    );
+    if !packer.fits(width + 2 * e, height + 2 * e) {
+        input.problems.push(Problem::new(
+            path,
+            ProblemKind::TooLarge {
+                width,
+                height,
+                page_width: atlas.width,
+                page_height: atlas.height,
+            },
+        ));
+        return None;
     }
 
-    Some(data)
+    // The edge pixels are repeated outwards.
+    let extruded = RgbaImage::from_fn(width + 2 * e, height + 2 * e, |x, y| {
+        let x = x.saturating_sub(e).min(width - 1);
+        let y = y.saturating_sub(e).min(height - 1);
+        *img.get_pixel(trim_x + x, trim_y + y)
+    });
+    input.images.push(extruded);
+
+    Some(Data {
+        path: path.to_owned(),
+        image: input.images.len() - 1,
+        trim_offset,
+        real_size,
+        slice: None,
+    })
 }
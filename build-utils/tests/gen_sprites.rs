@@ -0,0 +1,537 @@
+use build_utils::{AtlasBuilder, ManifestFormat, ProblemKind};
+use game_util::animation::PlaybackMode;
+use game_util::atlas::{Manifest, ManifestEntry};
+use image::{Rgba, RgbaImage};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory with `sprites`, `images` and `code` subdirectories.
+fn fixture(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("build-utils-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for sub in &["sprites", "images", "code"] {
+        fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    dir
+}
+
+fn sprite(dir: &Path, path: &str, w: u32, h: u32) {
+    let path = dir.join("sprites").join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    RgbaImage::from_pixel(w, h, Rgba([255, 0, 0, 255]))
+        .save(path)
+        .unwrap();
+}
+
+fn generate(dir: &Path) -> String {
+    generate_to(dir, "images", "code")
+}
+
+fn problems(dir: &Path) -> Vec<build_utils::Problem> {
+    build_utils::gen_sprites(
+        dir.join("sprites"),
+        dir.join("images"),
+        dir.join("code"),
+        64,
+    )
+    .unwrap_err()
+    .problems
+}
+
+fn generate_to(dir: &Path, images: &str, code: &str) -> String {
+    build_utils::gen_sprites(dir.join("sprites"), dir.join(images), dir.join(code), 64).unwrap();
+    fs::read_to_string(dir.join(code).join("sprites.rs")).unwrap()
+}
+
+fn builder(dir: &Path) -> AtlasBuilder {
+    AtlasBuilder::new(dir.join("sprites"), dir.join("images"), dir.join("code"))
+}
+
+fn code(dir: &Path) -> String {
+    fs::read_to_string(dir.join("code/sprites.rs")).unwrap()
+}
+
+/// A `w` by `h` sprite that is transparent except for `opaque`, given as x, y, width and height
+/// in image coordinates.
+fn trimmed_sprite(dir: &Path, path: &str, w: u32, h: u32, opaque: (u32, u32, u32, u32)) {
+    let (x, y, width, height) = opaque;
+    RgbaImage::from_fn(w, h, |px, py| {
+        let inside = (x..x + width).contains(&px) && (y..y + height).contains(&py);
+        Rgba([255, 0, 0, if inside { 255 } else { 0 }])
+    })
+    .save(dir.join("sprites").join(path))
+    .unwrap();
+}
+
+fn aseprite(dir: &Path, fixture: &str, name: &str) {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(fixture);
+    fs::copy(source, dir.join("sprites").join(name)).unwrap();
+}
+
+/// Decodes a manifest from the image directory as `game_util::atlas::SpriteAtlas` does, since
+/// the two crates declare the manifest types separately.
+fn decode_manifest(dir: &Path, name: &str) -> Manifest {
+    Manifest::decode(&fs::read(dir.join("images").join(name)).unwrap(), name).unwrap()
+}
+
+#[test]
+fn sprites_and_arrays() {
+    let dir = fixture("arrays");
+    sprite(&dir, "ball.png", 8, 8);
+    sprite(&dir, "walk.0.png", 4, 6);
+    sprite(&dir, "walk.1.png", 4, 6);
+    sprite(&dir, "walk.2.png", 4, 6);
+
+    let code = generate(&dir);
+    assert!(code.contains("pub ball: Sprite,"));
+    assert!(code.contains("pub walk: [Sprite; 3],"));
+    assert!(code.contains("real_size: size2(4.0, 6.0)"));
+    assert!(dir.join("images/0.png").exists());
+    // Pages are cropped, so the layers they're loaded into are cleared.
+    assert!(code.contains("TextureArray::new_cleared("));
+}
+
+#[test]
+fn nested_folders() {
+    let dir = fixture("nested");
+    sprite(&dir, "ui/button.png", 8, 4);
+    sprite(&dir, "ui/icons/heart.png", 5, 5);
+    sprite(&dir, "ui/icons/star.0.png", 5, 5);
+
+    let code = generate(&dir);
+    assert!(code.contains("pub ui_button: Sprite,"));
+    assert!(code.contains("pub ui_icons_heart: Sprite,"));
+    assert!(code.contains("pub ui_icons_star: [Sprite; 1],"));
+}
+
+#[test]
+fn output_is_sorted_and_deterministic() {
+    let dir = fixture("deterministic");
+    for name in &["zebra", "apple", "mango", "kiwi"] {
+        sprite(&dir, &format!("{}.png", name), 7, 5);
+    }
+
+    let first = generate_to(&dir, "images", "code");
+    fs::create_dir_all(dir.join("code2")).unwrap();
+    let second = generate_to(&dir, "images2", "code2");
+    assert_eq!(first, second);
+    assert_eq!(
+        fs::read(dir.join("images/0.png")).unwrap(),
+        fs::read(dir.join("images2/0.png")).unwrap()
+    );
+
+    let position = |field: &str| first.find(&format!("pub {}:", field)).unwrap();
+    assert!(position("apple") < position("kiwi"));
+    assert!(position("kiwi") < position("mango"));
+    assert!(position("mango") < position("zebra"));
+}
+
+#[test]
+fn unchanged_inputs_are_not_repacked() {
+    let dir = fixture("incremental");
+    sprite(&dir, "ball.png", 8, 8);
+    generate(&dir);
+
+    // A stand-in for the atlas that only survives if nothing is repacked.
+    fs::write(dir.join("images/0.png"), "stale").unwrap();
+    generate(&dir);
+    assert_eq!(fs::read(dir.join("images/0.png")).unwrap(), b"stale");
+
+    sprite(&dir, "ball.png", 9, 8);
+    let code = generate(&dir);
+    assert_ne!(fs::read(dir.join("images/0.png")).unwrap(), b"stale");
+    assert!(code.contains("real_size: size2(9.0, 8.0)"));
+
+    // Missing output is regenerated even if the inputs are the same.
+    fs::remove_file(dir.join("images/0.png")).unwrap();
+    generate(&dir);
+    assert!(dir.join("images/0.png").exists());
+}
+
+#[test]
+fn missing_array_index() {
+    let dir = fixture("missing-index");
+    sprite(&dir, "walk.0.png", 4, 4);
+    sprite(&dir, "walk.2.png", 4, 4);
+    sprite(&dir, "walk.5.png", 4, 4);
+
+    let problems = problems(&dir);
+    let missing: Vec<_> = problems
+        .iter()
+        .map(|p| match &p.kind {
+            ProblemKind::MissingIndex { array, index } if array == "walk" => *index,
+            kind => panic!("unexpected problem: {}", kind),
+        })
+        .collect();
+    assert_eq!(missing, [1, 3, 4]);
+}
+
+#[test]
+fn every_invalid_name_is_reported() {
+    let dir = fixture("invalid-name");
+    sprite(&dir, "2fast.png", 4, 4);
+    sprite(&dir, "ui/bad-name.png", 4, 4);
+    sprite(&dir, "ok.png", 4, 4);
+
+    let problems = problems(&dir);
+    assert_eq!(problems.len(), 2);
+    assert!(problems
+        .iter()
+        .all(|p| matches!(p.kind, ProblemKind::InvalidName)));
+    assert!(problems[0].path.ends_with("2fast.png"));
+    assert!(problems[1].path.ends_with("ui/bad-name.png"));
+    assert!(problems[0].to_string().contains("2fast.png: invalid name"));
+}
+
+#[test]
+fn duplicate_index() {
+    let dir = fixture("duplicate-index");
+    sprite(&dir, "walk.0.png", 4, 4);
+    sprite(&dir, "walk.00.png", 4, 4);
+
+    let problems = problems(&dir);
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].path.ends_with("walk.00.png"));
+    match &problems[0].kind {
+        ProblemKind::DuplicateIndex { array, index } => {
+            assert_eq!((&array[..], *index), ("walk", 0))
+        }
+        kind => panic!("unexpected problem: {}", kind),
+    }
+}
+
+#[test]
+fn sprite_and_array_with_the_same_name() {
+    let dir = fixture("mixed");
+    sprite(&dir, "walk.png", 4, 4);
+    sprite(&dir, "walk.0.png", 4, 4);
+
+    let problems = problems(&dir);
+    assert_eq!(problems.len(), 1);
+    assert!(matches!(&problems[0].kind, ProblemKind::MixedKinds(name) if name == "walk"));
+}
+
+#[test]
+fn sprite_too_large_for_the_page() {
+    let dir = fixture("too-large");
+    sprite(&dir, "small.png", 4, 4);
+    sprite(&dir, "huge.png", 100, 20);
+
+    let problems = problems(&dir);
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].path.ends_with("huge.png"));
+    assert!(matches!(
+        problems[0].kind,
+        ProblemKind::TooLarge {
+            width: 100,
+            height: 20,
+            page_width: 64,
+            page_height: 64,
+        }
+    ));
+}
+
+#[test]
+fn non_square_pages_and_report() {
+    let dir = fixture("report");
+    for i in 0..10 {
+        sprite(&dir, &format!("tile.{}.png", i), 8, 8);
+    }
+
+    let report = builder(&dir)
+        .page_size(32, 16)
+        .padding(0)
+        .extrusion(0)
+        .generate()
+        .unwrap();
+    assert_eq!(report.pages.len(), 2);
+    assert_eq!(report.pages[0].sprites, 8);
+    assert_eq!(report.pages[0].utilization(), 1.0);
+    assert_eq!(report.pages[1].sprites, 2);
+    assert_eq!(report.pages[1].used_pixels, 128);
+    assert!(code(&dir).contains("32, 16, 2,"));
+    assert!(report
+        .to_string()
+        .starts_with("page 0: 8 sprites, 100.0% of 32x16 used\n"));
+
+    // The report survives runs that skip packing.
+    let again = builder(&dir)
+        .page_size(32, 16)
+        .padding(0)
+        .extrusion(0)
+        .generate()
+        .unwrap();
+    assert_eq!(again, report);
+}
+
+#[test]
+fn rotation() {
+    let dir = fixture("rotation");
+    sprite(&dir, "long.png", 40, 10);
+
+    builder(&dir).page_size(16, 64).generate().unwrap();
+    assert!(code(&dir).contains("rotated: true"));
+
+    let problems = builder(&dir)
+        .page_size(16, 64)
+        .allow_rotation(false)
+        .generate()
+        .unwrap_err()
+        .problems;
+    assert!(matches!(problems[0].kind, ProblemKind::TooLarge { .. }));
+}
+
+#[test]
+fn trimming() {
+    let dir = fixture("trimming");
+    // Square, so never turned.
+    trimmed_sprite(&dir, "small.png", 10, 8, (2, 1, 4, 4));
+    // Only fits the page turned.
+    trimmed_sprite(&dir, "long.png", 24, 12, (1, 2, 20, 6));
+
+    builder(&dir)
+        .page_size(16, 32)
+        .padding(0)
+        .extrusion(0)
+        .generate()
+        .unwrap();
+    // The sprites in field order, from the trimmed size on.
+    let code = code(&dir).replace(char::is_whitespace, "");
+    let sprites: Vec<_> = code
+        .split("trimmed_size:")
+        .skip(1)
+        .map(|sprite| &sprite[..sprite.find('}').unwrap()])
+        .collect();
+
+    // Trim offsets are measured from the bottom left, and the trimmed size is not turned with
+    // the sprite.
+    assert_eq!(
+        sprites,
+        [
+            "size2(20.0,6.0),trim_offset:vec2(1.0,4.0),real_size:size2(24.0,12.0),\
+             layer:0.0,rotated:true",
+            "size2(4.0,4.0),trim_offset:vec2(2.0,3.0),real_size:size2(10.0,8.0),\
+             layer:0.0,rotated:false",
+        ]
+    );
+}
+
+#[test]
+fn extrusion_repeats_edge_pixels() {
+    let dir = fixture("extrusion");
+    sprite(&dir, "ball.png", 4, 4);
+
+    builder(&dir).extrusion(2).generate().unwrap();
+    let atlas = image::open(dir.join("images/0.png")).unwrap().to_rgba8();
+    assert_eq!(atlas.dimensions(), (8, 8));
+    assert!(atlas.pixels().all(|p| p.0 == [255, 0, 0, 255]));
+    assert!(
+        code(&dir).contains("tex: rect(2.0 / 1024.0, 2.0 / 1024.0, 4.0 / 1024.0, 4.0 / 1024.0)")
+    );
+}
+
+#[test]
+fn padding_separates_sprites() {
+    let dir = fixture("padding");
+    sprite(&dir, "a.png", 4, 4);
+    sprite(&dir, "b.png", 4, 4);
+
+    builder(&dir)
+        .page_size(64, 4)
+        .padding(3)
+        .extrusion(0)
+        .generate()
+        .unwrap();
+    let atlas = image::open(dir.join("images/0.png")).unwrap().to_rgba8();
+    assert_eq!(atlas.dimensions(), (11, 4));
+    assert_eq!(atlas.get_pixel(5, 0).0, [0; 4]);
+}
+
+#[test]
+fn manifest() {
+    let dir = fixture("manifest");
+    sprite(&dir, "ball.png", 8, 6);
+    sprite(&dir, "ball@2x.png", 16, 12);
+    sprite(&dir, "button.0.9-2.png", 8, 8);
+    sprite(&dir, "button.1.9-2.png", 8, 8);
+    sprite(&dir, "walk.0.png", 4, 4);
+    sprite(&dir, "walk.1.png", 4, 4);
+    fs::write(
+        dir.join("sprites/walk.anim"),
+        "mode = once\nevent.1 = step\n",
+    )
+    .unwrap();
+    aseprite(&dir, "hero.aseprite", "hero.aseprite");
+
+    for &(format, extension) in &[
+        (ManifestFormat::Json, "json"),
+        (ManifestFormat::Bincode, "bin"),
+    ] {
+        builder(&dir).manifest(format).generate().unwrap();
+
+        let manifest = decode_manifest(&dir, &format!("sprites.{}", extension));
+        assert_eq!(manifest.pages, ["0.png"]);
+        assert_eq!(manifest.page_width, 1024);
+        assert!(!manifest.premultiplied_alpha);
+        let sprite = |name: &str| match &manifest.entries[name] {
+            ManifestEntry::Sprite(sprite) => sprite.clone(),
+            _ => panic!("{} is not a sprite", name),
+        };
+        let ball = sprite("ball");
+        assert_eq!((ball.real_size, ball.scale), ([8, 6], 1));
+        assert!(ball.nine_slice.is_none());
+        assert!(sprite("hero_panel").nine_slice.is_some());
+
+        match &manifest.entries["button"] {
+            ManifestEntry::Array(buttons) => {
+                assert_eq!(buttons.len(), 2);
+                for button in buttons {
+                    assert_eq!(button.nine_slice.as_ref().unwrap().insets, [2; 4]);
+                }
+            }
+            _ => panic!("button is not an array"),
+        }
+        match &manifest.entries["walk"] {
+            ManifestEntry::Animation { frames, mode } => {
+                assert_eq!(*mode, PlaybackMode::Once);
+                assert_eq!(frames.len(), 2);
+                assert_eq!(frames[0].duration, 0.1);
+                assert_eq!(frames[1].event.as_deref(), Some("step"));
+            }
+            _ => panic!("walk is not an animation"),
+        }
+        match &manifest.entries["hero_idle_loop"] {
+            ManifestEntry::Animation { frames, mode } => {
+                assert_eq!(*mode, PlaybackMode::PingPong);
+                let hit = &frames[0].slices[0];
+                assert_eq!(hit.name, "hit");
+                assert_eq!(hit.rect, [1, 4, 3, 3]);
+                assert_eq!(hit.pivot, Some([2, 6]));
+            }
+            _ => panic!("hero_idle_loop is not an animation"),
+        }
+
+        let manifest = decode_manifest(&dir, &format!("sprites@2x.{}", extension));
+        assert_eq!(manifest.pages, ["0@2x.png"]);
+        match &manifest.entries["ball"] {
+            ManifestEntry::Sprite(ball) => assert_eq!((ball.real_size, ball.scale), ([16, 12], 2)),
+            _ => panic!("ball is not a sprite"),
+        }
+    }
+}
+
+#[test]
+fn scale_variants() {
+    let dir = fixture("variants");
+    sprite(&dir, "ball.png", 8, 6);
+    sprite(&dir, "ball@2x.png", 16, 12);
+    sprite(&dir, "star.png", 5, 5);
+    sprite(&dir, "walk.0@2x.png", 10, 10);
+
+    let report = builder(&dir)
+        .manifest(ManifestFormat::Json)
+        .generate()
+        .unwrap();
+    assert_eq!(
+        report.pages.iter().map(|p| p.scale).collect::<Vec<_>>(),
+        [1, 2]
+    );
+    assert!(report.to_string().contains("\npage 0@2x: 3 sprites"));
+    assert!(dir.join("images/0.png").exists());
+    assert!(dir.join("images/0@2x.png").exists());
+
+    let code = code(&dir);
+    assert!(code.contains("pub const SCALES: &[u32] = &[1, 2];"));
+    assert!(code.contains("\"/0@2x.png\""));
+    // Sizes are the same at both scales, and sprites without a variant at a scale use the
+    // closest one.
+    assert_eq!(code.matches("real_size: size2(8.0, 6.0)").count(), 2);
+    assert_eq!(code.matches("real_size: size2(5.0, 5.0)").count(), 4);
+
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&fs::read(dir.join("images/sprites@2x.json")).unwrap()).unwrap();
+    assert_eq!(manifest["pages"], serde_json::json!(["0@2x.png"]));
+    let ball = &manifest["entries"]["ball"]["Sprite"];
+    assert_eq!(ball["real_size"], serde_json::json!([16, 12]));
+    assert_eq!(ball["scale"], 2);
+    assert_eq!(manifest["entries"]["star"]["Sprite"]["scale"], 1);
+}
+
+#[test]
+fn mismatched_scale_variant() {
+    let dir = fixture("mismatched-variant");
+    sprite(&dir, "ball.png", 8, 6);
+    sprite(&dir, "ball@2x.png", 16, 16);
+    sprite(&dir, "ball@3x.png", 24, 18);
+    // Insets are scaled like sizes.
+    sprite(&dir, "button.9-4.png", 16, 16);
+    sprite(&dir, "button.9-4@2x.png", 32, 32);
+    sprite(&dir, "panel.9-4.png", 16, 16);
+    sprite(&dir, "panel.9-8@2x.png", 32, 32);
+
+    let problems = problems(&dir);
+    assert_eq!(problems.len(), 2);
+    assert!(problems[0].path.ends_with("ball@2x.png"));
+    assert!(matches!(
+        &problems[0].kind,
+        ProblemKind::MismatchedVariant { name, scale: 2 } if name == "ball"
+    ));
+    assert!(problems[1].path.ends_with("button.9-4@2x.png"));
+    assert!(matches!(
+        &problems[1].kind,
+        ProblemKind::MismatchedVariant { name, scale: 2 } if name == "button"
+    ));
+}
+
+#[test]
+fn aseprite_layers_tags_linked_cels_and_slices() {
+    let dir = fixture("aseprite");
+    aseprite(&dir, "hero.aseprite", "hero.aseprite");
+
+    let code = generate(&dir);
+    assert!(code.contains("pub hero_walk: game_util::animation::Animation,"));
+    assert!(code.contains("pub hero_idle_loop: game_util::animation::Animation,"));
+    assert!(code.contains("pub hero_panel: game_util::sprite::NineSlice,"));
+    assert!(code.contains("game_util::animation::PlaybackMode::PingPong"));
+    assert!(code.contains("duration: 0.15,"));
+    assert!(code.contains("SideOffsets2D::new(2.0, 2.0, 2.0, 2.0)"));
+    // The hitbox is flipped to count from the bottom, and attached to every frame.
+    assert_eq!(
+        code.matches("name: \"hit\".to_owned(),rect: rect(1.0, 4.0, 3.0, 3.0),pivot: Some(point2(2.0, 6.0)),")
+            .count(),
+        3
+    );
+    // Frames 1 and 2 link the background cel of frame 0, so none are trimmed.
+    assert_eq!(code.matches("trimmed_size: size2(8.0, 8.0)").count(), 4);
+
+    // The hidden layer is left out, and the layer in the group is drawn over the background.
+    let atlas = image::open(dir.join("images/0.png")).unwrap().to_rgba8();
+    assert!(atlas.pixels().all(|p| p.0 != [0, 255, 0, 255]));
+    assert!(atlas.pixels().any(|p| p.0 == [0, 0, 255, 255]));
+}
+
+#[test]
+fn malformed_aseprite_files() {
+    let dir = fixture("aseprite-malformed");
+    aseprite(&dir, "no_frames.aseprite", "empty.aseprite");
+    aseprite(&dir, "bad_tag.aseprite", "tag.aseprite");
+    aseprite(&dir, "bad_slice.aseprite", "slice.aseprite");
+
+    let messages: Vec<_> = problems(&dir)
+        .iter()
+        .map(|p| match &p.kind {
+            ProblemKind::InvalidAseprite(message) => message.clone(),
+            kind => panic!("unexpected problem: {}", kind),
+        })
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "the file has no frames",
+            "the center of nine-patch slice \"panel\" is outside the slice",
+            "tag \"run\" covers frames 0 to 3, but the file has 1 frames",
+        ]
+    );
+}
@@ -7,5 +7,6 @@ fn main() {
         "res/generated",
         Path::new(&env::var("OUT_DIR").unwrap()),
         128,
-    );
+    )
+    .unwrap_or_else(|e| e.exit());
 }
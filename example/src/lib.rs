@@ -110,10 +110,6 @@ impl game_util::Game for Game {
 
             self.gl.clear_color(0.25, 0.5, 1.0, 1.0);
             self.gl.clear(glow::COLOR_BUFFER_BIT);
-
-            self.gl.enable(glow::BLEND);
-            self.gl
-                .blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
         }
 
         let camera = Transform3D::ortho(
@@ -171,6 +167,7 @@ pub fn main() {
         WindowBuilder::new(),
         60.0,
         true,
+        cfg!(debug_assertions),
         |window, gl, proxy, executor| {
             let dpi = window.scale_factor();
             let psize = window.inner_size();
@@ -197,7 +194,11 @@ pub fn main() {
                         )
                         .unwrap()
                     },
-                    async { sprites::Sprites::load(&gl, "res/generated").await.unwrap() },
+                    async {
+                        sprites::Sprites::load(&gl, "res/generated", dpi)
+                            .await
+                            .unwrap()
+                    },
                     async { Sound::load("res/pluck.ogg").await.unwrap() }
                 );
 
@@ -219,14 +220,21 @@ pub fn main() {
                         t
                     },
                     sprites,
-                    sprite_renderer: SpriteBatch::new(
+                    sprite_renderer: {
+                        let mut batch = SpriteBatch::new(
+                            &gl,
+                            game_util::sprite::sprite_shader(&gl).unwrap(),
+                            sprite_tex,
+                        )
+                        .unwrap();
+                        batch.set_blend_mode(sprites::Sprites::BLEND_MODE);
+                        batch
+                    },
+                    shape_renderer: ShapeRenderer::new(
                         &gl,
-                        game_util::sprite::sprite_shader(&gl),
-                        sprite_tex,
+                        game_util::shape::shape_shader(&gl).unwrap(),
                     )
                     .unwrap(),
-                    shape_renderer: ShapeRenderer::new(&gl, game_util::shape::shape_shader(&gl))
-                        .unwrap(),
                     gl,
                     pluck,
                     sound_service: SoundService::new(&executor),
@@ -0,0 +1,332 @@
+use crate::prelude::*;
+use crate::sprite::Sprite;
+
+/// A sequence of sprites shown for set durations.
+///
+/// Animations don't keep track of playback themselves so that they can be shared; use an
+/// `AnimationPlayer` for each thing being animated.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    pub mode: PlaybackMode,
+}
+
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub sprite: Sprite,
+    /// How long the frame is shown, in seconds. Should be positive.
+    pub duration: f32,
+    /// Reported by `AnimationPlayer::update` when the frame is reached, for things like footstep
+    /// sounds or hitboxes that should line up with the animation.
+    pub event: Option<String>,
+    /// Named rectangles on the frame, such as hitboxes.
+    pub slices: Vec<Slice>,
+}
+
+/// A named rectangle on an animation frame, in pixels of the untrimmed sprite from its
+/// bottom-left corner, like `SpriteDraw::pivot`.
+#[derive(Clone, Debug)]
+pub struct Slice {
+    pub name: String,
+    pub rect: Rect<f32>,
+    pub pivot: Option<Point2<f32>>,
+}
+
+/// What happens when an animation reaches its last frame.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum PlaybackMode {
+    /// Start over from the first frame.
+    Loop,
+    /// Play backwards to the first frame, then forwards again.
+    PingPong,
+    /// Stay on the last frame.
+    Once,
+}
+
+/// Playback state of an `Animation`.
+#[derive(Clone, Debug)]
+pub struct AnimationPlayer {
+    /// Multiplies the time passed to `update`. Defaults to 1.
+    pub speed: f32,
+    frame: usize,
+    time: f32,
+    backwards: bool,
+    started: bool,
+    finished: bool,
+}
+
+impl Animation {
+    /// Creates an animation showing each sprite for `frame_duration` seconds, without events.
+    pub fn new(
+        sprites: impl IntoIterator<Item = Sprite>,
+        frame_duration: f32,
+        mode: PlaybackMode,
+    ) -> Self {
+        Animation {
+            frames: sprites
+                .into_iter()
+                .map(|sprite| Frame {
+                    sprite,
+                    duration: frame_duration,
+                    event: None,
+                    slices: vec![],
+                })
+                .collect(),
+            mode,
+        }
+    }
+
+    /// The time to play every frame once, in seconds.
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|f| f.duration).sum()
+    }
+}
+
+impl Frame {
+    /// The first slice with the given name.
+    pub fn slice(&self, name: &str) -> Option<&Slice> {
+        self.slices.iter().find(|s| s.name == name)
+    }
+}
+
+impl AnimationPlayer {
+    pub fn new() -> Self {
+        AnimationPlayer {
+            speed: 1.0,
+            frame: 0,
+            time: 0.0,
+            backwards: false,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Goes back to the start of the first frame.
+    pub fn restart(&mut self) {
+        *self = AnimationPlayer {
+            speed: self.speed,
+            ..AnimationPlayer::new()
+        };
+    }
+
+    /// Advances playback by `delta` seconds, usually the length of an update tick, returning the
+    /// events of the frames reached in order. The event of the first frame is reported by the
+    /// first update after creating or restarting the player.
+    pub fn update<'a>(&mut self, animation: &'a Animation, delta: f32) -> Vec<&'a str> {
+        let frames = &animation.frames;
+        let mut events = vec![];
+        if frames.is_empty() {
+            return events;
+        }
+        if self.frame >= frames.len() {
+            // The player was used with a longer animation before.
+            self.frame = frames.len() - 1;
+            self.time = 0.0;
+            self.backwards = false;
+            self.finished = false;
+        }
+        if !self.started {
+            self.started = true;
+            events.extend(frames[self.frame].event.as_deref());
+        }
+        if self.finished {
+            return events;
+        }
+
+        self.time += delta * self.speed;
+        // Frames without duration never use up time, so if all of them are, each update plays at
+        // most one pass through the animation.
+        let mut steps = if animation.duration() > 0.0 {
+            usize::MAX
+        } else {
+            frames.len()
+        };
+        loop {
+            let duration = frames[self.frame].duration;
+            if steps == 0 {
+                self.time = 0.0;
+                break;
+            }
+            if self.time < duration {
+                break;
+            }
+            self.time -= duration;
+            steps -= 1;
+
+            let last = frames.len() - 1;
+            match animation.mode {
+                PlaybackMode::Loop => {
+                    self.frame = if self.frame == last {
+                        0
+                    } else {
+                        self.frame + 1
+                    }
+                }
+                PlaybackMode::Once if self.frame == last => {
+                    self.finished = true;
+                    self.time = 0.0;
+                    break;
+                }
+                PlaybackMode::Once => self.frame += 1,
+                PlaybackMode::PingPong if last == 0 => {}
+                PlaybackMode::PingPong => {
+                    if self.backwards && self.frame == 0 || !self.backwards && self.frame == last {
+                        self.backwards = !self.backwards;
+                    }
+                    if self.backwards {
+                        self.frame -= 1;
+                    } else {
+                        self.frame += 1;
+                    }
+                }
+            }
+            events.extend(frames[self.frame].event.as_deref());
+        }
+        events
+    }
+
+    /// The index of the current frame.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// The sprite of the current frame.
+    ///
+    /// Panics if the animation has no frames.
+    pub fn sprite<'a>(&self, animation: &'a Animation) -> &'a Sprite {
+        &animation.frames[self.frame.min(animation.frames.len() - 1)].sprite
+    }
+
+    /// Whether a `PlaybackMode::Once` animation has reached the end of its last frame.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An animation whose frames have their index as their event.
+    fn animation(durations: &[f32], mode: PlaybackMode) -> Animation {
+        let sprite = Sprite {
+            tex: rect(0.0, 0.0, 1.0, 1.0),
+            trimmed_size: size2(1.0, 1.0),
+            trim_offset: vec2(0.0, 0.0),
+            real_size: size2(1.0, 1.0),
+            layer: 0.0,
+            rotated: false,
+        };
+        Animation {
+            frames: durations
+                .iter()
+                .enumerate()
+                .map(|(i, &duration)| Frame {
+                    sprite,
+                    duration,
+                    event: Some(i.to_string()),
+                    slices: vec![],
+                })
+                .collect(),
+            mode,
+        }
+    }
+
+    /// The frames shown after each of `steps` updates by `delta`.
+    fn frames(animation: &Animation, delta: f32, steps: usize) -> Vec<usize> {
+        let mut player = AnimationPlayer::new();
+        (0..steps)
+            .map(|_| {
+                player.update(animation, delta);
+                player.frame()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loop_wraps_around() {
+        let walk = animation(&[1.0; 3], PlaybackMode::Loop);
+        assert_eq!(frames(&walk, 1.0, 5), [1, 2, 0, 1, 2]);
+        let mut player = AnimationPlayer::new();
+        assert_eq!(player.update(&walk, 0.5), ["0"]);
+        assert_eq!(player.update(&walk, 0.5), ["1"]);
+        assert_eq!(player.update(&walk, 0.5), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn ping_pong_turns_at_both_ends() {
+        let bounce = animation(&[1.0; 3], PlaybackMode::PingPong);
+        assert_eq!(frames(&bounce, 1.0, 6), [1, 2, 1, 0, 1, 2]);
+
+        let still = animation(&[1.0], PlaybackMode::PingPong);
+        let mut player = AnimationPlayer::new();
+        assert_eq!(player.update(&still, 0.5), ["0"]);
+        // Like a single frame loop, the frame is reached again after every turn.
+        assert_eq!(player.update(&still, 1.0), ["0"]);
+        assert_eq!((player.frame(), player.is_finished()), (0, false));
+    }
+
+    #[test]
+    fn once_finishes_on_the_last_frame() {
+        let once = animation(&[1.0; 2], PlaybackMode::Once);
+        let mut player = AnimationPlayer::new();
+        assert_eq!(player.update(&once, 1.5), ["0", "1"]);
+        assert!(!player.is_finished());
+        assert_eq!(player.update(&once, 0.5), Vec::<&str>::new());
+        assert!(player.is_finished());
+        assert_eq!(player.update(&once, 10.0), Vec::<&str>::new());
+        assert_eq!(player.frame(), 1);
+
+        player.restart();
+        assert!(!player.is_finished());
+        assert_eq!(player.update(&once, 0.0), ["0"]);
+    }
+
+    #[test]
+    fn events_of_every_frame_crossed_are_reported_in_order() {
+        let walk = animation(&[1.0, 2.0, 1.0], PlaybackMode::Loop);
+        let mut player = AnimationPlayer::new();
+        assert_eq!(
+            player.update(&walk, 9.5),
+            ["0", "1", "2", "0", "1", "2", "0", "1"]
+        );
+        assert_eq!(player.frame(), 1);
+    }
+
+    #[test]
+    fn speed_scales_time() {
+        let walk = animation(&[1.0; 3], PlaybackMode::Loop);
+        let mut player = AnimationPlayer::new();
+        player.speed = 2.0;
+        assert_eq!(player.update(&walk, 1.0), ["0", "1", "2"]);
+        player.speed = 0.5;
+        assert_eq!(player.update(&walk, 1.0), Vec::<&str>::new());
+        assert_eq!(player.update(&walk, 1.0), ["0"]);
+    }
+
+    #[test]
+    fn zero_durations_play_one_pass_per_update() {
+        let flicker = animation(&[0.0; 3], PlaybackMode::Loop);
+        let mut player = AnimationPlayer::new();
+        assert_eq!(player.update(&flicker, 0.016), ["0", "1", "2", "0"]);
+        assert_eq!(player.update(&flicker, 0.016), ["1", "2", "0"]);
+    }
+
+    #[test]
+    fn shorter_animation_after_a_longer_one() {
+        let long = animation(&[1.0; 5], PlaybackMode::Loop);
+        let short = animation(&[1.0; 2], PlaybackMode::Loop);
+        let mut player = AnimationPlayer::new();
+        player.update(&long, 4.0);
+        assert_eq!(player.frame(), 4);
+        assert_eq!(player.update(&short, 0.5), Vec::<&str>::new());
+        assert_eq!(player.frame(), 1);
+        assert_eq!(player.update(&short, 0.5), ["0"]);
+    }
+}
@@ -0,0 +1,300 @@
+//! Sprite atlases loaded at runtime from a manifest, for sprites that aren't known when the
+//! game is compiled, such as those of mods or downloadable content.
+//!
+//! `build_utils::AtlasBuilder::manifest` writes manifests next to the atlas pages, but they can
+//! also be written by other tools; the JSON form of a manifest is serde's usual representation
+//! of `Manifest`. Atlases with `@2x` sprite variants and the like get a manifest per scale,
+//! such as `sprites@2x.json`.
+//!
+//! Images that only exist once the game is running are packed with
+//! `dynamic_atlas::DynamicAtlas` instead.
+
+use crate::animation::{self, Animation, PlaybackMode};
+use crate::material::BlendMode;
+use crate::prelude::*;
+use crate::sprite::{NineSlice, SliceMode, Sprite};
+use crate::texture::{TextureArray, TextureFormat, TextureOptions};
+use crate::Error;
+use std::collections::{BTreeMap, HashMap};
+
+/// The description of an atlas: where its sprites are on which pages.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub page_width: u32,
+    pub page_height: u32,
+    /// The paths of the page images, relative to the manifest.
+    pub pages: Vec<String>,
+    pub premultiplied_alpha: bool,
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ManifestEntry {
+    Sprite(ManifestSprite),
+    Array(Vec<ManifestSprite>),
+    Animation {
+        frames: Vec<ManifestFrame>,
+        mode: PlaybackMode,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestSprite {
+    /// The x, y, width and height in pixels of the sprite on its page, turned if `rotated`.
+    pub rect: [u32; 4],
+    pub layer: u32,
+    /// Whether the sprite is stored turned 90 degrees clockwise.
+    pub rotated: bool,
+    /// From the bottom-left corner of the untrimmed sprite to that of the stored part.
+    pub trim_offset: [u32; 2],
+    /// The size of the untrimmed sprite.
+    pub real_size: [u32; 2],
+    /// The pixels per unit the sprite was drawn at, like 2 for `@2x` sprites. Sizes, offsets
+    /// and insets are divided by it, so that sprites are the same size at every scale.
+    pub scale: u32,
+    /// Makes the sprite a `NineSlice`.
+    pub nine_slice: Option<ManifestNineSlice>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestNineSlice {
+    /// Top, right, bottom and left.
+    pub insets: [u32; 4],
+    pub edges: SliceMode,
+    pub center: SliceMode,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestFrame {
+    pub sprite: ManifestSprite,
+    /// In seconds.
+    pub duration: f32,
+    pub event: Option<String>,
+    pub slices: Vec<ManifestSlice>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestSlice {
+    pub name: String,
+    /// The x, y, width and height in pixels from the bottom-left corner of the untrimmed sprite.
+    pub rect: [i32; 4],
+    pub pivot: Option<[i32; 2]>,
+}
+
+/// Sprites loaded from a manifest, looked up by name.
+///
+/// Names are the same as the fields of the `Sprites` struct `build_utils` generates.
+#[derive(Clone)]
+pub struct SpriteAtlas {
+    texture: TextureArray,
+    blend_mode: BlendMode,
+    entries: HashMap<String, AtlasEntry>,
+}
+
+#[derive(Clone, Debug)]
+pub enum AtlasEntry {
+    Sprite(Sprite),
+    NineSlice(NineSlice),
+    Array(Vec<Sprite>),
+    NineSliceArray(Vec<NineSlice>),
+    Animation(Animation),
+}
+
+impl Manifest {
+    /// Decodes a manifest. `source` is only used for error messages, and decides the format:
+    /// JSON if it ends with `.json`, otherwise bincode.
+    pub fn decode(data: &[u8], source: &str) -> Result<Self, Error> {
+        if source.ends_with(".json") {
+            serde_json::from_slice(data).map_err(|e| Error::decode(source, e))
+        } else {
+            bincode::deserialize(data).map_err(|e| Error::decode(source, e))
+        }
+    }
+}
+
+impl SpriteAtlas {
+    /// Loads a manifest and its pages. The manifest is JSON if its name ends with `.json`, and
+    /// bincode otherwise.
+    pub async fn load(gl: &Gl, source: &str, options: TextureOptions) -> Result<Self, Error> {
+        let manifest = Manifest::decode(&crate::load_binary(source).await?, source)?;
+        let base = match source.rfind('/') {
+            Some(i) => &source[..i + 1],
+            None => "",
+        };
+        Self::from_manifest(gl, &manifest, base, options).await
+    }
+
+    /// Loads the pages of a manifest, prefixing their paths with `base`.
+    pub async fn from_manifest(
+        gl: &Gl,
+        manifest: &Manifest,
+        base: &str,
+        options: TextureOptions,
+    ) -> Result<Self, Error> {
+        // Pages may be smaller than the layers, and the rest mustn't be filtered into sprites.
+        let texture = TextureArray::new_cleared(
+            gl,
+            manifest.page_width,
+            manifest.page_height,
+            manifest.pages.len() as u32,
+            TextureFormat::Rgba8,
+            options,
+        )?;
+        futures::future::try_join_all(manifest.pages.iter().enumerate().map(|(i, page)| {
+            let texture = &texture;
+            async move {
+                texture
+                    .load_layer(&format!("{}{}", base, page), i as u32)
+                    .await
+            }
+        }))
+        .await?;
+        texture.generate_mipmaps();
+
+        let page_size = size2(manifest.page_width as f32, manifest.page_height as f32);
+        let mut entries = HashMap::new();
+        for (name, entry) in &manifest.entries {
+            let entry = match entry {
+                ManifestEntry::Sprite(s) => match &s.nine_slice {
+                    Some(_) => AtlasEntry::NineSlice(nine_slice(s, page_size)),
+                    None => AtlasEntry::Sprite(sprite(s, page_size)),
+                },
+                ManifestEntry::Array(v) if v.iter().any(|s| s.nine_slice.is_some()) => {
+                    AtlasEntry::NineSliceArray(v.iter().map(|s| nine_slice(s, page_size)).collect())
+                }
+                ManifestEntry::Array(v) => {
+                    AtlasEntry::Array(v.iter().map(|s| sprite(s, page_size)).collect())
+                }
+                ManifestEntry::Animation { frames, mode } => AtlasEntry::Animation(Animation {
+                    frames: frames.iter().map(|f| frame(f, page_size)).collect(),
+                    mode: *mode,
+                }),
+            };
+            entries.insert(name.clone(), entry);
+        }
+
+        Ok(SpriteAtlas {
+            texture,
+            blend_mode: if manifest.premultiplied_alpha {
+                BlendMode::PremultipliedAlpha
+            } else {
+                BlendMode::Alpha
+            },
+            entries,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AtlasEntry> {
+        self.entries.get(name)
+    }
+
+    pub fn sprite(&self, name: &str) -> Option<&Sprite> {
+        match self.get(name)? {
+            AtlasEntry::Sprite(sprite) => Some(sprite),
+            _ => None,
+        }
+    }
+
+    pub fn nine_slice(&self, name: &str) -> Option<&NineSlice> {
+        match self.get(name)? {
+            AtlasEntry::NineSlice(nine_slice) => Some(nine_slice),
+            _ => None,
+        }
+    }
+
+    pub fn array(&self, name: &str) -> Option<&[Sprite]> {
+        match self.get(name)? {
+            AtlasEntry::Array(sprites) => Some(sprites),
+            _ => None,
+        }
+    }
+
+    pub fn nine_slice_array(&self, name: &str) -> Option<&[NineSlice]> {
+        match self.get(name)? {
+            AtlasEntry::NineSliceArray(nine_slices) => Some(nine_slices),
+            _ => None,
+        }
+    }
+
+    pub fn animation(&self, name: &str) -> Option<&Animation> {
+        match self.get(name)? {
+            AtlasEntry::Animation(animation) => Some(animation),
+            _ => None,
+        }
+    }
+
+    /// The names of all entries, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// The texture to draw the sprites with.
+    pub fn texture(&self) -> &TextureArray {
+        &self.texture
+    }
+
+    /// The blend mode to draw the sprites with.
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+}
+
+fn sprite(s: &ManifestSprite, page_size: Size2<f32>) -> Sprite {
+    let [x, y, w, h] = s.rect;
+    let [x, y, w, h] = [x as f32, y as f32, w as f32, h as f32];
+    let scale = s.scale as f32;
+    Sprite {
+        tex: rect(
+            x / page_size.width,
+            y / page_size.height,
+            w / page_size.width,
+            h / page_size.height,
+        ),
+        trimmed_size: if s.rotated { size2(h, w) } else { size2(w, h) } / scale,
+        trim_offset: vec2(s.trim_offset[0] as f32, s.trim_offset[1] as f32) / scale,
+        real_size: size2(s.real_size[0] as f32, s.real_size[1] as f32) / scale,
+        layer: s.layer as f32,
+        rotated: s.rotated,
+    }
+}
+
+/// Sprites without nine-slice settings become nine-slices without insets.
+fn nine_slice(s: &ManifestSprite, page_size: Size2<f32>) -> NineSlice {
+    let (insets, edges, center) = match &s.nine_slice {
+        Some(n) => (n.insets, n.edges, n.center),
+        None => ([0; 4], SliceMode::Stretch, SliceMode::Stretch),
+    };
+    let [top, right, bottom, left] = insets;
+    let scale = s.scale as f32;
+    NineSlice {
+        sprite: sprite(s, page_size),
+        insets: euclid::SideOffsets2D::new(
+            top as f32 / scale,
+            right as f32 / scale,
+            bottom as f32 / scale,
+            left as f32 / scale,
+        ),
+        edges,
+        center,
+    }
+}
+
+fn frame(f: &ManifestFrame, page_size: Size2<f32>) -> animation::Frame {
+    animation::Frame {
+        sprite: sprite(&f.sprite, page_size),
+        duration: f.duration,
+        event: f.event.clone(),
+        slices: f
+            .slices
+            .iter()
+            .map(|slice| {
+                let [x, y, w, h] = slice.rect;
+                animation::Slice {
+                    name: slice.name.clone(),
+                    rect: rect(x as f32, y as f32, w as f32, h as f32),
+                    pivot: slice.pivot.map(|[x, y]| point2(x as f32, y as f32)),
+                }
+            })
+            .collect(),
+    }
+}
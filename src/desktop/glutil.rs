@@ -1,80 +1,261 @@
 use std::path::PathBuf;
 
 use crate::prelude::*;
+use crate::Error;
 use futures::channel::oneshot;
 
 #[derive(Clone)]
-pub struct Gl(std::rc::Rc<glow::Context>);
+pub struct Gl(std::rc::Rc<GlInner>);
+
+struct GlInner {
+    gl: glow::Context,
+    state: glutil::StateCache,
+    debug: bool,
+    check_errors: bool,
+}
 
 impl Gl {
-    pub(crate) fn new(gl: glow::Context) -> Self {
-        Gl(std::rc::Rc::new(gl))
+    /// Wraps a context. If `debug` is true, `KHR_debug` output is routed to the `log` crate, or
+    /// if the extension is unavailable, renderers check `glGetError` after rendering instead.
+    pub fn new(gl: glow::Context, debug: bool) -> Self {
+        let debug_output = debug && gl.supports_debug();
+        if debug_output {
+            unsafe {
+                gl.enable(glow::DEBUG_OUTPUT);
+                // Report messages on the offending call so that backtraces are useful.
+                gl.enable(glow::DEBUG_OUTPUT_SYNCHRONOUS);
+                // glow hands the driver a pointer to its own stack copy of the callback, so the
+                // callback must not capture anything.
+                gl.debug_message_callback(log_debug_message);
+            }
+        } else if debug {
+            log::warn!("KHR_debug is not supported; falling back to glGetError checks");
+        }
+        Gl(std::rc::Rc::new(GlInner {
+            gl,
+            state: glutil::StateCache::new(),
+            debug: debug_output,
+            check_errors: debug && !debug_output,
+        }))
+    }
+
+    pub(crate) fn state(&self) -> &glutil::StateCache {
+        &self.0.state
+    }
+
+    /// Whether the context was created in debug mode.
+    pub fn is_debug(&self) -> bool {
+        self.0.debug || self.0.check_errors
+    }
+
+    /// Attaches a debug label to a buffer. Does nothing unless debug output is enabled.
+    pub fn label_buffer(&self, buffer: glow::Buffer, label: &str) {
+        self.label(glow::BUFFER, buffer, label);
+    }
+
+    /// Attaches a debug label to a texture. Does nothing unless debug output is enabled.
+    pub fn label_texture(&self, texture: glow::Texture, label: &str) {
+        self.label(glow::TEXTURE, texture, label);
+    }
+
+    /// Attaches a debug label to a shader program. Does nothing unless debug output is enabled.
+    pub fn label_program(&self, program: glow::Program, label: &str) {
+        self.label(glow::PROGRAM, program, label);
+    }
+
+    fn label(&self, identifier: u32, name: u32, label: &str) {
+        if self.0.debug {
+            unsafe { self.0.gl.object_label(identifier, name, Some(label)) }
+        }
+    }
+
+    /// Logs any pending OpenGL errors if in debug mode and `KHR_debug` is unavailable. `context`
+    /// identifies the operation that caused the errors.
+    pub fn check_errors(&self, context: &str) {
+        if self.0.check_errors {
+            glutil::log_errors(&self.0.gl, context);
+        }
     }
 }
 
 impl std::ops::Deref for Gl {
     type Target = glow::Context;
     fn deref(&self) -> &glow::Context {
-        &self.0
+        &self.0.gl
     }
 }
 
-async fn load_image(source: &str) -> Result<image::RgbaImage, String> {
+fn log_debug_message(source: u32, gltype: u32, id: u32, severity: u32, message: &str) {
+    let level = match severity {
+        glow::DEBUG_SEVERITY_HIGH => log::Level::Error,
+        glow::DEBUG_SEVERITY_MEDIUM => log::Level::Warn,
+        glow::DEBUG_SEVERITY_LOW => log::Level::Info,
+        _ => log::Level::Debug,
+    };
+    let source = match source {
+        glow::DEBUG_SOURCE_API => "API",
+        glow::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        glow::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        glow::DEBUG_SOURCE_THIRD_PARTY => "third party",
+        glow::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
+    };
+    let gltype = match gltype {
+        glow::DEBUG_TYPE_ERROR => "error",
+        glow::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated behavior",
+        glow::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+        glow::DEBUG_TYPE_PORTABILITY => "portability",
+        glow::DEBUG_TYPE_PERFORMANCE => "performance",
+        glow::DEBUG_TYPE_MARKER => "marker",
+        _ => "other",
+    };
+    log::log!(level, "GL {} {} {}: {}", source, gltype, id, message);
+}
+
+pub(crate) type Image = image::RgbaImage;
+
+pub(crate) async fn load_image(source: &str) -> Result<Image, Error> {
     let (s, r) = oneshot::channel();
-    let source = PathBuf::from(source);
+    let path = PathBuf::from(source);
     std::thread::spawn(|| {
-        s.send(image::open(source)).ok();
+        s.send(image::open(path)).ok();
+    });
+    match r.await.unwrap() {
+        Ok(img) => Ok(img.to_rgba8()),
+        Err(image::ImageError::IoError(e)) => Err(Error::io(source, e)),
+        Err(e) => Err(Error::decode(source, e)),
+    }
+}
+
+pub(crate) async fn decode_image(data: &[u8]) -> Result<Image, Error> {
+    // Decoding large images takes a while, so like loading it is done on another thread, which
+    // needs its own copy of the data.
+    let (s, r) = oneshot::channel();
+    let data = data.to_vec();
+    std::thread::spawn(move || {
+        s.send(image::load_from_memory(&data).map(|img| img.to_rgba8()))
+            .ok();
     });
-    r.await
-        .unwrap()
-        .map_err(|e| e.to_string())
-        .map(|img| img.to_rgba8())
+    r.await.unwrap().map_err(|e| Error::decode("<memory>", e))
 }
 
-pub async fn load_texture_2d(gl: &Gl, source: &str) -> Result<glow::Texture, String> {
+pub(crate) fn image_size(image: &Image) -> (u32, u32) {
+    image.dimensions()
+}
+
+/// Uploads an image to level 0 of the `GL_TEXTURE_2D` binding.
+pub(crate) unsafe fn tex_image_2d(gl: &Gl, image: &Image) {
+    gl.tex_image_2d(
+        glow::TEXTURE_2D,
+        0,
+        glow::RGBA8 as i32,
+        image.width() as i32,
+        image.height() as i32,
+        0,
+        glow::RGBA,
+        glow::UNSIGNED_BYTE,
+        Some(image),
+    );
+}
+
+/// Uploads an image to a layer of level 0 of the `GL_TEXTURE_2D_ARRAY` binding.
+pub(crate) unsafe fn tex_sub_image_3d(gl: &Gl, layer: i32, image: &Image) {
+    gl.tex_sub_image_3d(
+        glow::TEXTURE_2D_ARRAY,
+        0,
+        0,
+        0,
+        layer,
+        image.width() as i32,
+        image.height() as i32,
+        1,
+        glow::RGBA,
+        glow::UNSIGNED_BYTE,
+        glow::PixelUnpackData::Slice(image),
+    );
+}
+
+#[deprecated(note = "use `texture::Texture::load` instead")]
+pub async fn load_texture_2d(gl: &Gl, source: &str) -> Result<glow::Texture, Error> {
     let image = load_image(source).await?;
     unsafe {
-        let texture = gl.create_texture()?;
+        let texture = gl.create_texture().map_err(Error::Gl)?;
         gl.bind_texture(glow::TEXTURE_2D, Some(texture));
-        gl.tex_image_2d(
-            glow::TEXTURE_2D,
-            0,
-            glow::RGBA8 as i32,
-            image.width() as i32,
-            image.height() as i32,
-            0,
-            glow::RGBA,
-            glow::UNSIGNED_BYTE,
-            Some(&image),
-        );
+        tex_image_2d(gl, &image);
         gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAX_LEVEL, 0);
 
         Ok(texture)
     }
 }
 
+#[deprecated(note = "use `texture::TextureArray::load_layer` instead")]
 pub async fn load_texture_layer(
     gl: &Gl,
     source: &str,
     texture: glow::Texture,
     layer: i32,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     let image = load_image(source).await?;
     unsafe {
         gl.bind_texture(glow::TEXTURE_2D_ARRAY, Some(texture));
-        gl.tex_sub_image_3d(
-            glow::TEXTURE_2D_ARRAY,
-            0,
-            0,
-            0,
-            layer,
-            image.width() as i32,
-            image.height() as i32,
-            1,
-            glow::RGBA,
-            glow::UNSIGNED_BYTE,
-            glow::PixelUnpackData::Slice(&image),
-        );
+        tex_sub_image_3d(gl, layer, &image);
     }
     Ok(())
 }
+
+/// Timer queries are core in OpenGL 3.3.
+pub(crate) fn timer_queries_supported(_gl: &Gl) -> bool {
+    true
+}
+
+/// Whether a timing-invalidating event has happened since the last call. This never happens on
+/// desktop OpenGL.
+pub(crate) fn gpu_disjoint(_gl: &Gl) -> bool {
+    false
+}
+
+/// A `GL_TIME_ELAPSED` query object.
+pub(crate) struct TimerQuery {
+    gl: Gl,
+    query: glow::Query,
+}
+
+impl TimerQuery {
+    pub(crate) fn new(gl: &Gl) -> Result<Self, Error> {
+        Ok(TimerQuery {
+            gl: gl.clone(),
+            query: unsafe { gl.create_query().map_err(Error::Gl)? },
+        })
+    }
+
+    pub(crate) fn begin(&self) {
+        unsafe { self.gl.begin_query(glow::TIME_ELAPSED, self.query) }
+    }
+
+    pub(crate) fn end(&self) {
+        unsafe { self.gl.end_query(glow::TIME_ELAPSED) }
+    }
+
+    /// The elapsed time in nanoseconds, or `None` if the result is not available yet.
+    pub(crate) fn result(&self) -> Option<u64> {
+        unsafe {
+            if self
+                .gl
+                .get_query_parameter_u32(self.query, glow::QUERY_RESULT_AVAILABLE)
+                == 0
+            {
+                return None;
+            }
+            Some(
+                self.gl
+                    .get_query_parameter_u32(self.query, glow::QUERY_RESULT) as u64,
+            )
+        }
+    }
+}
+
+impl Drop for TimerQuery {
+    fn drop(&mut self) {
+        unsafe { self.gl.delete_query(self.query) }
+    }
+}
@@ -8,18 +8,19 @@ use rodio::source::{Buffered, UniformSourceIterator};
 use rodio::{Decoder, OutputStream, Sink, Source};
 
 use crate::sound::SoundCommand;
+use crate::Error;
 
 pub(crate) type InternalSound = Buffered<UniformSourceIterator<Decoder<BufReader<File>>, i16>>;
 
 const CHANNELS: u16 = 2;
 const SAMPLE_RATE: u32 = 44_100;
 
-pub(crate) async fn load(source: &str) -> Result<InternalSound, String> {
+pub(crate) async fn load(source: &str) -> Result<InternalSound, Error> {
     Ok(UniformSourceIterator::new(
         Decoder::new(BufReader::new(
-            File::open(source).map_err(|e| e.to_string())?,
+            File::open(source).map_err(|e| Error::io(source, e))?,
         ))
-        .map_err(|e| e.to_string())?,
+        .map_err(|e| Error::decode(source, e))?,
         CHANNELS,
         SAMPLE_RATE,
     )
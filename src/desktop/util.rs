@@ -1,5 +1,6 @@
 use crate::gameloop::*;
 use crate::prelude::*;
+use crate::Error;
 
 use futures::channel::oneshot;
 use futures::executor::{LocalPool, LocalSpawner};
@@ -11,10 +12,15 @@ use winit::event::WindowEvent;
 use winit::event_loop::{EventLoop, EventLoopProxy};
 use winit::window::{Window, WindowBuilder};
 
+/// Creates the window and OpenGL context and runs the game loop.
+///
+/// If `debug` is true, OpenGL debug output is enabled and routed to the `log` crate. See
+/// `Gl::check_errors`.
 pub fn launch<G, F>(
     wb: WindowBuilder,
     ups: f64,
     lockstep: bool,
+    debug: bool,
     init: impl FnOnce(&Window, Gl, EventLoopProxy<G::UserEvent>, LocalExecutor) -> F,
 ) where
     G: Game + 'static,
@@ -25,6 +31,7 @@ pub fn launch<G, F>(
     let context = glutin::ContextBuilder::new()
         .with_vsync(true)
         .with_gl(GlRequest::Specific(Api::OpenGl, (3, 3)))
+        .with_gl_debug_flag(debug)
         .build_windowed(wb.with_visible(false), &el);
     let context = match context {
         Ok(v) => v,
@@ -37,8 +44,10 @@ pub fn launch<G, F>(
 
     let context = unsafe { context.make_current() }.unwrap();
 
-    let gl =
-        Gl::new(unsafe { glow::Context::from_loader_function(|s| context.get_proc_address(s)) });
+    let gl = Gl::new(
+        unsafe { glow::Context::from_loader_function(|s| context.get_proc_address(s)) },
+        debug,
+    );
 
     unsafe {
         gl.bind_vertex_array(gl.create_vertex_array().ok());
@@ -103,39 +112,40 @@ impl LocalExecutor {
     }
 }
 
-pub async fn load_binary(source: &str) -> Result<Vec<u8>, String> {
+pub async fn load_binary(source: &str) -> Result<Vec<u8>, Error> {
     let (s, r) = oneshot::channel();
     let path = PathBuf::from(source);
-    std::thread::spawn(|| s.send(std::fs::read(path).map_err(|e| e.to_string())));
-    r.await.unwrap()
+    std::thread::spawn(|| s.send(std::fs::read(path)));
+    r.await.unwrap().map_err(|e| Error::io(source, e))
 }
 
-pub fn store<T: Serialize>(key: &str, value: &T, human_readable: bool) -> Result<(), String> {
+pub fn store<T: Serialize>(key: &str, value: &T, human_readable: bool) -> Result<(), Error> {
     let extension = if human_readable { "yaml" } else { "dat" };
-    let to = std::io::BufWriter::new(
-        std::fs::File::create(format!("{}.{}", key, extension)).map_err(|e| e.to_string())?,
-    );
+    let path = format!("{}.{}", key, extension);
+    let to =
+        std::io::BufWriter::new(std::fs::File::create(&path).map_err(|e| Error::io(&path, e))?);
     if human_readable {
-        serde_yaml::to_writer(to, value).map_err(|e| e.to_string())
+        serde_yaml::to_writer(to, value).map_err(|e| Error::Serialization(e.to_string()))
     } else {
-        bincode::serialize_into(to, &value).map_err(|e| e.to_string())
+        bincode::serialize_into(to, &value).map_err(|e| Error::Serialization(e.to_string()))
     }
 }
 
-pub fn load<T: DeserializeOwned>(key: &str, human_readable: bool) -> Result<Option<T>, String> {
+pub fn load<T: DeserializeOwned>(key: &str, human_readable: bool) -> Result<Option<T>, Error> {
     let extension = if human_readable { "yaml" } else { "dat" };
-    let from = match std::fs::File::open(format!("{}.{}", key, extension)) {
+    let path = format!("{}.{}", key, extension);
+    let from = match std::fs::File::open(&path) {
         Ok(f) => std::io::BufReader::new(f),
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
-        Err(e) => return Err(e.to_string()),
+        Err(e) => return Err(Error::io(&path, e)),
     };
     if human_readable {
         serde_yaml::from_reader(from)
-            .map_err(|e| e.to_string())
+            .map_err(|e| Error::Serialization(e.to_string()))
             .map(Some)
     } else {
         bincode::deserialize_from(from)
-            .map_err(|e| e.to_string())
+            .map_err(|e| Error::Serialization(e.to_string()))
             .map(Some)
     }
 }
@@ -0,0 +1,441 @@
+//! A sprite atlas packed while the game runs, for images that don't exist when it's built, such
+//! as downloaded avatars, user-generated content and procedurally generated images.
+
+use crate::prelude::*;
+use crate::sprite::Sprite;
+use crate::texture::{TextureArray, TextureFormat, TextureOptions};
+use crate::Error;
+
+/// Shelf heights are rounded up to a multiple of this, so that the space freed by a sprite can
+/// be reused by sprites of similar heights. A power of two.
+const SHELF_ROUNDING: u32 = 8;
+
+/// Packs RGBA images into the layers of a texture array on demand, returning ordinary `Sprite`s
+/// that `SpriteBatch` draws like any other.
+///
+/// Sprites are packed on shelves, rows as high as the sprites on them, and the space of freed
+/// sprites is reused by later ones. When no layer has room, a layer is added.
+///
+/// Texture arrays can't be resized, so growing creates a larger one and uploads a copy of the
+/// pixels kept in memory. Get the texture from `texture` whenever sprites are drawn rather than
+/// keeping it, since it changes when the atlas grows; sprites stay valid.
+///
+/// ```ignore
+/// let mut atlas = DynamicAtlas::new(&gl, 1024, 1024, TextureOptions::default())?;
+/// let (avatar, region) = atlas.add_rgba(64, 64, &pixels)?;
+/// batch.set_texture(atlas.texture())?;
+/// batch.draw(&avatar, point2(10.0, 10.0), [255; 4]);
+/// // Once the avatar is no longer drawn:
+/// atlas.free(region);
+/// ```
+pub struct DynamicAtlas {
+    /// The transparent pixels kept between sprites, so that filtering doesn't blend neighboring
+    /// sprites. Only affects sprites added afterwards. Defaults to 1.
+    pub padding: u32,
+    gl: Gl,
+    texture: TextureArray,
+    max_layers: u32,
+    layers: Vec<Layer>,
+}
+
+/// The space taken by a sprite of a `DynamicAtlas`, for freeing it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct AtlasRegion {
+    layer: u32,
+    x: u32,
+    y: u32,
+    /// Including padding.
+    width: u32,
+}
+
+struct Layer {
+    /// The RGBA pixels of the layer, for uploading to a larger texture when the atlas grows.
+    pixels: Vec<u8>,
+    /// Sorted by `y`, without gaps between them.
+    shelves: Vec<Shelf>,
+}
+
+/// A row of sprites.
+struct Shelf {
+    y: u32,
+    height: u32,
+    /// The free parts of the row as x and width, sorted by x. Adjacent parts are merged.
+    free: Vec<(u32, u32)>,
+}
+
+impl DynamicAtlas {
+    /// Creates an empty atlas with one `width` by `height` layer.
+    pub fn new(gl: &Gl, width: u32, height: u32, options: TextureOptions) -> Result<Self, Error> {
+        let texture = TextureArray::new(gl, width, height, 1, TextureFormat::Rgba8, options)?;
+        // New textures are uninitialized, and layers have to be transparent around sprites.
+        let layer = Layer::new(width, height);
+        texture.update_layer(0, 0, 0, width, height, &layer.pixels)?;
+        Ok(DynamicAtlas {
+            padding: 1,
+            gl: gl.clone(),
+            texture,
+            max_layers: unsafe { gl.get_parameter_i32(glow::MAX_ARRAY_TEXTURE_LAYERS) } as u32,
+            layers: vec![layer],
+        })
+    }
+
+    /// Packs a `width` by `height` image of 8-bit RGBA pixels, adding a layer if none has room.
+    ///
+    /// Fails if the image doesn't fit on a layer with padding, if `data` has the wrong length,
+    /// or if the atlas has as many layers as the driver supports.
+    pub fn add_rgba(
+        &mut self,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<(Sprite, AtlasRegion), Error> {
+        let expected = width as usize * height as usize * 4;
+        if data.len() != expected {
+            return Err(Error::InvalidArgument(format!(
+                "Improper data length of {} for {}x{} image (expected {})",
+                data.len(),
+                width,
+                height,
+                expected
+            )));
+        }
+        let (layer_width, layer_height) = (self.texture.width(), self.texture.height());
+        let (padded_width, padded_height) = (width + self.padding, height + self.padding);
+        if padded_width > layer_width || padded_height > layer_height {
+            return Err(Error::InvalidArgument(format!(
+                "{}x{} image with {} pixels of padding does not fit in a {}x{} atlas",
+                width, height, self.padding, layer_width, layer_height
+            )));
+        }
+
+        let region = match self.allocate(padded_width, padded_height) {
+            Some(region) => region,
+            None => {
+                self.grow()?;
+                self.allocate(padded_width, padded_height).unwrap()
+            }
+        };
+
+        // The padding is cleared along with the sprite, since freed space may hold old sprites.
+        let mut padded = vec![0; padded_width as usize * padded_height as usize * 4];
+        let row = width as usize * 4;
+        let padded_row = padded_width as usize * 4;
+        for y in 0..height as usize {
+            padded[y * padded_row..y * padded_row + row]
+                .copy_from_slice(&data[y * row..(y + 1) * row]);
+        }
+        let pixels = &mut self.layers[region.layer as usize].pixels;
+        let layer_row = layer_width as usize * 4;
+        for y in 0..padded_height as usize {
+            let start = (region.y as usize + y) * layer_row + region.x as usize * 4;
+            pixels[start..start + padded_row]
+                .copy_from_slice(&padded[y * padded_row..(y + 1) * padded_row]);
+        }
+        self.texture.update_layer(
+            region.layer,
+            region.x,
+            region.y,
+            padded_width,
+            padded_height,
+            &padded,
+        )?;
+
+        let (w, h) = (width as f32, height as f32);
+        let sprite = Sprite {
+            tex: rect(
+                region.x as f32 / layer_width as f32,
+                region.y as f32 / layer_height as f32,
+                w / layer_width as f32,
+                h / layer_height as f32,
+            ),
+            trimmed_size: size2(w, h),
+            trim_offset: vec2(0.0, 0.0),
+            real_size: size2(w, h),
+            layer: region.layer as f32,
+            rotated: false,
+        };
+        Ok((sprite, region))
+    }
+
+    /// Packs an image. See `add_rgba`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_image(&mut self, image: &image::RgbaImage) -> Result<(Sprite, AtlasRegion), Error> {
+        self.add_rgba(image.width(), image.height(), image.as_raw())
+    }
+
+    /// Makes the space of a sprite available to later ones. The sprite must no longer be drawn,
+    /// since its pixels may be replaced.
+    ///
+    /// Freeing a region twice, or a region of another atlas, corrupts the atlas or panics.
+    pub fn free(&mut self, region: AtlasRegion) {
+        let layer_width = self.texture.width();
+        self.layers[region.layer as usize].free(region.x, region.y, region.width, layer_width);
+    }
+
+    /// The texture to draw the sprites with. It changes when the atlas grows.
+    pub fn texture(&self) -> &TextureArray {
+        &self.texture
+    }
+
+    pub fn width(&self) -> u32 {
+        self.texture.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.texture.height()
+    }
+
+    pub fn layers(&self) -> u32 {
+        self.texture.layers()
+    }
+
+    /// Finds space for a `width` by `height` rectangle on the existing layers.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<AtlasRegion> {
+        let (layer_width, layer_height) = (self.texture.width(), self.texture.height());
+        self.layers.iter_mut().enumerate().find_map(|(i, layer)| {
+            let (x, y) = layer.allocate(width, height, layer_width, layer_height)?;
+            Some(AtlasRegion {
+                layer: i as u32,
+                x,
+                y,
+                width,
+            })
+        })
+    }
+
+    /// Adds an empty layer, moving the sprites to a texture with one more layer. The atlas is
+    /// unchanged if this fails.
+    fn grow(&mut self) -> Result<(), Error> {
+        let layers = self.layers.len() as u32 + 1;
+        if layers > self.max_layers {
+            return Err(Error::InvalidArgument(format!(
+                "Sprite atlas is full and cannot grow past {} layers",
+                self.max_layers
+            )));
+        }
+        let (width, height) = (self.texture.width(), self.texture.height());
+        let texture = TextureArray::new(
+            &self.gl,
+            width,
+            height,
+            layers,
+            TextureFormat::Rgba8,
+            self.texture.options(),
+        )?;
+        let new_layer = Layer::new(width, height);
+        let pixels = self
+            .layers
+            .iter()
+            .chain(Some(&new_layer))
+            .map(|l| &l.pixels);
+        for (i, pixels) in pixels.enumerate() {
+            texture.update_layer(i as u32, 0, 0, width, height, pixels)?;
+        }
+        self.layers.push(new_layer);
+        self.texture = texture;
+        Ok(())
+    }
+}
+
+impl Layer {
+    fn new(width: u32, height: u32) -> Self {
+        Layer {
+            pixels: vec![0; width as usize * height as usize * 4],
+            shelves: vec![],
+        }
+    }
+
+    /// Puts a rectangle on the shelf that fits it best, or on a new shelf if that fits it better
+    /// or none has room. Returns its position.
+    fn allocate(
+        &mut self,
+        width: u32,
+        height: u32,
+        layer_width: u32,
+        layer_height: u32,
+    ) -> Option<(u32, u32)> {
+        let top = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height);
+        let shelf_height =
+            ((height + SHELF_ROUNDING - 1) & !(SHELF_ROUNDING - 1)).min(layer_height - top);
+        let can_add_shelf = height <= shelf_height;
+
+        // The lowest shelf with room, unless it's so much higher than a new shelf would be that
+        // most of the space above the rectangle would be wasted.
+        let best = self
+            .shelves
+            .iter()
+            .enumerate()
+            .filter(|(_, shelf)| shelf.height >= height && shelf.fits(width))
+            .min_by_key(|(_, shelf)| shelf.height)
+            .map(|(i, shelf)| (i, shelf.height))
+            .filter(|&(_, best)| !can_add_shelf || best <= 2 * shelf_height);
+        let i = match best {
+            Some((i, _)) => i,
+            None if can_add_shelf => {
+                self.shelves.push(Shelf {
+                    y: top,
+                    height: shelf_height,
+                    free: vec![(0, layer_width)],
+                });
+                self.shelves.len() - 1
+            }
+            None => return None,
+        };
+        let shelf = &mut self.shelves[i];
+        Some((shelf.allocate(width), shelf.y))
+    }
+
+    /// Returns `width` at `x` on the shelf at `y` to the free parts.
+    fn free(&mut self, x: u32, y: u32, width: u32, layer_width: u32) {
+        let shelf = self
+            .shelves
+            .iter_mut()
+            .find(|shelf| shelf.y == y)
+            .expect("region is not from this atlas");
+        shelf.free(x, width);
+
+        // Empty shelves at the top of the layer are removed, so that their space can be split
+        // into shelves of other heights.
+        while let Some(shelf) = self.shelves.last() {
+            if shelf.free[..] != [(0, layer_width)] {
+                break;
+            }
+            self.shelves.pop();
+        }
+    }
+}
+
+impl Shelf {
+    fn fits(&self, width: u32) -> bool {
+        self.free.iter().any(|&(_, w)| w >= width)
+    }
+
+    /// Takes `width` from the start of the first free part it fits in. Returns its x.
+    fn allocate(&mut self, width: u32) -> u32 {
+        let i = self.free.iter().position(|&(_, w)| w >= width).unwrap();
+        let (x, w) = self.free[i];
+        if w == width {
+            self.free.remove(i);
+        } else {
+            self.free[i] = (x + width, w - width);
+        }
+        x
+    }
+
+    /// Returns `width` at `x` to the free parts, merging it with its neighbors.
+    fn free(&mut self, x: u32, width: u32) {
+        let i = self
+            .free
+            .binary_search_by_key(&x, |&(free_x, _)| free_x)
+            .unwrap_or_else(|i| i);
+        self.free.insert(i, (x, width));
+        if i + 1 < self.free.len() && x + width == self.free[i + 1].0 {
+            self.free[i].1 += self.free.remove(i + 1).1;
+        }
+        if i > 0 && self.free[i - 1].0 + self.free[i - 1].1 == x {
+            self.free[i - 1].1 += self.free.remove(i).1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shelf(free: &[(u32, u32)]) -> Shelf {
+        Shelf {
+            y: 0,
+            height: 8,
+            free: free.to_vec(),
+        }
+    }
+
+    #[test]
+    fn freed_parts_merge_with_their_neighbors() {
+        let mut s = shelf(&[(0, 10), (20, 10), (40, 10)]);
+        s.free(10, 5);
+        assert_eq!(s.free, [(0, 15), (20, 10), (40, 10)]);
+        s.free(35, 5);
+        assert_eq!(s.free, [(0, 15), (20, 10), (35, 15)]);
+        s.free(30, 5);
+        assert_eq!(s.free, [(0, 15), (20, 30)]);
+        s.free(15, 5);
+        assert_eq!(s.free, [(0, 50)]);
+    }
+
+    #[test]
+    fn freed_parts_are_reused() {
+        let mut s = shelf(&[(0, 100)]);
+        assert_eq!(
+            (s.allocate(30), s.allocate(30), s.allocate(30)),
+            (0, 30, 60)
+        );
+        assert_eq!(s.free, [(90, 10)]);
+        assert!(!s.fits(20));
+
+        s.free(30, 30);
+        assert!(s.fits(20));
+        assert_eq!(s.allocate(20), 30);
+        assert_eq!(s.allocate(10), 50);
+        assert_eq!(s.free, [(90, 10)]);
+    }
+
+    #[test]
+    fn layer_allocation() {
+        let mut layer = Layer::new(32, 32);
+        // Shelf heights are rounded up.
+        assert_eq!(layer.allocate(20, 5, 32, 32), Some((0, 0)));
+        assert_eq!(layer.allocate(12, 7, 32, 32), Some((20, 0)));
+        assert_eq!(layer.shelves[0].height, 8);
+        // The first shelf is full, so a second one is added above it.
+        assert_eq!(layer.allocate(10, 3, 32, 32), Some((0, 8)));
+        // A lower shelf is preferred when it has room.
+        assert_eq!(layer.allocate(10, 2, 32, 32), Some((10, 8)));
+        // Sprites taller than an existing shelf get their own.
+        assert_eq!(layer.allocate(8, 12, 32, 32), Some((0, 16)));
+        assert_eq!(layer.shelves[2].height, 16);
+        // The last shelf is clamped to the layer, and nothing fits above it.
+        assert_eq!(layer.allocate(32, 1, 32, 32), None);
+        assert_eq!(layer.allocate(24, 10, 32, 32), Some((8, 16)));
+        assert_eq!(layer.shelves.len(), 3);
+    }
+
+    #[test]
+    fn layer_allocation_avoids_much_higher_shelves() {
+        let mut layer = Layer::new(64, 64);
+        assert_eq!(layer.allocate(8, 32, 64, 64), Some((0, 0)));
+        // Putting this on the 32 pixel shelf would waste most of the space above it.
+        assert_eq!(layer.allocate(8, 4, 64, 64), Some((0, 32)));
+        assert_eq!(layer.shelves.len(), 2);
+        // But the shelf is used once there's no room elsewhere.
+        assert_eq!(layer.allocate(56, 4, 64, 64), Some((8, 32)));
+        assert_eq!(layer.allocate(60, 20, 64, 64), Some((0, 40)));
+        assert_eq!(layer.allocate(8, 4, 64, 64), Some((8, 0)));
+    }
+
+    #[test]
+    fn empty_top_shelves_are_removed() {
+        let mut layer = Layer::new(32, 32);
+        assert_eq!(layer.allocate(10, 8, 32, 32), Some((0, 0)));
+        assert_eq!(layer.allocate(10, 16, 32, 32), Some((0, 8)));
+        assert_eq!(layer.allocate(10, 8, 32, 32), Some((10, 0)));
+        assert_eq!(layer.allocate(24, 4, 32, 32), Some((0, 24)));
+
+        // A shelf below others is kept even when it's empty.
+        layer.free(0, 8, 10, 32);
+        assert_eq!(layer.shelves.len(), 3);
+        // Freeing the top shelf also removes the empty one below it.
+        layer.free(0, 24, 24, 32);
+        assert_eq!(layer.shelves.len(), 1);
+        // Its space can be taken by a taller shelf.
+        assert_eq!(layer.allocate(10, 24, 32, 32), Some((0, 8)));
+
+        layer.free(0, 8, 10, 32);
+        layer.free(0, 0, 10, 32);
+        layer.free(10, 0, 10, 32);
+        assert!(layer.shelves.is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+
+/// The error type for fallible operations in this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// A resource could not be read or written. On the web, this includes failed fetches.
+    Io { path: String, message: String },
+    /// A resource was read, but could not be decoded (e.g. a corrupt image or sound).
+    Decode { path: String, message: String },
+    /// An OpenGL operation failed, such as creating an object.
+    Gl(String),
+    /// A shader failed to compile. `log` is the driver's info log.
+    ShaderCompile { log: String },
+    /// A shader program failed to link. `log` is the driver's info log.
+    ShaderLink { log: String },
+    /// A shader could not be preprocessed, or a uniform or attribute lookup failed.
+    Shader(String),
+    /// A value could not be serialized or deserialized.
+    Serialization(String),
+    /// An argument was invalid, such as a buffer of the wrong length.
+    InvalidArgument(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub(crate) fn io(path: &str, message: impl ToString) -> Self {
+        Error::Io {
+            path: path.to_owned(),
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn decode(path: &str, message: impl ToString) -> Self {
+        Error::Decode {
+            path: path.to_owned(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path, message } => write!(f, "I/O error on `{}`: {}", path, message),
+            Error::Decode { path, message } => {
+                write!(f, "could not decode `{}`: {}", path, message)
+            }
+            Error::Gl(message) => write!(f, "OpenGL error: {}", message),
+            Error::ShaderCompile { log } => write!(f, "shader compilation failed:\n{}", log),
+            Error::ShaderLink { log } => write!(f, "shader linking failed:\n{}", log),
+            Error::Shader(message) => write!(f, "shader error: {}", message),
+            Error::Serialization(message) => write!(f, "serialization error: {}", message),
+            Error::InvalidArgument(message) => write!(f, "invalid argument: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
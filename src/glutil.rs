@@ -1,13 +1,32 @@
 use crate::prelude::*;
+use crate::Error;
 use scopeguard::defer;
 
+mod preprocess;
+mod program;
+mod state;
+mod stream;
+mod vertex;
+
 pub use crate::backend::glutil::*;
+pub use preprocess::*;
+pub use program::*;
+pub use state::*;
+pub use stream::*;
+pub use vertex::*;
+
+/// The `#version` directive prepended to every shader.
+#[cfg(not(target_arch = "wasm32"))]
+pub const GLSL_VERSION: &str = "#version 330 core";
+/// The `#version` directive prepended to every shader.
+#[cfg(target_arch = "wasm32")]
+pub const GLSL_VERSION: &str = "#version 300 es";
 
 pub fn compile_shader_program(
     gl: &Gl,
     vs_code: &str,
     fs_code: &str,
-) -> Result<glow::Program, String> {
+) -> Result<glow::Program, Error> {
     let vs = compile_shader(gl, glow::VERTEX_SHADER, vs_code)?;
     defer!(unsafe { gl.delete_shader(vs) });
     let fs = compile_shader(gl, glow::FRAGMENT_SHADER, fs_code)?;
@@ -16,32 +35,35 @@ pub fn compile_shader_program(
     link_program(gl, &[vs, fs])
 }
 
-pub fn compile_shader(gl: &Gl, shader_type: u32, code: &str) -> Result<glow::Shader, String> {
+pub fn compile_shader(gl: &Gl, shader_type: u32, code: &str) -> Result<glow::Shader, Error> {
     let mut patched_code = String::new();
-    #[cfg(not(target_arch = "wasm32"))]
-    patched_code.push_str("#version 330 core\n");
-    #[cfg(target_arch = "wasm32")]
-    patched_code.push_str("#version 300 es\n");
+    patched_code.push_str(GLSL_VERSION);
+    patched_code.push('\n');
     patched_code.push_str(code);
 
+    compile_shader_raw(gl, shader_type, &patched_code)
+}
+
+/// Compiles shader code as-is, without prepending the `#version` directive.
+pub fn compile_shader_raw(gl: &Gl, shader_type: u32, code: &str) -> Result<glow::Shader, Error> {
     unsafe {
-        let shader = gl.create_shader(shader_type)?;
-        gl.shader_source(shader, &patched_code);
+        let shader = gl.create_shader(shader_type).map_err(Error::Gl)?;
+        gl.shader_source(shader, code);
         gl.compile_shader(shader);
 
         if !gl.get_shader_compile_status(shader) {
             let info_log = gl.get_shader_info_log(shader);
             gl.delete_shader(shader);
-            Err(info_log)
+            Err(Error::ShaderCompile { log: info_log })
         } else {
             Ok(shader)
         }
     }
 }
 
-pub fn link_program(gl: &Gl, shaders: &[glow::Shader]) -> Result<glow::Program, String> {
+pub fn link_program(gl: &Gl, shaders: &[glow::Shader]) -> Result<glow::Program, Error> {
     unsafe {
-        let program = gl.create_program()?;
+        let program = gl.create_program().map_err(Error::Gl)?;
         for &shader in shaders {
             gl.attach_shader(program, shader);
         }
@@ -50,7 +72,7 @@ pub fn link_program(gl: &Gl, shaders: &[glow::Shader]) -> Result<glow::Program,
         if !gl.get_program_link_status(program) {
             let info_log = gl.get_program_info_log(program);
             gl.delete_program(program);
-            Err(info_log)
+            Err(Error::ShaderLink { log: info_log })
         } else {
             Ok(program)
         }
@@ -61,12 +83,36 @@ pub fn get_uniform_location(
     gl: &Gl,
     shader: glow::Program,
     name: &str,
-) -> Result<glow::UniformLocation, String> {
+) -> Result<glow::UniformLocation, Error> {
     unsafe { gl.get_uniform_location(shader, name) }
-        .ok_or_else(|| format!("Could not find uniform named `{}`.", name))
+        .ok_or_else(|| Error::Shader(format!("Could not find uniform named `{}`.", name)))
 }
 
 pub fn as_u8_slice<T>(data: &[T]) -> &[u8] {
     let size = std::mem::size_of_val(data);
     unsafe { std::slice::from_raw_parts(data.as_ptr() as *const _, size) }
 }
+
+const CONTEXT_LOST_WEBGL: u32 = 0x9242;
+
+/// Drains the OpenGL error queue, logging each error.
+pub(crate) fn log_errors(gl: &glow::Context, context: &str) {
+    loop {
+        let error = unsafe { gl.get_error() };
+        let name = match error {
+            glow::NO_ERROR => break,
+            glow::INVALID_ENUM => "GL_INVALID_ENUM",
+            glow::INVALID_VALUE => "GL_INVALID_VALUE",
+            glow::INVALID_OPERATION => "GL_INVALID_OPERATION",
+            glow::INVALID_FRAMEBUFFER_OPERATION => "GL_INVALID_FRAMEBUFFER_OPERATION",
+            glow::OUT_OF_MEMORY => "GL_OUT_OF_MEMORY",
+            // A lost context may report this on every call, so stop after reporting it once.
+            glow::CONTEXT_LOST | CONTEXT_LOST_WEBGL => {
+                log::error!("{}: context lost", context);
+                break;
+            }
+            _ => "unknown error",
+        };
+        log::error!("{}: {} (0x{:04X})", context, name, error);
+    }
+}
@@ -0,0 +1,385 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::prelude::*;
+use crate::Error;
+use scopeguard::defer;
+
+/// GLSL preprocessor supporting `#include` from a virtual file table.
+///
+/// Since shaders are usually embedded with `include_str!`, included files are looked up in a
+/// table of named sources instead of the filesystem, which means this works the same on desktop
+/// and on the web.
+///
+/// Supported extensions to GLSL:
+/// - `#include "name"` (or `#include <name>`) pastes the named file in place. Each file is
+///   included at most once per shader, so shared code does not need include guards.
+/// - `#precision <qualifier> <type>` expands to a `precision` statement on OpenGL ES and is
+///   removed on desktop OpenGL.
+///
+/// Caller-supplied defines and default precision statements are inserted after the `#version`
+/// line. Line numbers in the driver's info log are mapped back to the original file and line.
+///
+/// Conditionals are left to the driver, so `#include` is expanded even inside an inactive
+/// `#if`/`#ifdef` block: the file must exist, and since it is only included once, a later
+/// `#include` of it in an active block is dropped. Include such files unconditionally.
+#[derive(Clone, Default)]
+pub struct Preprocessor {
+    files: HashMap<String, Cow<'static, str>>,
+    defines: Vec<(String, String)>,
+    precisions: Vec<String>,
+}
+
+/// The output of the preprocessor, ready to be passed to the driver.
+pub struct PreprocessedSource {
+    pub code: String,
+    files: Vec<String>,
+    /// For each line of `code`, the index into `files` and the 1-based line in that file. `None`
+    /// for lines generated by the preprocessor.
+    lines: Vec<Option<(usize, usize)>>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file to the virtual file table, replacing any previous file with the same name.
+    pub fn add_file(
+        &mut self,
+        name: impl Into<String>,
+        source: impl Into<Cow<'static, str>>,
+    ) -> &mut Self {
+        self.files.insert(name.into(), source.into());
+        self
+    }
+
+    /// Adds a `#define` to every shader processed by this preprocessor.
+    pub fn define(&mut self, name: impl Into<String>, value: impl ToString) -> &mut Self {
+        self.defines.push((name.into(), value.to_string()));
+        self
+    }
+
+    /// Adds a default precision statement (e.g. `"mediump float"`) which is only emitted on
+    /// OpenGL ES.
+    pub fn precision(&mut self, precision: impl Into<String>) -> &mut Self {
+        self.precisions.push(precision.into());
+        self
+    }
+
+    /// Preprocesses the file with the given name from the virtual file table.
+    pub fn process(&self, name: &str) -> Result<PreprocessedSource, Error> {
+        let source = self
+            .files
+            .get(name)
+            .ok_or_else(|| Error::Shader(format!("Shader source `{}` not found.", name)))?;
+        self.process_source(name, source)
+    }
+
+    /// Preprocesses the given source code. `name` is used in error messages.
+    pub fn process_source(&self, name: &str, source: &str) -> Result<PreprocessedSource, Error> {
+        let mut out = PreprocessedSource {
+            code: String::new(),
+            files: vec![],
+            lines: vec![],
+        };
+
+        out.push_generated(glutil::GLSL_VERSION);
+        for (name, value) in &self.defines {
+            out.push_generated(&format!("#define {} {}", name, value));
+        }
+        if cfg!(target_arch = "wasm32") {
+            for precision in &self.precisions {
+                out.push_generated(&format!("precision {};", precision));
+            }
+        }
+
+        self.process_file(&mut out, &mut vec![], name, source)?;
+        Ok(out)
+    }
+
+    /// Preprocesses, compiles and links the named vertex and fragment shaders.
+    pub fn compile_program(
+        &self,
+        gl: &Gl,
+        vs_name: &str,
+        fs_name: &str,
+    ) -> Result<glow::Program, Error> {
+        let vs = self.process(vs_name)?.compile(gl, glow::VERTEX_SHADER)?;
+        defer!(unsafe { gl.delete_shader(vs) });
+        let fs = self.process(fs_name)?.compile(gl, glow::FRAGMENT_SHADER)?;
+        defer!(unsafe { gl.delete_shader(fs) });
+
+        glutil::link_program(gl, &[vs, fs])
+    }
+
+    fn process_file(
+        &self,
+        out: &mut PreprocessedSource,
+        stack: &mut Vec<String>,
+        name: &str,
+        source: &str,
+    ) -> Result<(), Error> {
+        let file = out.files.len();
+        out.files.push(name.to_owned());
+        stack.push(name.to_owned());
+
+        for (i, line) in source.lines().enumerate() {
+            let location = (file, i + 1);
+            let directive = line.trim_start();
+            if let Some(rest) = directive.strip_prefix("#include") {
+                let target = parse_include(rest).ok_or_else(|| {
+                    Error::Shader(format!("{}:{}: malformed #include", name, i + 1))
+                })?;
+                if stack.iter().any(|f| f == target) {
+                    return Err(Error::Shader(format!(
+                        "{}:{}: recursive #include of `{}`",
+                        name,
+                        i + 1,
+                        target
+                    )));
+                }
+                if out.files.iter().any(|f| f == target) {
+                    continue;
+                }
+                let source = self.files.get(target).ok_or_else(|| {
+                    Error::Shader(format!(
+                        "{}:{}: included file `{}` not found",
+                        name,
+                        i + 1,
+                        target
+                    ))
+                })?;
+                self.process_file(out, stack, target, source)?;
+            } else if let Some(rest) = directive.strip_prefix("#precision") {
+                let precision = rest.trim();
+                if precision.is_empty() {
+                    return Err(Error::Shader(format!(
+                        "{}:{}: malformed #precision",
+                        name,
+                        i + 1
+                    )));
+                }
+                if cfg!(target_arch = "wasm32") {
+                    out.push(&format!("precision {};", precision), location);
+                } else {
+                    out.push("", location);
+                }
+            } else if directive.starts_with("#version") {
+                // The version directive is supplied by the preprocessor.
+                out.push("", location);
+            } else {
+                out.push(line, location);
+            }
+        }
+
+        stack.pop();
+        Ok(())
+    }
+}
+
+impl PreprocessedSource {
+    fn push(&mut self, line: &str, location: (usize, usize)) {
+        self.code.push_str(line);
+        self.code.push('\n');
+        self.lines.push(Some(location));
+    }
+
+    fn push_generated(&mut self, line: &str) {
+        self.code.push_str(line);
+        self.code.push('\n');
+        self.lines.push(None);
+    }
+
+    /// Returns the original file and line of the given 1-based line of the processed source.
+    pub fn original_location(&self, line: usize) -> Option<(&str, usize)> {
+        let (file, line) = (*self.lines.get(line.checked_sub(1)?)?)?;
+        Some((&self.files[file], line))
+    }
+
+    /// Rewrites line references in a shader info log to refer to the original files.
+    ///
+    /// Understands the `0:LINE` (Mesa, ANGLE, WebGL) and `0(LINE)` (NVIDIA) formats. Lines of
+    /// the log that are not recognized are passed through unchanged.
+    pub fn map_info_log(&self, log: &str) -> String {
+        let mut mapped = String::new();
+        for line in log.lines() {
+            match find_line_reference(line) {
+                Some((start, end, number)) => match self.original_location(number) {
+                    Some((file, original)) => {
+                        write!(
+                            mapped,
+                            "{}{}:{}{}",
+                            &line[..start],
+                            file,
+                            original,
+                            &line[end..]
+                        )
+                        .unwrap();
+                    }
+                    None => mapped.push_str(line),
+                },
+                None => mapped.push_str(line),
+            }
+            mapped.push('\n');
+        }
+        mapped
+    }
+
+    /// Compiles the processed source, mapping any errors back to the original files.
+    pub fn compile(&self, gl: &Gl, shader_type: u32) -> Result<glow::Shader, Error> {
+        glutil::compile_shader_raw(gl, shader_type, &self.code).map_err(|e| match e {
+            Error::ShaderCompile { log } => Error::ShaderCompile {
+                log: self.map_info_log(&log),
+            },
+            e => e,
+        })
+    }
+}
+
+fn parse_include(rest: &str) -> Option<&str> {
+    let rest = rest.trim();
+    let close = match rest.chars().next()? {
+        '"' => '"',
+        '<' => '>',
+        _ => return None,
+    };
+    let rest = &rest[1..];
+    let end = rest.find(close)?;
+    if !rest[end + 1..].trim().is_empty() {
+        return None;
+    }
+    Some(&rest[..end])
+}
+
+/// Finds a `0:LINE` or `0(LINE)` reference near the start of a line of an info log, returning
+/// the byte range of the reference and the line number.
+fn find_line_reference(line: &str) -> Option<(usize, usize, usize)> {
+    let mut start = 0;
+    for prefix in &["ERROR: ", "WARNING: "] {
+        if line.starts_with(prefix) {
+            start = prefix.len();
+        }
+    }
+
+    let rest = &line[start..];
+    let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+    if digits == 0 {
+        return None;
+    }
+    let rest = &rest[digits..];
+    let (open_len, close) = if rest.starts_with(':') {
+        (1, None)
+    } else if rest.starts_with('(') {
+        (1, Some(')'))
+    } else {
+        return None;
+    };
+
+    let number_start = start + digits + open_len;
+    let number_len = line[number_start..]
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(line.len() - number_start);
+    if number_len == 0 {
+        return None;
+    }
+    let number = line[number_start..number_start + number_len].parse().ok()?;
+    let mut end = number_start + number_len;
+    if let Some(close) = close {
+        if !line[end..].starts_with(close) {
+            return None;
+        }
+        end += 1;
+    }
+    Some((start, end, number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocessor() -> Preprocessor {
+        let mut pp = Preprocessor::new();
+        pp.add_file(
+            "main",
+            "#version 100\n#include \"a\"\n#include <b>\nvoid main() {}",
+        )
+        .add_file("a", "#include \"b\"\nfloat a;")
+        .add_file("b", "float b;");
+        pp
+    }
+
+    #[test]
+    fn nested_includes_are_included_once() {
+        let out = preprocessor().process("main").unwrap();
+        let code: Vec<_> = out.code.lines().collect();
+        assert_eq!(
+            code,
+            [
+                glutil::GLSL_VERSION,
+                "",
+                "float b;",
+                "float a;",
+                "void main() {}"
+            ]
+        );
+    }
+
+    #[test]
+    fn recursive_include() {
+        let mut pp = preprocessor();
+        pp.add_file("b", "float b;\n#include \"a\"");
+        match pp.process("main") {
+            Err(Error::Shader(message)) => {
+                assert_eq!(message, "b:2: recursive #include of `a`")
+            }
+            _ => panic!("expected a recursive include error"),
+        }
+    }
+
+    #[test]
+    fn missing_include() {
+        let mut pp = preprocessor();
+        pp.add_file("a", "\n#include \"c\"");
+        match pp.process("main") {
+            Err(Error::Shader(message)) => {
+                assert_eq!(message, "a:2: included file `c` not found")
+            }
+            _ => panic!("expected a missing include error"),
+        }
+    }
+
+    #[test]
+    fn original_locations() {
+        let mut pp = preprocessor();
+        pp.define("N", 4);
+        let out = pp.process("main").unwrap();
+        let locations: Vec<_> = (0..=7).map(|line| out.original_location(line)).collect();
+        assert_eq!(
+            locations,
+            [
+                None,
+                None,
+                None,
+                Some(("main", 1)),
+                Some(("b", 1)),
+                Some(("a", 2)),
+                Some(("main", 4)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn info_log_lines_are_mapped() {
+        let out = preprocessor().process("main").unwrap();
+        let log = "ERROR: 0:3: 'a' : redefinition\n0(4) : error C0000: syntax error\n\
+                   WARNING: 0:99: unknown line\nsomething else";
+        assert_eq!(
+            out.map_info_log(log),
+            "ERROR: b:1: 'a' : redefinition\na:2 : error C0000: syntax error\n\
+             WARNING: 0:99: unknown line\nsomething else\n"
+        );
+    }
+}
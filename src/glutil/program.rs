@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use crate::prelude::*;
+use crate::Error;
+
+/// An owned, linked shader program with reflected uniforms and attributes.
+///
+/// The program is deleted when this is dropped.
+pub struct ShaderProgram {
+    gl: Gl,
+    program: glow::Program,
+    uniforms: HashMap<String, UniformInfo>,
+    attributes: HashMap<String, AttributeInfo>,
+}
+
+/// Information about an active uniform of a `ShaderProgram`.
+#[derive(Clone, Debug)]
+pub struct UniformInfo {
+    pub location: glow::UniformLocation,
+    /// The GL type of the uniform, e.g. `glow::FLOAT_MAT4`.
+    pub gl_type: u32,
+    /// The number of array elements, or 1 if the uniform is not an array.
+    pub size: i32,
+}
+
+/// Information about an active vertex attribute of a `ShaderProgram`.
+#[derive(Copy, Clone, Debug)]
+pub struct AttributeInfo {
+    pub location: u32,
+    /// The GL type of the attribute, e.g. `glow::FLOAT_VEC2`.
+    pub gl_type: u32,
+    /// The number of array elements, or 1 if the attribute is not an array.
+    pub size: i32,
+}
+
+/// A type-checked handle to a uniform of a `ShaderProgram`.
+pub struct Uniform<T> {
+    location: glow::UniformLocation,
+    _type: PhantomData<fn(T)>,
+}
+
+impl<T> Clone for Uniform<T> {
+    // Uniform locations are only `Copy` on desktop.
+    #[allow(clippy::clone_on_copy)]
+    fn clone(&self) -> Self {
+        Uniform {
+            location: self.location.clone(),
+            _type: PhantomData,
+        }
+    }
+}
+
+/// A texture unit to bind to a sampler uniform.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Sampler(pub u32);
+
+/// A column-major 2x2 matrix.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat2(pub [f32; 4]);
+
+/// A column-major 3x3 matrix.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat3(pub [f32; 9]);
+
+impl ShaderProgram {
+    /// Takes ownership of a linked program and reflects its active uniforms and attributes.
+    pub fn new(gl: &Gl, program: glow::Program) -> Self {
+        let mut uniforms = HashMap::new();
+        let mut attributes = HashMap::new();
+        unsafe {
+            for i in 0..gl.get_active_uniforms(program) {
+                let uniform = match gl.get_active_uniform(program, i) {
+                    Some(u) => u,
+                    None => continue,
+                };
+                // Uniforms in uniform blocks have no location.
+                let location = match gl.get_uniform_location(program, &uniform.name) {
+                    Some(l) => l,
+                    None => continue,
+                };
+                let name = uniform
+                    .name
+                    .strip_suffix("[0]")
+                    .unwrap_or(&uniform.name)
+                    .to_owned();
+                uniforms.insert(
+                    name,
+                    UniformInfo {
+                        location,
+                        gl_type: uniform.utype,
+                        size: uniform.size,
+                    },
+                );
+            }
+
+            for i in 0..gl.get_active_attributes(program) {
+                let attribute = match gl.get_active_attribute(program, i) {
+                    Some(a) => a,
+                    None => continue,
+                };
+                // Built-in attributes such as `gl_VertexID` have no location.
+                let location = match gl.get_attrib_location(program, &attribute.name) {
+                    Some(l) => l,
+                    None => continue,
+                };
+                attributes.insert(
+                    attribute.name,
+                    AttributeInfo {
+                        location,
+                        gl_type: attribute.atype,
+                        size: attribute.size,
+                    },
+                );
+            }
+        }
+
+        ShaderProgram {
+            gl: gl.clone(),
+            program,
+            uniforms,
+            attributes,
+        }
+    }
+
+    /// Compiles and links a program from vertex and fragment shader source code.
+    pub fn from_source(gl: &Gl, vs_code: &str, fs_code: &str) -> Result<Self, Error> {
+        glutil::compile_shader_program(gl, vs_code, fs_code).map(|p| Self::new(gl, p))
+    }
+
+    /// The underlying program object.
+    pub fn raw(&self) -> glow::Program {
+        self.program
+    }
+
+    /// Attaches a debug label to the program, shown in debug output and graphics debuggers.
+    pub fn set_label(&self, label: &str) {
+        self.gl.label_program(self.program, label);
+    }
+
+    /// Makes this the current shader program.
+    pub fn bind(&self) {
+        unsafe {
+            self.gl.use_program(Some(self.program));
+        }
+    }
+
+    pub fn uniforms(&self) -> impl Iterator<Item = (&str, &UniformInfo)> {
+        self.uniforms.iter().map(|(k, v)| (&**k, v))
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = (&str, &AttributeInfo)> {
+        self.attributes.iter().map(|(k, v)| (&**k, v))
+    }
+
+    pub fn uniform_info(&self, name: &str) -> Option<&UniformInfo> {
+        self.uniforms.get(name)
+    }
+
+    pub fn attribute_info(&self, name: &str) -> Option<&AttributeInfo> {
+        self.attributes.get(name)
+    }
+
+    /// Looks up the location of the named vertex attribute.
+    pub fn attribute_location(&self, name: &str) -> Result<u32, Error> {
+        self.attributes
+            .get(name)
+            .map(|a| a.location)
+            .ok_or_else(|| Error::Shader(format!("Could not find attribute named `{}`.", name)))
+    }
+
+    /// Looks up the named uniform, checking that its type matches `T`.
+    pub fn uniform<T: UniformValue>(&self, name: &str) -> Result<Uniform<T>, Error> {
+        let info = self
+            .uniforms
+            .get(name)
+            .ok_or_else(|| Error::Shader(format!("Could not find uniform named `{}`.", name)))?;
+        if !T::GL_TYPES.contains(&info.gl_type) {
+            return Err(Error::Shader(format!(
+                "Uniform `{}` has type {}, but a value of type {} was given.",
+                name,
+                type_name(info.gl_type),
+                type_name(T::GL_TYPES[0])
+            )));
+        }
+        #[allow(clippy::clone_on_copy)]
+        let location = info.location.clone();
+        Ok(Uniform {
+            location,
+            _type: PhantomData,
+        })
+    }
+
+    /// Sets the value of a uniform. This makes this the current shader program.
+    pub fn set<T: UniformValue>(&self, uniform: &Uniform<T>, value: T) {
+        self.bind();
+        unsafe {
+            value.set(&self.gl, &uniform.location);
+        }
+    }
+
+    /// Looks up and sets the named uniform. This makes this the current shader program.
+    pub fn set_uniform<T: UniformValue>(&self, name: &str, value: T) -> Result<(), Error> {
+        let uniform = self.uniform(name)?;
+        self.set(&uniform, value);
+        Ok(())
+    }
+}
+
+impl Drop for ShaderProgram {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.delete_program(self.program);
+        }
+    }
+}
+
+/// A value that can be assigned to a uniform.
+pub trait UniformValue {
+    /// The GL uniform types this value can be assigned to. The first is used in error messages.
+    const GL_TYPES: &'static [u32];
+
+    /// Assigns this value to the uniform at `location` of the current program.
+    ///
+    /// # Safety
+    /// The uniform must have one of the types in `GL_TYPES`.
+    unsafe fn set(&self, gl: &Gl, location: &glow::UniformLocation);
+}
+
+macro_rules! uniform_value {
+    ($t:ty, [$($gl_type:ident),*], |$v:ident, $gl:ident, $loc:ident| $set:expr) => {
+        impl UniformValue for $t {
+            const GL_TYPES: &'static [u32] = &[$(glow::$gl_type),*];
+
+            unsafe fn set(&self, $gl: &Gl, $loc: &glow::UniformLocation) {
+                let $v = self;
+                $set
+            }
+        }
+    };
+}
+
+uniform_value!(f32, [FLOAT], |v, gl, l| gl.uniform_1_f32(Some(l), *v));
+uniform_value!([f32; 2], [FLOAT_VEC2], |v, gl, l| gl.uniform_2_f32(
+    Some(l),
+    v[0],
+    v[1]
+));
+uniform_value!([f32; 3], [FLOAT_VEC3], |v, gl, l| gl.uniform_3_f32(
+    Some(l),
+    v[0],
+    v[1],
+    v[2]
+));
+uniform_value!([f32; 4], [FLOAT_VEC4], |v, gl, l| gl.uniform_4_f32(
+    Some(l),
+    v[0],
+    v[1],
+    v[2],
+    v[3]
+));
+uniform_value!(Vec2<f32>, [FLOAT_VEC2], |v, gl, l| gl.uniform_2_f32(
+    Some(l),
+    v.x,
+    v.y
+));
+uniform_value!(Vec3<f32>, [FLOAT_VEC3], |v, gl, l| gl.uniform_3_f32(
+    Some(l),
+    v.x,
+    v.y,
+    v.z
+));
+uniform_value!(Point2<f32>, [FLOAT_VEC2], |v, gl, l| gl.uniform_2_f32(
+    Some(l),
+    v.x,
+    v.y
+));
+uniform_value!(Point3<f32>, [FLOAT_VEC3], |v, gl, l| gl.uniform_3_f32(
+    Some(l),
+    v.x,
+    v.y,
+    v.z
+));
+uniform_value!(Size2<f32>, [FLOAT_VEC2], |v, gl, l| gl.uniform_2_f32(
+    Some(l),
+    v.width,
+    v.height
+));
+uniform_value!(bool, [BOOL], |v, gl, l| gl
+    .uniform_1_i32(Some(l), *v as i32));
+uniform_value!(i32, [INT], |v, gl, l| gl.uniform_1_i32(Some(l), *v));
+uniform_value!([i32; 2], [INT_VEC2], |v, gl, l| gl.uniform_2_i32(
+    Some(l),
+    v[0],
+    v[1]
+));
+uniform_value!([i32; 3], [INT_VEC3], |v, gl, l| gl.uniform_3_i32(
+    Some(l),
+    v[0],
+    v[1],
+    v[2]
+));
+uniform_value!([i32; 4], [INT_VEC4], |v, gl, l| gl.uniform_4_i32(
+    Some(l),
+    v[0],
+    v[1],
+    v[2],
+    v[3]
+));
+uniform_value!(u32, [UNSIGNED_INT], |v, gl, l| gl
+    .uniform_1_u32(Some(l), *v));
+uniform_value!([u32; 2], [UNSIGNED_INT_VEC2], |v, gl, l| gl.uniform_2_u32(
+    Some(l),
+    v[0],
+    v[1]
+));
+uniform_value!([u32; 3], [UNSIGNED_INT_VEC3], |v, gl, l| gl.uniform_3_u32(
+    Some(l),
+    v[0],
+    v[1],
+    v[2]
+));
+uniform_value!([u32; 4], [UNSIGNED_INT_VEC4], |v, gl, l| gl.uniform_4_u32(
+    Some(l),
+    v[0],
+    v[1],
+    v[2],
+    v[3]
+));
+uniform_value!(Mat2, [FLOAT_MAT2], |v, gl, l| gl
+    .uniform_matrix_2_f32_slice(Some(l), false, &v.0));
+uniform_value!(Mat3, [FLOAT_MAT3], |v, gl, l| gl
+    .uniform_matrix_3_f32_slice(Some(l), false, &v.0));
+uniform_value!(Transform2D<f32>, [FLOAT_MAT3], |v, gl, l| gl
+    .uniform_matrix_3_f32_slice(
+        Some(l),
+        false,
+        &[v.m11, v.m12, 0.0, v.m21, v.m22, 0.0, v.m31, v.m32, 1.0]
+    ));
+uniform_value!(Transform3D<f32>, [FLOAT_MAT4], |v, gl, l| gl
+    .uniform_matrix_4_f32_slice(Some(l), false, &v.to_array()));
+uniform_value!(
+    Sampler,
+    [
+        SAMPLER_2D,
+        SAMPLER_3D,
+        SAMPLER_CUBE,
+        SAMPLER_2D_SHADOW,
+        SAMPLER_2D_ARRAY,
+        SAMPLER_2D_ARRAY_SHADOW,
+        SAMPLER_CUBE_SHADOW,
+        INT_SAMPLER_2D,
+        INT_SAMPLER_3D,
+        INT_SAMPLER_CUBE,
+        INT_SAMPLER_2D_ARRAY,
+        UNSIGNED_INT_SAMPLER_2D,
+        UNSIGNED_INT_SAMPLER_3D,
+        UNSIGNED_INT_SAMPLER_CUBE,
+        UNSIGNED_INT_SAMPLER_2D_ARRAY
+    ],
+    |v, gl, l| gl.uniform_1_i32(Some(l), v.0 as i32)
+);
+
+/// Texture units for the elements of a sampler array, starting at the first element.
+impl UniformValue for Vec<Sampler> {
+    const GL_TYPES: &'static [u32] = Sampler::GL_TYPES;
+
+    unsafe fn set(&self, gl: &Gl, location: &glow::UniformLocation) {
+        let units: Vec<_> = self.iter().map(|s| s.0 as i32).collect();
+        gl.uniform_1_i32_slice(Some(location), &units);
+    }
+}
+
+/// Returns the GLSL name of a GL type enum, for use in error messages.
+pub fn type_name(gl_type: u32) -> &'static str {
+    match gl_type {
+        glow::FLOAT => "float",
+        glow::FLOAT_VEC2 => "vec2",
+        glow::FLOAT_VEC3 => "vec3",
+        glow::FLOAT_VEC4 => "vec4",
+        glow::INT => "int",
+        glow::INT_VEC2 => "ivec2",
+        glow::INT_VEC3 => "ivec3",
+        glow::INT_VEC4 => "ivec4",
+        glow::UNSIGNED_INT => "uint",
+        glow::UNSIGNED_INT_VEC2 => "uvec2",
+        glow::UNSIGNED_INT_VEC3 => "uvec3",
+        glow::UNSIGNED_INT_VEC4 => "uvec4",
+        glow::BOOL => "bool",
+        glow::BOOL_VEC2 => "bvec2",
+        glow::BOOL_VEC3 => "bvec3",
+        glow::BOOL_VEC4 => "bvec4",
+        glow::FLOAT_MAT2 => "mat2",
+        glow::FLOAT_MAT3 => "mat3",
+        glow::FLOAT_MAT4 => "mat4",
+        glow::FLOAT_MAT2x3 => "mat2x3",
+        glow::FLOAT_MAT2x4 => "mat2x4",
+        glow::FLOAT_MAT3x2 => "mat3x2",
+        glow::FLOAT_MAT3x4 => "mat3x4",
+        glow::FLOAT_MAT4x2 => "mat4x2",
+        glow::FLOAT_MAT4x3 => "mat4x3",
+        glow::SAMPLER_2D => "sampler2D",
+        glow::SAMPLER_3D => "sampler3D",
+        glow::SAMPLER_CUBE => "samplerCube",
+        glow::SAMPLER_2D_SHADOW => "sampler2DShadow",
+        glow::SAMPLER_2D_ARRAY => "sampler2DArray",
+        glow::SAMPLER_2D_ARRAY_SHADOW => "sampler2DArrayShadow",
+        glow::SAMPLER_CUBE_SHADOW => "samplerCubeShadow",
+        glow::INT_SAMPLER_2D => "isampler2D",
+        glow::INT_SAMPLER_3D => "isampler3D",
+        glow::INT_SAMPLER_CUBE => "isamplerCube",
+        glow::INT_SAMPLER_2D_ARRAY => "isampler2DArray",
+        glow::UNSIGNED_INT_SAMPLER_2D => "usampler2D",
+        glow::UNSIGNED_INT_SAMPLER_3D => "usampler3D",
+        glow::UNSIGNED_INT_SAMPLER_CUBE => "usamplerCube",
+        glow::UNSIGNED_INT_SAMPLER_2D_ARRAY => "usampler2DArray",
+        _ => "<unknown type>",
+    }
+}
@@ -0,0 +1,392 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+/// Number of texture units whose bindings are tracked. Bindings on higher units bypass the cache.
+const TRACKED_TEXTURE_UNITS: usize = 16;
+
+/// Vertex attribute indices at or above this bypass the cache.
+const TRACKED_ATTRIBUTES: u32 = 32;
+
+/// Cache of the OpenGL state that `Gl` tracks.
+///
+/// For each value, `None` means the value is unknown, in which case the next call that sets it is
+/// always issued.
+pub(crate) struct StateCache {
+    program: Cell<Option<Option<glow::Program>>>,
+    array_buffer: Cell<Option<Option<glow::Buffer>>>,
+    vertex_array: Cell<Option<Option<glow::VertexArray>>>,
+    vertex_arrays: RefCell<HashMap<Option<glow::VertexArray>, VertexArrayState>>,
+    active_texture: Cell<Option<u32>>,
+    textures: RefCell<[TextureUnit; TRACKED_TEXTURE_UNITS]>,
+    unpack_alignment: Cell<Option<i32>>,
+}
+
+/// State stored in a vertex array object.
+#[derive(Copy, Clone)]
+struct VertexArrayState {
+    element_array_buffer: Option<Option<glow::Buffer>>,
+    /// Bitmask of attributes whose enabled state is known.
+    known_attributes: u32,
+    /// Bitmask of enabled attributes. Only meaningful for bits set in `known_attributes`.
+    enabled_attributes: u32,
+}
+
+#[derive(Copy, Clone)]
+struct TextureUnit {
+    texture_2d: Option<Option<glow::Texture>>,
+    texture_2d_array: Option<Option<glow::Texture>>,
+}
+
+/// OpenGL state saved by `Gl::save_state`, restored when dropped.
+pub struct SavedState {
+    gl: Gl,
+    program: Option<Option<glow::Program>>,
+    array_buffer: Option<Option<glow::Buffer>>,
+    vertex_array: Option<Option<glow::VertexArray>>,
+    vertex_array_state: VertexArrayState,
+    active_texture: Option<u32>,
+    textures: [TextureUnit; TRACKED_TEXTURE_UNITS],
+    unpack_alignment: Option<i32>,
+}
+
+impl VertexArrayState {
+    const NEW: VertexArrayState = VertexArrayState {
+        element_array_buffer: Some(None),
+        known_attributes: !0,
+        enabled_attributes: 0,
+    };
+
+    const UNKNOWN: VertexArrayState = VertexArrayState {
+        element_array_buffer: None,
+        known_attributes: 0,
+        enabled_attributes: 0,
+    };
+}
+
+impl TextureUnit {
+    const NEW: TextureUnit = TextureUnit {
+        texture_2d: Some(None),
+        texture_2d_array: Some(None),
+    };
+
+    const UNKNOWN: TextureUnit = TextureUnit {
+        texture_2d: None,
+        texture_2d_array: None,
+    };
+
+    fn binding(&mut self, target: u32) -> Option<&mut Option<Option<glow::Texture>>> {
+        match target {
+            glow::TEXTURE_2D => Some(&mut self.texture_2d),
+            glow::TEXTURE_2D_ARRAY => Some(&mut self.texture_2d_array),
+            _ => None,
+        }
+    }
+}
+
+impl StateCache {
+    /// The cache for a freshly created context, in which all tracked state has its default value.
+    pub(crate) fn new() -> Self {
+        let mut vertex_arrays = HashMap::new();
+        vertex_arrays.insert(None, VertexArrayState::NEW);
+        StateCache {
+            program: Cell::new(Some(None)),
+            array_buffer: Cell::new(Some(None)),
+            vertex_array: Cell::new(Some(None)),
+            vertex_arrays: RefCell::new(vertex_arrays),
+            active_texture: Cell::new(Some(glow::TEXTURE0)),
+            textures: RefCell::new([TextureUnit::NEW; TRACKED_TEXTURE_UNITS]),
+            unpack_alignment: Cell::new(Some(4)),
+        }
+    }
+
+    fn invalidate(&self) {
+        self.program.set(None);
+        self.array_buffer.set(None);
+        self.vertex_array.set(None);
+        self.vertex_arrays.borrow_mut().clear();
+        self.active_texture.set(None);
+        *self.textures.borrow_mut() = [TextureUnit::UNKNOWN; TRACKED_TEXTURE_UNITS];
+        self.unpack_alignment.set(None);
+    }
+
+    /// Runs `f` on the state of the currently bound vertex array, or returns `None` if it is
+    /// unknown which vertex array is bound.
+    fn with_vertex_array<R>(&self, f: impl FnOnce(&mut VertexArrayState) -> R) -> Option<R> {
+        let vao = self.vertex_array.get()?;
+        let mut vertex_arrays = self.vertex_arrays.borrow_mut();
+        Some(f(vertex_arrays
+            .entry(vao)
+            .or_insert(VertexArrayState::UNKNOWN)))
+    }
+}
+
+/// State tracking.
+///
+/// These methods shadow the `HasContext` methods of the same name, so calls made through `Gl`
+/// update the cache and redundant calls are skipped. State changed by other means (e.g. a
+/// library holding its own context handle) must be reported with `invalidate_state`.
+///
+/// The safety requirements of the `unsafe` methods are those of their `HasContext` counterparts.
+#[allow(clippy::missing_safety_doc)]
+impl Gl {
+    pub unsafe fn use_program(&self, program: Option<glow::Program>) {
+        let state = self.state();
+        if state.program.get() == Some(program) {
+            return;
+        }
+        state.program.set(Some(program));
+        HasContext::use_program(&**self, program);
+    }
+
+    pub unsafe fn bind_buffer(&self, target: u32, buffer: Option<glow::Buffer>) {
+        let state = self.state();
+        match target {
+            glow::ARRAY_BUFFER => {
+                if state.array_buffer.get() == Some(buffer) {
+                    return;
+                }
+                state.array_buffer.set(Some(buffer));
+            }
+            glow::ELEMENT_ARRAY_BUFFER => {
+                let redundant = state.with_vertex_array(|vao| {
+                    let redundant = vao.element_array_buffer == Some(buffer);
+                    vao.element_array_buffer = Some(buffer);
+                    redundant
+                });
+                if redundant == Some(true) {
+                    return;
+                }
+            }
+            _ => {}
+        }
+        HasContext::bind_buffer(&**self, target, buffer);
+    }
+
+    pub unsafe fn delete_buffer(&self, buffer: glow::Buffer) {
+        // Deleting a buffer unbinds it from the context and the current vertex array, but not
+        // from other vertex arrays.
+        let state = self.state();
+        if state.array_buffer.get() == Some(Some(buffer)) {
+            state.array_buffer.set(Some(None));
+        }
+        state.with_vertex_array(|vao| {
+            if vao.element_array_buffer == Some(Some(buffer)) {
+                vao.element_array_buffer = Some(None);
+            }
+        });
+        HasContext::delete_buffer(&**self, buffer);
+    }
+
+    pub unsafe fn create_vertex_array(&self) -> Result<glow::VertexArray, String> {
+        let vao = HasContext::create_vertex_array(&**self)?;
+        self.state()
+            .vertex_arrays
+            .borrow_mut()
+            .insert(Some(vao), VertexArrayState::NEW);
+        Ok(vao)
+    }
+
+    pub unsafe fn bind_vertex_array(&self, vertex_array: Option<glow::VertexArray>) {
+        let state = self.state();
+        if state.vertex_array.get() == Some(vertex_array) {
+            return;
+        }
+        state.vertex_array.set(Some(vertex_array));
+        HasContext::bind_vertex_array(&**self, vertex_array);
+    }
+
+    pub unsafe fn delete_vertex_array(&self, vertex_array: glow::VertexArray) {
+        let state = self.state();
+        state.vertex_arrays.borrow_mut().remove(&Some(vertex_array));
+        if state.vertex_array.get() == Some(Some(vertex_array)) {
+            state.vertex_array.set(Some(None));
+        }
+        HasContext::delete_vertex_array(&**self, vertex_array);
+    }
+
+    pub unsafe fn enable_vertex_attrib_array(&self, index: u32) {
+        if self.set_attribute_enabled(index, true) {
+            HasContext::enable_vertex_attrib_array(&**self, index);
+        }
+    }
+
+    pub unsafe fn disable_vertex_attrib_array(&self, index: u32) {
+        if self.set_attribute_enabled(index, false) {
+            HasContext::disable_vertex_attrib_array(&**self, index);
+        }
+    }
+
+    /// Updates the cache, returning whether the call needs to be issued.
+    fn set_attribute_enabled(&self, index: u32, enabled: bool) -> bool {
+        if index >= TRACKED_ATTRIBUTES {
+            return true;
+        }
+        let bit = 1 << index;
+        self.state()
+            .with_vertex_array(|vao| {
+                let redundant = vao.known_attributes & bit != 0
+                    && (vao.enabled_attributes & bit != 0) == enabled;
+                vao.known_attributes |= bit;
+                if enabled {
+                    vao.enabled_attributes |= bit;
+                } else {
+                    vao.enabled_attributes &= !bit;
+                }
+                !redundant
+            })
+            .unwrap_or(true)
+    }
+
+    pub unsafe fn active_texture(&self, unit: u32) {
+        let state = self.state();
+        if state.active_texture.get() == Some(unit) {
+            return;
+        }
+        state.active_texture.set(Some(unit));
+        HasContext::active_texture(&**self, unit);
+    }
+
+    pub unsafe fn bind_texture(&self, target: u32, texture: Option<glow::Texture>) {
+        let state = self.state();
+        let mut textures = state.textures.borrow_mut();
+        match state.active_texture.get() {
+            Some(unit) => {
+                let binding = textures
+                    .get_mut(unit.wrapping_sub(glow::TEXTURE0) as usize)
+                    .and_then(|unit| unit.binding(target));
+                if let Some(binding) = binding {
+                    if *binding == Some(texture) {
+                        return;
+                    }
+                    *binding = Some(texture);
+                }
+            }
+            None => {
+                // We don't know which unit this affects.
+                for unit in textures.iter_mut() {
+                    if let Some(binding) = unit.binding(target) {
+                        *binding = None;
+                    }
+                }
+            }
+        }
+        HasContext::bind_texture(&**self, target, texture);
+    }
+
+    pub unsafe fn delete_texture(&self, texture: glow::Texture) {
+        // Deleting a texture unbinds it from every texture unit.
+        for unit in self.state().textures.borrow_mut().iter_mut() {
+            for binding in [&mut unit.texture_2d, &mut unit.texture_2d_array].iter_mut() {
+                if **binding == Some(Some(texture)) {
+                    **binding = Some(None);
+                }
+            }
+        }
+        HasContext::delete_texture(&**self, texture);
+    }
+
+    pub unsafe fn pixel_store_i32(&self, parameter: u32, value: i32) {
+        if parameter == glow::UNPACK_ALIGNMENT {
+            let state = self.state();
+            if state.unpack_alignment.get() == Some(value) {
+                return;
+            }
+            state.unpack_alignment.set(Some(value));
+        }
+        HasContext::pixel_store_i32(&**self, parameter, value);
+    }
+
+    /// Marks all tracked state as unknown. Call this after changing OpenGL state without going
+    /// through `Gl`.
+    pub fn invalidate_state(&self) {
+        self.state().invalidate();
+    }
+
+    /// Saves the tracked OpenGL state, restoring it when the returned guard is dropped.
+    ///
+    /// The tracked state is:
+    /// - Current shader program
+    /// - `GL_ARRAY_BUFFER` binding
+    /// - Vertex array binding, along with its `GL_ELEMENT_ARRAY_BUFFER` binding and which vertex
+    ///   attribute arrays are enabled
+    /// - Active texture, and `GL_TEXTURE_2D` and `GL_TEXTURE_2D_ARRAY` bindings of the first 16
+    ///   texture units
+    /// - `GL_UNPACK_ALIGNMENT` pixel store parameter
+    ///
+    /// Since redundant calls are skipped, restoring only issues calls for state that changed.
+    pub fn save_state(&self) -> SavedState {
+        let state = self.state();
+        SavedState {
+            gl: self.clone(),
+            program: state.program.get(),
+            array_buffer: state.array_buffer.get(),
+            vertex_array: state.vertex_array.get(),
+            vertex_array_state: state
+                .with_vertex_array(|vao| *vao)
+                .unwrap_or(VertexArrayState::UNKNOWN),
+            active_texture: state.active_texture.get(),
+            textures: *state.textures.borrow(),
+            unpack_alignment: state.unpack_alignment.get(),
+        }
+    }
+}
+
+impl Drop for SavedState {
+    fn drop(&mut self) {
+        let gl = &self.gl;
+        unsafe {
+            if let Some(vao) = self.vertex_array {
+                gl.bind_vertex_array(vao);
+                if let Some(buffer) = self.vertex_array_state.element_array_buffer {
+                    gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, buffer);
+                }
+                for index in 0..TRACKED_ATTRIBUTES {
+                    let bit = 1 << index;
+                    if self.vertex_array_state.known_attributes & bit == 0 {
+                        continue;
+                    }
+                    if self.vertex_array_state.enabled_attributes & bit != 0 {
+                        gl.enable_vertex_attrib_array(index);
+                    } else {
+                        gl.disable_vertex_attrib_array(index);
+                    }
+                }
+            }
+            if let Some(buffer) = self.array_buffer {
+                gl.bind_buffer(glow::ARRAY_BUFFER, buffer);
+            }
+            if let Some(program) = self.program {
+                gl.use_program(program);
+            }
+
+            let current = *gl.state().textures.borrow();
+            for (i, (saved, current)) in self.textures.iter().zip(current.iter()).enumerate() {
+                let unit = glow::TEXTURE0 + i as u32;
+                for &(target, saved, current) in &[
+                    (glow::TEXTURE_2D, saved.texture_2d, current.texture_2d),
+                    (
+                        glow::TEXTURE_2D_ARRAY,
+                        saved.texture_2d_array,
+                        current.texture_2d_array,
+                    ),
+                ] {
+                    if let Some(texture) = saved {
+                        if current != saved {
+                            gl.active_texture(unit);
+                            gl.bind_texture(target, texture);
+                        }
+                    }
+                }
+            }
+            if let Some(unit) = self.active_texture {
+                gl.active_texture(unit);
+            }
+
+            if let Some(alignment) = self.unpack_alignment {
+                gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, alignment);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::prelude::*;
+use crate::Error;
+
+/// Smallest allocation made for a stream buffer, in bytes.
+const MIN_CAPACITY: usize = 64 * 1024;
+
+/// A buffer for data that is rewritten every frame, such as batched vertices.
+///
+/// Writes are appended to the buffer with `glBufferSubData`, so several batches per frame share
+/// one allocation. When the buffer is full it is orphaned (reallocated with `glBufferData`) so
+/// the driver can hand out fresh memory instead of waiting for pending draws, and it grows to
+/// fit if a single write is larger than the buffer.
+///
+/// The buffer is deleted when this is dropped.
+pub struct StreamBuffer {
+    gl: Gl,
+    buffer: glow::Buffer,
+    target: u32,
+    capacity: usize,
+    offset: usize,
+}
+
+impl StreamBuffer {
+    /// Creates an empty stream buffer for the given target, e.g. `glow::ARRAY_BUFFER`.
+    pub fn new(gl: &Gl, target: u32) -> Result<Self, Error> {
+        Ok(StreamBuffer {
+            gl: gl.clone(),
+            buffer: unsafe { gl.create_buffer().map_err(Error::Gl)? },
+            target,
+            capacity: 0,
+            offset: 0,
+        })
+    }
+
+    /// Appends `data` to the buffer, returning the index of its first element, which can be
+    /// passed as `first` to `glDrawArrays`.
+    ///
+    /// Binds the buffer to its target. For `GL_ELEMENT_ARRAY_BUFFER`, this is part of the
+    /// current vertex array, so bind the vertex array that uses this buffer first.
+    pub fn write<T: Copy>(&mut self, data: &[T]) -> usize {
+        let size = std::mem::size_of::<T>().max(1);
+        let bytes = glutil::as_u8_slice(data);
+
+        // Offsets must be a multiple of the element size to be addressable by index.
+        // `usize::div_ceil` needs Rust 1.73.
+        #[allow(clippy::manual_div_ceil)]
+        let mut offset = (self.offset + size - 1) / size * size;
+        unsafe {
+            self.gl.bind_buffer(self.target, Some(self.buffer));
+            if offset + bytes.len() > self.capacity {
+                let capacity = if bytes.len() > self.capacity {
+                    (bytes.len().max(self.capacity * 2)).max(MIN_CAPACITY)
+                } else {
+                    self.capacity
+                };
+                self.gl
+                    .buffer_data_size(self.target, capacity as i32, glow::STREAM_DRAW);
+                self.capacity = capacity;
+                offset = 0;
+            }
+            if !bytes.is_empty() {
+                self.gl
+                    .buffer_sub_data_u8_slice(self.target, offset as i32, bytes);
+            }
+        }
+        self.offset = offset + bytes.len();
+        offset / size
+    }
+
+    /// Sets a debug label on the underlying buffer.
+    pub fn set_label(&self, label: &str) {
+        self.gl.label_buffer(self.buffer, label);
+    }
+
+    pub fn raw(&self) -> glow::Buffer {
+        self.buffer
+    }
+
+    /// The current size of the buffer's allocation, in bytes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl Drop for StreamBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.delete_buffer(self.buffer);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use crate::prelude::*;
+use crate::Error;
+
+/// The in-memory format of a vertex attribute.
+///
+/// The `Float` and `*Norm` formats feed `float`/`vecN` shader inputs; the remaining integer
+/// formats feed `int`/`ivecN` or `uint`/`uvecN` inputs. The parameter is the number of components
+/// (1 to 4).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AttributeFormat {
+    Float(i32),
+    /// Unsigned bytes normalized to `[0, 1]`, e.g. RGBA8 colors.
+    U8Norm(i32),
+    /// Unsigned shorts normalized to `[0, 1]`.
+    U16Norm(i32),
+    /// Signed shorts normalized to `[-1, 1]`.
+    I16Norm(i32),
+    U8(i32),
+    U16(i32),
+    I16(i32),
+    U32(i32),
+    I32(i32),
+}
+
+/// A vertex attribute in a `VertexLayout`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VertexAttribute {
+    /// The name of the shader input this attribute feeds.
+    pub name: String,
+    pub format: AttributeFormat,
+    /// Offset from the start of the vertex, in bytes.
+    pub offset: i32,
+}
+
+/// Describes how the fields of a vertex type are laid out in a vertex buffer.
+///
+/// Attributes are matched to shader inputs by name when creating a `VertexArray`, so the layout
+/// does not depend on attribute locations.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VertexLayout {
+    attributes: Vec<VertexAttribute>,
+    stride: i32,
+    divisor: u32,
+}
+
+/// A vertex type with a known layout.
+///
+/// ```ignore
+/// #[derive(Copy, Clone)]
+/// #[repr(C)]
+/// struct MyVertex {
+///     pos: Point2<f32>,
+///     color: [u8; 4],
+/// }
+///
+/// impl Vertex for MyVertex {
+///     fn layout() -> VertexLayout {
+///         VertexLayout::new()
+///             .attribute("pos", AttributeFormat::Float(2))
+///             .attribute("color", AttributeFormat::U8Norm(4))
+///     }
+/// }
+/// ```
+pub trait Vertex: Copy {
+    fn layout() -> VertexLayout;
+}
+
+/// An owned vertex array object configured from one or more `VertexLayout`s.
+///
+/// The vertex array is deleted when this is dropped.
+pub struct VertexArray {
+    gl: Gl,
+    vao: glow::VertexArray,
+    bindings: Vec<Binding>,
+}
+
+/// The attributes sourced from one buffer, resolved to shader input locations.
+struct Binding {
+    buffer: glow::Buffer,
+    stride: i32,
+    divisor: u32,
+    attributes: Vec<(u32, AttributeFormat, i32)>,
+}
+
+impl AttributeFormat {
+    pub fn components(self) -> i32 {
+        match self {
+            AttributeFormat::Float(n)
+            | AttributeFormat::U8Norm(n)
+            | AttributeFormat::U16Norm(n)
+            | AttributeFormat::I16Norm(n)
+            | AttributeFormat::U8(n)
+            | AttributeFormat::U16(n)
+            | AttributeFormat::I16(n)
+            | AttributeFormat::U32(n)
+            | AttributeFormat::I32(n) => n,
+        }
+    }
+
+    /// The size of an attribute of this format, in bytes.
+    pub fn size(self) -> i32 {
+        let component_size = match self {
+            AttributeFormat::U8Norm(_) | AttributeFormat::U8(_) => 1,
+            AttributeFormat::U16Norm(_)
+            | AttributeFormat::I16Norm(_)
+            | AttributeFormat::U16(_)
+            | AttributeFormat::I16(_) => 2,
+            AttributeFormat::Float(_) | AttributeFormat::U32(_) | AttributeFormat::I32(_) => 4,
+        };
+        component_size * self.components()
+    }
+
+    fn data_type(self) -> u32 {
+        match self {
+            AttributeFormat::Float(_) => glow::FLOAT,
+            AttributeFormat::U8Norm(_) | AttributeFormat::U8(_) => glow::UNSIGNED_BYTE,
+            AttributeFormat::U16Norm(_) | AttributeFormat::U16(_) => glow::UNSIGNED_SHORT,
+            AttributeFormat::I16Norm(_) | AttributeFormat::I16(_) => glow::SHORT,
+            AttributeFormat::U32(_) => glow::UNSIGNED_INT,
+            AttributeFormat::I32(_) => glow::INT,
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(
+            self,
+            AttributeFormat::Float(_)
+                | AttributeFormat::U8Norm(_)
+                | AttributeFormat::U16Norm(_)
+                | AttributeFormat::I16Norm(_)
+        )
+    }
+
+    fn is_signed(self) -> bool {
+        matches!(self, AttributeFormat::I16(_) | AttributeFormat::I32(_))
+    }
+}
+
+impl VertexLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an attribute directly after the previous one. The stride grows to fit.
+    pub fn attribute(self, name: impl Into<String>, format: AttributeFormat) -> Self {
+        let offset = self
+            .attributes
+            .last()
+            .map_or(0, |a| a.offset + a.format.size());
+        self.attribute_at(name, format, offset)
+    }
+
+    /// Adds an attribute at the given byte offset. The stride grows to fit.
+    pub fn attribute_at(
+        mut self,
+        name: impl Into<String>,
+        format: AttributeFormat,
+        offset: i32,
+    ) -> Self {
+        self.stride = self.stride.max(offset + format.size());
+        self.attributes.push(VertexAttribute {
+            name: name.into(),
+            format,
+            offset,
+        });
+        self
+    }
+
+    /// Sets the distance between vertices, in bytes, e.g. to account for trailing padding.
+    pub fn with_stride(mut self, stride: i32) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    pub fn attributes(&self) -> &[VertexAttribute] {
+        &self.attributes
+    }
+
+    /// The distance between vertices, in bytes.
+    pub fn stride(&self) -> i32 {
+        self.stride
+    }
+
+    /// Makes the attributes advance once per instance rather than once per vertex when drawing
+    /// with `glDrawArraysInstanced` and friends.
+    pub fn per_instance(mut self) -> Self {
+        self.divisor = 1;
+        self
+    }
+
+    /// The number of instances drawn before the attributes advance, or 0 if they advance per
+    /// vertex.
+    pub fn divisor(&self) -> u32 {
+        self.divisor
+    }
+
+    /// Checks that this layout describes vertices of type `V`.
+    pub fn check_size<V>(&self) -> Result<(), Error> {
+        if self.stride as usize != std::mem::size_of::<V>() {
+            return Err(Error::InvalidArgument(format!(
+                "Vertex layout stride of {} does not match size of {} bytes of `{}`",
+                self.stride,
+                std::mem::size_of::<V>(),
+                std::any::type_name::<V>()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl VertexArray {
+    /// Creates a vertex array sourcing vertices of type `V` from `buffer`, and indices from
+    /// `index_buffer` if one is given.
+    ///
+    /// See `with_layouts`.
+    pub fn new<V: Vertex>(
+        gl: &Gl,
+        shader: &glutil::ShaderProgram,
+        buffer: glow::Buffer,
+        index_buffer: Option<glow::Buffer>,
+    ) -> Result<Self, Error> {
+        let layout = V::layout();
+        layout.check_size::<V>()?;
+        Self::with_layouts(gl, shader, &[(buffer, &layout)], index_buffer)
+    }
+
+    /// Creates a vertex array sourcing attributes from each buffer according to its layout.
+    ///
+    /// Each attribute is bound to the location of the shader input with the same name. It is an
+    /// error for an active shader input to be missing from the layouts, or for an attribute to
+    /// feed a shader input of the wrong kind (float vs. signed or unsigned integer). Attributes
+    /// with no matching active input are ignored, since the compiler may remove unused inputs.
+    pub fn with_layouts(
+        gl: &Gl,
+        shader: &glutil::ShaderProgram,
+        buffers: &[(glow::Buffer, &VertexLayout)],
+        index_buffer: Option<glow::Buffer>,
+    ) -> Result<Self, Error> {
+        for (name, info) in shader.attributes() {
+            // Built-in inputs such as `gl_VertexID` are reported on some drivers.
+            if name.starts_with("gl_") {
+                continue;
+            }
+            let attribute = buffers
+                .iter()
+                .flat_map(|(_, layout)| layout.attributes.iter())
+                .find(|a| a.name == name)
+                .ok_or_else(|| {
+                    Error::Shader(format!("Vertex layout is missing shader input `{}`.", name))
+                })?;
+            check_compatible(attribute, info)?;
+        }
+
+        let bindings = buffers
+            .iter()
+            .map(|&(buffer, layout)| Binding {
+                buffer,
+                stride: layout.stride,
+                divisor: layout.divisor,
+                attributes: layout
+                    .attributes
+                    .iter()
+                    .filter_map(|a| {
+                        let info = shader.attribute_info(&a.name)?;
+                        Some((info.location, a.format, a.offset))
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let _saved = gl.save_state();
+        unsafe {
+            let vao = gl.create_vertex_array().map_err(Error::Gl)?;
+            let array = VertexArray {
+                gl: gl.clone(),
+                vao,
+                bindings,
+            };
+            gl.bind_vertex_array(Some(vao));
+            for binding in &array.bindings {
+                binding.specify(gl, 0);
+                for &(location, _, _) in &binding.attributes {
+                    gl.vertex_attrib_divisor(location, binding.divisor);
+                    gl.enable_vertex_attrib_array(location);
+                }
+            }
+            if let Some(index_buffer) = index_buffer {
+                gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(index_buffer));
+            }
+            Ok(array)
+        }
+    }
+
+    /// Makes the attributes sourced from the `binding`th buffer passed to `with_layouts` start at
+    /// the element with index `first`, such as an index returned by `StreamBuffer::write`.
+    ///
+    /// This is needed for per-instance attributes, since GLES 3.0 has no way to offset the
+    /// instance index of a draw. Binds this vertex array.
+    pub fn set_first(&self, binding: usize, first: usize) {
+        let binding = &self.bindings[binding];
+        self.bind();
+        unsafe {
+            binding.specify(&self.gl, first as i32 * binding.stride);
+        }
+    }
+
+    /// Makes this the current vertex array.
+    pub fn bind(&self) {
+        unsafe {
+            self.gl.bind_vertex_array(Some(self.vao));
+        }
+    }
+
+    pub fn raw(&self) -> glow::VertexArray {
+        self.vao
+    }
+}
+
+impl Binding {
+    /// Points the attributes at the buffer, starting `base` bytes in. Requires the vertex array
+    /// to be bound.
+    unsafe fn specify(&self, gl: &Gl, base: i32) {
+        gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.buffer));
+        for &(location, format, offset) in &self.attributes {
+            if format.is_integer() {
+                gl.vertex_attrib_pointer_i32(
+                    location,
+                    format.components(),
+                    format.data_type(),
+                    self.stride,
+                    base + offset,
+                );
+            } else {
+                gl.vertex_attrib_pointer_f32(
+                    location,
+                    format.components(),
+                    format.data_type(),
+                    !matches!(format, AttributeFormat::Float(_)),
+                    self.stride,
+                    base + offset,
+                );
+            }
+        }
+    }
+}
+
+impl Drop for VertexArray {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.delete_vertex_array(self.vao);
+        }
+    }
+}
+
+fn check_compatible(
+    attribute: &VertexAttribute,
+    info: &glutil::AttributeInfo,
+) -> Result<(), Error> {
+    let (integer, signed) = match info.gl_type {
+        glow::FLOAT | glow::FLOAT_VEC2 | glow::FLOAT_VEC3 | glow::FLOAT_VEC4 => (false, false),
+        glow::INT | glow::INT_VEC2 | glow::INT_VEC3 | glow::INT_VEC4 => (true, true),
+        glow::UNSIGNED_INT
+        | glow::UNSIGNED_INT_VEC2
+        | glow::UNSIGNED_INT_VEC3
+        | glow::UNSIGNED_INT_VEC4 => (true, false),
+        other => {
+            return Err(Error::Shader(format!(
+                "Shader input `{}` has unsupported type {}.",
+                attribute.name,
+                glutil::type_name(other)
+            )))
+        }
+    };
+    let format = attribute.format;
+    if format.is_integer() != integer || (integer && format.is_signed() != signed) {
+        return Err(Error::Shader(format!(
+            "Vertex attribute `{}` of format {:?} cannot feed shader input of type {}.",
+            attribute.name,
+            format,
+            glutil::type_name(info.gl_type)
+        )));
+    }
+    Ok(())
+}
@@ -4,12 +4,19 @@ pub extern crate futures;
 pub extern crate rusttype;
 pub extern crate winit;
 
+pub mod animation;
+pub mod atlas;
+pub mod dynamic_atlas;
+mod error;
 mod gameloop;
 pub mod glutil;
+pub mod material;
+pub mod profiler;
 pub mod shape;
 pub mod sound;
 pub mod sprite;
 pub mod text;
+pub mod texture;
 pub mod tilemap;
 
 #[cfg_attr(target_arch = "wasm32", path = "web/mod.rs")]
@@ -17,6 +24,7 @@ pub mod tilemap;
 mod backend;
 
 pub use backend::util::*;
+pub use error::*;
 pub use gameloop::*;
 
 pub mod prelude {
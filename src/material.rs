@@ -0,0 +1,166 @@
+use std::rc::Rc;
+
+use crate::glutil::{ShaderProgram, UniformValue};
+use crate::prelude::*;
+use crate::Error;
+
+/// How drawn colors are combined with the colors already in the framebuffer.
+///
+/// Renderers apply their blend mode when rendering, so blending doesn't need to be configured
+/// globally.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BlendMode {
+    /// Blends straight (non-premultiplied) colors by their alpha.
+    Alpha,
+    /// Blends colors that are already multiplied by their alpha, such as those of atlases
+    /// generated with premultiplied alpha. Avoids dark or light fringes around filtered edges.
+    /// Tint colors should be premultiplied as well.
+    PremultipliedAlpha,
+    /// Adds colors weighted by their alpha, for glows and light effects.
+    Additive,
+    /// Multiplies the framebuffer by the drawn colors, for shadows and tinting. Alpha is
+    /// ignored, so areas that should have no effect must be white.
+    Multiply,
+    /// Brightens the framebuffer by the drawn colors, the inverse of `Multiply`. Alpha is
+    /// ignored, so areas that should have no effect must be black.
+    Screen,
+    /// Overwrites the framebuffer without blending.
+    Opaque,
+}
+
+/// A shader together with values for its uniforms and a blend mode.
+///
+/// Materials are cheap to clone. Setting a uniform on a clone does not affect the original.
+///
+/// Uniform values live in the shader program, and binding a material only sets the uniforms it
+/// has values for. Others keep the value set by the last material bound with the same shader, so
+/// materials sharing a shader, including clones, should all set the same uniforms.
+///
+/// ```ignore
+/// let mut flash = batch.material(include_str!("flash.frag.glsl"))?;
+/// flash.set_uniform("flash_color", [1.0, 1.0, 1.0, 0.8])?;
+/// batch.set_material(&flash)?;
+/// batch.draw(&sprites.enemy, pos, [255; 4]);
+/// ```
+#[derive(Clone)]
+pub struct Material {
+    shader: Rc<ShaderProgram>,
+    uniforms: Vec<(String, UniformSetter)>,
+    /// The blend mode to draw with, or `None` to draw with whatever blending is configured.
+    pub blend: Option<BlendMode>,
+}
+
+/// Sets a uniform to a stored value.
+type UniformSetter = Rc<dyn Fn(&ShaderProgram)>;
+
+impl BlendMode {
+    /// Enables or disables `GL_BLEND` and sets the blend equation and functions for this mode.
+    pub fn apply(self, gl: &Gl) {
+        unsafe {
+            if self == BlendMode::Opaque {
+                gl.disable(glow::BLEND);
+                return;
+            }
+            gl.enable(glow::BLEND);
+            gl.blend_equation(glow::FUNC_ADD);
+            match self {
+                BlendMode::Alpha => gl.blend_func_separate(
+                    glow::SRC_ALPHA,
+                    glow::ONE_MINUS_SRC_ALPHA,
+                    glow::ONE,
+                    glow::ONE_MINUS_SRC_ALPHA,
+                ),
+                BlendMode::PremultipliedAlpha => {
+                    gl.blend_func(glow::ONE, glow::ONE_MINUS_SRC_ALPHA)
+                }
+                BlendMode::Additive => gl.blend_func(glow::SRC_ALPHA, glow::ONE),
+                // Destination alpha is kept as-is by both.
+                BlendMode::Multiply => {
+                    gl.blend_func_separate(glow::DST_COLOR, glow::ZERO, glow::ZERO, glow::ONE)
+                }
+                BlendMode::Screen => gl.blend_func_separate(
+                    glow::ONE,
+                    glow::ONE_MINUS_SRC_COLOR,
+                    glow::ZERO,
+                    glow::ONE,
+                ),
+                BlendMode::Opaque => unreachable!(),
+            }
+        }
+    }
+}
+
+impl Material {
+    /// Creates a material with no uniform values and no blend mode.
+    pub fn new(shader: ShaderProgram) -> Self {
+        Self::from_shared(Rc::new(shader))
+    }
+
+    /// Creates a material using a shader shared with other materials.
+    pub fn from_shared(shader: Rc<ShaderProgram>) -> Self {
+        Material {
+            shader,
+            uniforms: vec![],
+            blend: None,
+        }
+    }
+
+    pub fn shader(&self) -> &ShaderProgram {
+        &self.shader
+    }
+
+    pub fn shared_shader(&self) -> &Rc<ShaderProgram> {
+        &self.shader
+    }
+
+    /// Sets the value the named uniform takes when drawing with this material, replacing any
+    /// previous value.
+    pub fn set_uniform<T>(&mut self, name: &str, value: T) -> Result<(), Error>
+    where
+        T: UniformValue + Clone + 'static,
+    {
+        let uniform = self.shader.uniform::<T>(name)?;
+        let apply: UniformSetter = Rc::new(move |shader| shader.set(&uniform, value.clone()));
+        match self.uniforms.iter_mut().find(|(n, _)| n == name) {
+            Some((_, old)) => *old = apply,
+            None => self.uniforms.push((name.to_owned(), apply)),
+        }
+        Ok(())
+    }
+
+    /// Builder-style version of `set_uniform`.
+    pub fn with_uniform<T>(mut self, name: &str, value: T) -> Result<Self, Error>
+    where
+        T: UniformValue + Clone + 'static,
+    {
+        self.set_uniform(name, value)?;
+        Ok(self)
+    }
+
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = Some(blend);
+        self
+    }
+
+    /// Makes the shader current and sets the uniform values of this material. Uniforms this
+    /// material has no values for are left as they are.
+    pub fn bind(&self) {
+        self.shader.bind();
+        for (_, apply) in &self.uniforms {
+            apply(&self.shader);
+        }
+    }
+
+    /// Whether drawing with `other` is the same as drawing with this material, i.e. whether it
+    /// is a clone that has not been modified since.
+    pub fn same_as(&self, other: &Material) -> bool {
+        Rc::ptr_eq(&self.shader, &other.shader)
+            && self.blend == other.blend
+            && self.uniforms.len() == other.uniforms.len()
+            && self
+                .uniforms
+                .iter()
+                .zip(&other.uniforms)
+                .all(|((_, a), (_, b))| Rc::ptr_eq(a, b))
+    }
+}
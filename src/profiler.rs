@@ -0,0 +1,199 @@
+use std::collections::{HashMap, VecDeque};
+
+use instant::{Duration, Instant};
+
+use crate::backend::glutil as backend;
+use crate::prelude::*;
+
+/// Results older than this many frames are discarded rather than waited on.
+const MAX_FRAMES_IN_FLIGHT: usize = 8;
+
+/// Measures the CPU and GPU time spent in named scopes, such as each renderer's `render` call.
+///
+/// GPU time is measured with `GL_TIME_ELAPSED` queries where timer queries are supported (always
+/// on desktop, and with `EXT_disjoint_timer_query_webgl2` on the web). Query results are
+/// collected a few frames later when they become available, so reading them never stalls the
+/// pipeline. Timings are averaged over the last `samples` measurements of each scope.
+///
+/// Timer queries cannot be nested, so only CPU time is measured for a scope entered from within
+/// another scope.
+pub struct GpuProfiler {
+    gl: Gl,
+    gpu_supported: bool,
+    scopes: Vec<ScopeStats>,
+    indices: HashMap<String, usize>,
+    frame: Vec<(usize, backend::TimerQuery)>,
+    in_flight: VecDeque<Vec<(usize, backend::TimerQuery)>>,
+    free_queries: Vec<backend::TimerQuery>,
+    depth: usize,
+
+    /// The number of measurements each average is taken over.
+    pub samples: usize,
+}
+
+struct ScopeStats {
+    name: String,
+    cpu: VecDeque<Duration>,
+    gpu: VecDeque<Duration>,
+}
+
+/// Average timings of a profiled scope.
+#[derive(Clone, Debug)]
+pub struct ScopeTimings {
+    pub name: String,
+    pub cpu: Duration,
+    /// `None` if timer queries are unsupported or no results have arrived yet.
+    pub gpu: Option<Duration>,
+}
+
+impl GpuProfiler {
+    pub fn new(gl: &Gl) -> Self {
+        GpuProfiler {
+            gl: gl.clone(),
+            gpu_supported: backend::timer_queries_supported(gl),
+            scopes: vec![],
+            indices: HashMap::new(),
+            frame: vec![],
+            in_flight: VecDeque::new(),
+            free_queries: vec![],
+            depth: 0,
+            samples: 60,
+        }
+    }
+
+    /// Whether GPU timings are available on this context.
+    pub fn gpu_supported(&self) -> bool {
+        self.gpu_supported
+    }
+
+    /// Runs `f`, measuring the time it takes under the given scope name.
+    pub fn scope<R>(&mut self, name: &str, f: impl FnOnce() -> R) -> R {
+        let index = match self.indices.get(name) {
+            Some(&index) => index,
+            None => {
+                self.scopes.push(ScopeStats {
+                    name: name.to_owned(),
+                    cpu: VecDeque::new(),
+                    gpu: VecDeque::new(),
+                });
+                self.indices.insert(name.to_owned(), self.scopes.len() - 1);
+                self.scopes.len() - 1
+            }
+        };
+
+        let query = if self.depth == 0 && self.gpu_supported {
+            self.take_query()
+        } else {
+            None
+        };
+
+        self.depth += 1;
+        let start = Instant::now();
+        if let Some(query) = &query {
+            query.begin();
+        }
+        let result = f();
+        if let Some(query) = query {
+            query.end();
+            self.frame.push((index, query));
+        }
+        let elapsed = start.elapsed();
+        self.depth -= 1;
+
+        let samples = self.samples;
+        push_sample(&mut self.scopes[index].cpu, elapsed, samples);
+        result
+    }
+
+    /// Marks the end of a frame and collects any GPU timings that have become available.
+    pub fn end_frame(&mut self) {
+        if !self.gpu_supported {
+            return;
+        }
+
+        self.in_flight.push_back(std::mem::take(&mut self.frame));
+
+        if backend::gpu_disjoint(&self.gl) {
+            // Every in-flight result is unreliable.
+            while let Some(frame) = self.in_flight.pop_front() {
+                self.recycle(frame);
+            }
+            return;
+        }
+
+        while let Some(frame) = self.in_flight.front() {
+            let results: Option<Vec<_>> = frame.iter().map(|(_, q)| q.result()).collect();
+            let results = match results {
+                Some(results) => results,
+                None if self.in_flight.len() > MAX_FRAMES_IN_FLIGHT => {
+                    let frame = self.in_flight.pop_front().unwrap();
+                    self.recycle(frame);
+                    continue;
+                }
+                None => break,
+            };
+            let frame = self.in_flight.pop_front().unwrap();
+            for (&(index, _), ns) in frame.iter().zip(results) {
+                let samples = self.samples;
+                push_sample(
+                    &mut self.scopes[index].gpu,
+                    Duration::from_nanos(ns),
+                    samples,
+                );
+            }
+            self.recycle(frame);
+        }
+    }
+
+    /// Returns the average timings of each scope, in the order the scopes were first entered.
+    pub fn timings(&self) -> Vec<ScopeTimings> {
+        self.scopes
+            .iter()
+            .map(|scope| ScopeTimings {
+                name: scope.name.clone(),
+                cpu: average(&scope.cpu).unwrap_or_default(),
+                gpu: average(&scope.gpu),
+            })
+            .collect()
+    }
+
+    fn take_query(&mut self) -> Option<backend::TimerQuery> {
+        match self.free_queries.pop() {
+            Some(query) => Some(query),
+            None => backend::TimerQuery::new(&self.gl).ok(),
+        }
+    }
+
+    fn recycle(&mut self, frame: Vec<(usize, backend::TimerQuery)>) {
+        self.free_queries
+            .extend(frame.into_iter().map(|(_, query)| query));
+    }
+}
+
+impl std::fmt::Display for ScopeTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: cpu {:.3} ms", self.name, as_millis(self.cpu))?;
+        match self.gpu {
+            Some(gpu) => write!(f, ", gpu {:.3} ms", as_millis(gpu)),
+            None => write!(f, ", gpu n/a"),
+        }
+    }
+}
+
+fn push_sample(samples: &mut VecDeque<Duration>, sample: Duration, max: usize) {
+    samples.push_back(sample);
+    while samples.len() > max.max(1) {
+        samples.pop_front();
+    }
+}
+
+fn average(samples: &VecDeque<Duration>) -> Option<Duration> {
+    if samples.is_empty() {
+        return None;
+    }
+    Some(samples.iter().sum::<Duration>() / samples.len() as u32)
+}
+
+fn as_millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
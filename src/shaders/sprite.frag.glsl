@@ -5,11 +5,34 @@ precision lowp sampler2DArray;
 
 in vec3 texcoord;
 in vec4 col;
+flat in uint tex_slot;
 
-uniform sampler2DArray sprites;
+uniform sampler2DArray sprites[4];
+uniform sampler2D images[4];
+uniform float alpha_cutoff;
 
 out vec4 color;
 
+vec4 sample_sprite() {
+    // Sampler arrays can only be indexed with constants in GLSL ES 3.00. Gradients are taken
+    // outside the branches since implicit derivatives are undefined in non-uniform control flow.
+    vec2 dx = dFdx(texcoord.xy);
+    vec2 dy = dFdy(texcoord.xy);
+    switch (tex_slot) {
+        case 0u: return textureGrad(sprites[0], texcoord, dx, dy);
+        case 1u: return textureGrad(sprites[1], texcoord, dx, dy);
+        case 2u: return textureGrad(sprites[2], texcoord, dx, dy);
+        case 3u: return textureGrad(sprites[3], texcoord, dx, dy);
+        case 4u: return textureGrad(images[0], texcoord.xy, dx, dy);
+        case 5u: return textureGrad(images[1], texcoord.xy, dx, dy);
+        case 6u: return textureGrad(images[2], texcoord.xy, dx, dy);
+        default: return textureGrad(images[3], texcoord.xy, dx, dy);
+    }
+}
+
 void main() {
-    color = texture(sprites, texcoord) * col;
+    color = sample_sprite() * col;
+    if (color.a < alpha_cutoff) {
+        discard;
+    }
 }
\ No newline at end of file
@@ -1,14 +1,19 @@
 layout(location = 0) in vec2 pos;
 layout(location = 1) in vec3 tex;
 layout(location = 2) in vec4 color;
+layout(location = 3) in float depth;
+layout(location = 4) in uint slot;
 
 uniform mat4 proj;
 
 out vec3 texcoord;
 out vec4 col;
+flat out uint tex_slot;
 
 void main() {
     gl_Position = proj * vec4(pos, 0.0, 1.0);
+    gl_Position.z += depth * gl_Position.w;
     texcoord = tex;
     col = color;
+    tex_slot = slot;
 }
\ No newline at end of file
@@ -0,0 +1,39 @@
+layout(location = 0) in vec2 axis_x;
+layout(location = 1) in vec2 axis_y;
+layout(location = 2) in vec2 origin;
+layout(location = 3) in vec4 uv;
+layout(location = 4) in float layer;
+layout(location = 5) in vec4 color_bl;
+layout(location = 6) in float rotated;
+layout(location = 7) in float depth;
+layout(location = 8) in uint slot;
+layout(location = 9) in vec4 color_tl;
+layout(location = 10) in vec4 color_br;
+layout(location = 11) in vec4 color_tr;
+
+uniform mat4 proj;
+
+out vec3 texcoord;
+out vec4 col;
+flat out uint tex_slot;
+
+// Two triangles: bottom-left, top-left, bottom-right, top-left, bottom-right, top-right.
+const vec2 CORNERS[6] = vec2[6](
+    vec2(0.0, 0.0), vec2(0.0, 1.0), vec2(1.0, 0.0),
+    vec2(0.0, 1.0), vec2(1.0, 0.0), vec2(1.0, 1.0)
+);
+const int CORNER_INDICES[6] = int[6](0, 1, 2, 1, 2, 3);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexID];
+    vec2 pos = origin + axis_x * (corner.x - 0.5) + axis_y * (corner.y - 0.5);
+    gl_Position = proj * vec4(pos, 0.0, 1.0);
+    gl_Position.z += depth * gl_Position.w;
+
+    // Texture coordinates have y pointing down; rotated sprites are stored turned 90 degrees.
+    vec2 t = rotated > 0.5 ? corner.yx : vec2(corner.x, 1.0 - corner.y);
+    texcoord = vec3(uv.xy + t * uv.zw, layer);
+    vec4 colors[4] = vec4[4](color_bl, color_tl, color_br, color_tr);
+    col = colors[CORNER_INDICES[gl_VertexID]];
+    tex_slot = slot;
+}
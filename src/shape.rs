@@ -1,38 +1,55 @@
+use crate::material::BlendMode;
 use crate::prelude::*;
+use crate::Error;
 
 pub struct ShapeRenderer {
     pub pixels_per_unit: f32,
     gl: Gl,
     vertices: Vec<ShapeVertex>,
     indices: Vec<u32>,
-    vbo: glow::Buffer,
-    ibo: glow::Buffer,
-    shader: glow::Program,
-    proj_loc: glow::UniformLocation,
+    blend: BlendMode,
+    /// The index in `indices` at which each blend mode starts being used.
+    blend_changes: Vec<(usize, BlendMode)>,
+    vbo: glutil::StreamBuffer,
+    ibo: glutil::StreamBuffer,
+    vao: glutil::VertexArray,
+    shader: glutil::ShaderProgram,
+    proj: glutil::Uniform<Transform3D<f32>>,
 }
 
 impl ShapeRenderer {
-    pub fn new(gl: &Gl, shader: glow::Program) -> Result<Self, String> {
-        let (vbo, ibo, proj_loc);
-        unsafe {
-            vbo = gl.create_buffer()?;
-            ibo = gl.create_buffer()?;
-            proj_loc = glutil::get_uniform_location(gl, shader, "proj")?;
-        }
+    pub fn new(gl: &Gl, shader: glutil::ShaderProgram) -> Result<Self, Error> {
+        let proj = shader.uniform("proj")?;
+        let vbo = glutil::StreamBuffer::new(gl, glow::ARRAY_BUFFER)?;
+        let ibo = glutil::StreamBuffer::new(gl, glow::ELEMENT_ARRAY_BUFFER)?;
+        vbo.set_label("ShapeRenderer vertices");
+        ibo.set_label("ShapeRenderer indices");
+        let vao = glutil::VertexArray::new::<ShapeVertex>(gl, &shader, vbo.raw(), Some(ibo.raw()))?;
         Ok(ShapeRenderer {
             pixels_per_unit: 1.0,
             gl: gl.clone(),
             vertices: vec![],
             indices: vec![],
+            blend: BlendMode::Alpha,
+            blend_changes: vec![],
             vbo,
             ibo,
+            vao,
             shader,
-            proj_loc,
+            proj,
         })
     }
 
+    /// Sets the blend mode used for the following shapes. Defaults to `BlendMode::Alpha`.
+    pub fn set_blend_mode(&mut self, blend: BlendMode) {
+        self.blend = blend;
+    }
+
     pub fn convex_polygon(&mut self, points: &[Point2<f32>], color: [u8; 4]) {
         assert!(points.len() >= 3);
+        if self.blend_changes.last().map(|&(_, b)| b) != Some(self.blend) {
+            self.blend_changes.push((self.indices.len(), self.blend));
+        }
         let zero_index = self.vertices.len() as u32;
         self.vertices
             .extend(points.iter().map(|&pos| ShapeVertex { pos, color }));
@@ -67,74 +84,68 @@ impl ShapeRenderer {
 
     /// Actually draws the queued shapes.
     ///
-    /// Touches the following Open_GL state:
-    /// - `GL_ARRAY_BUFFER` binding
-    /// - `GL_ELEMENT_ARRAY_BUFFER` binding
-    /// - Current shader program
-    /// - Vertex attribute arrays for indices 0, 1
+    /// Leaves the state tracked by `Gl::save_state` unchanged. Blending is left as configured
+    /// for the last shape.
     pub fn render(&mut self, camera: Transform3D<f32>) {
+        let _saved = self.gl.save_state();
         unsafe {
-            self.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vbo));
-            self.gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                glutil::as_u8_slice(&self.vertices),
-                glow::STREAM_DRAW,
-            );
-            self.gl
-                .bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(self.ibo));
-            self.gl.buffer_data_u8_slice(
-                glow::ELEMENT_ARRAY_BUFFER,
-                glutil::as_u8_slice(&self.indices),
-                glow::STREAM_DRAW,
-            );
-
-            self.gl
-                .vertex_attrib_pointer_f32(0, 2, glow::FLOAT, false, 12, 0);
-            self.gl
-                .vertex_attrib_pointer_f32(1, 4, glow::UNSIGNED_BYTE, true, 12, 8);
-            self.gl.enable_vertex_attrib_array(0);
-            self.gl.enable_vertex_attrib_array(1);
+            // The index buffer binding is part of the vertex array.
+            self.vao.bind();
 
-            self.gl.use_program(Some(self.shader));
-            let mat = camera.to_array();
-            self.gl
-                .uniform_matrix_4_f32_slice(Some(&self.proj_loc), false, &mat);
+            // There is no base vertex parameter in OpenGL ES 3.0, so offset the indices instead.
+            let base_vertex = self.vbo.write(&self.vertices) as u32;
+            if base_vertex != 0 {
+                for index in &mut self.indices {
+                    *index += base_vertex;
+                }
+            }
+            let first_index = self.ibo.write(&self.indices);
 
-            self.gl.draw_elements(
-                glow::TRIANGLES,
-                self.indices.len() as i32,
-                glow::UNSIGNED_INT,
-                0,
-            );
+            self.shader.set(&self.proj, camera);
 
-            self.gl.disable_vertex_attrib_array(0);
-            self.gl.disable_vertex_attrib_array(1);
+            for (i, &(start, blend)) in self.blend_changes.iter().enumerate() {
+                let end = self
+                    .blend_changes
+                    .get(i + 1)
+                    .map_or(self.indices.len(), |&(end, _)| end);
+                blend.apply(&self.gl);
+                self.gl.draw_elements(
+                    glow::TRIANGLES,
+                    (end - start) as i32,
+                    glow::UNSIGNED_INT,
+                    ((first_index + start) * std::mem::size_of::<u32>()) as i32,
+                );
+            }
         }
+        self.gl.check_errors("ShapeRenderer::render");
         self.indices.clear();
         self.vertices.clear();
+        self.blend_changes.clear();
     }
 }
 
-impl Drop for ShapeRenderer {
-    fn drop(&mut self) {
-        unsafe {
-            self.gl.delete_buffer(self.vbo);
-            self.gl.delete_buffer(self.ibo);
-        }
-    }
-}
-
+#[derive(Copy, Clone)]
 #[repr(C)]
 struct ShapeVertex {
     pos: Point2<f32>,
     color: [u8; 4],
 }
 
-pub fn shape_shader(gl: &Gl) -> glow::Program {
-    glutil::compile_shader_program(
+impl glutil::Vertex for ShapeVertex {
+    fn layout() -> glutil::VertexLayout {
+        use glutil::AttributeFormat::*;
+        glutil::VertexLayout::new()
+            .attribute("pos", Float(2))
+            .attribute("color", U8Norm(4))
+    }
+}
+
+pub fn shape_shader(gl: &Gl) -> Result<glutil::ShaderProgram, Error> {
+    let shader = glutil::ShaderProgram::from_source(
         gl,
         include_str!("shaders/shape.vert.glsl"),
         include_str!("shaders/shape.frag.glsl"),
-    )
-    .unwrap()
+    )?;
+    shader.set_label("shape shader");
+    Ok(shader)
 }
@@ -1,7 +1,7 @@
 use futures::channel::mpsc::{unbounded, UnboundedSender};
 
 use crate::backend::sound as backend;
-use crate::LocalExecutor;
+use crate::{Error, LocalExecutor};
 
 pub struct SoundService {
     send: UnboundedSender<SoundCommand>,
@@ -44,7 +44,7 @@ impl SoundService {
 }
 
 impl Sound {
-    pub async fn load(source: &str) -> Result<Self, String> {
+    pub async fn load(source: &str) -> Result<Self, Error> {
         backend::load(source).await.map(|sound| Sound { sound })
     }
 }
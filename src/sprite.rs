@@ -1,13 +1,116 @@
+use std::rc::{Rc, Weak};
+
+use euclid::{Angle, SideOffsets2D, UnknownUnit};
+
+use crate::material::{BlendMode, Material};
 use crate::prelude::*;
+use crate::texture::{Texture, TextureArray};
+use crate::Error;
 
 pub struct SpriteBatch {
     pub pixels_per_unit: f32,
+    /// The order sprites are drawn in. Defaults to `SortMode::Submission`.
+    pub sort: SortMode,
+    /// Draws sprites front to back using the depth buffer instead of blending them back to front,
+    /// which avoids overdraw for opaque sprites. Fragments with alpha below `alpha_cutoff` are
+    /// discarded so transparent parts of sprites don't hide what is behind them.
+    ///
+    /// The depth buffer should be cleared before rendering. Defaults to `false`.
+    pub depth_test: bool,
+    /// Alpha below which fragments are discarded when `depth_test` is set. Defaults to 0.5.
+    pub alpha_cutoff: f32,
     gl: Gl,
-    shader: glow::Program,
-    proj_loc: glow::UniformLocation,
-    tex: glow::Texture,
-    vbo: glow::Buffer,
-    buffer: Vec<SpriteVertex>,
+    material: Material,
+    /// Index into `shaders` of the shader of `material`.
+    shader: usize,
+    /// The materials used by the queued sprites, with the indices of their shaders.
+    materials: Vec<(Material, usize)>,
+    shaders: Vec<ShaderState>,
+    texture: SpriteTexture,
+    /// The textures used by the queued sprites.
+    textures: Vec<SpriteTexture>,
+    vbo: glutil::StreamBuffer,
+    buffer: Buffer,
+    keys: Vec<SortKey>,
+    order: Vec<usize>,
+}
+
+/// A texture that `SpriteBatch` can draw sprites from.
+#[derive(Clone)]
+pub enum SpriteTexture {
+    /// A texture array, such as a generated sprite atlas. `Sprite::layer` selects the layer.
+    Array(TextureArray),
+    /// A 2D texture, such as a render target or an image loaded at runtime. `Sprite::layer` is
+    /// ignored.
+    Image(Texture),
+}
+
+/// The order `SpriteBatch` draws sprites in. Sorting is stable, so sprites that compare equal are
+/// drawn in the order they were submitted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SortMode {
+    /// Draw sprites in the order they were submitted.
+    Submission,
+    /// Draw sprites with lower z values first, behind sprites with higher z values.
+    Z,
+    /// Sort by z, then draw sprites further up the screen first, as in top-down games.
+    ZThenY,
+}
+
+#[derive(Copy, Clone)]
+struct SortKey {
+    z: f32,
+    y: f32,
+    /// Index into `SpriteBatch::textures`.
+    texture: usize,
+    /// Index into `SpriteBatch::materials`.
+    material: usize,
+}
+
+/// A range of queued sprites drawn with one draw call, its material, and the textures bound for
+/// it as indices into `SpriteBatch::textures`.
+struct DrawCall {
+    start: usize,
+    end: usize,
+    material: usize,
+    arrays: Vec<usize>,
+    images: Vec<usize>,
+}
+
+/// What a `SpriteBatch` needs to draw with a shader.
+struct ShaderState {
+    /// Weak, so that the state of shaders whose materials are all gone can be dropped.
+    shader: Weak<glutil::ShaderProgram>,
+    proj: glutil::Uniform<Transform3D<f32>>,
+    alpha_cutoff: Option<glutil::Uniform<f32>>,
+    array_slots: usize,
+    image_slots: usize,
+    vao: glutil::VertexArray,
+}
+
+/// Queued sprites, in the form expected by the shader.
+enum Buffer {
+    /// Six vertices per sprite.
+    Vertices(Vec<SpriteVertex>),
+    /// One instance per sprite, expanded to a quad by the vertex shader.
+    Instances(Vec<SpriteInstance>),
+}
+
+/// A sprite quad ready to be queued.
+struct Quad {
+    /// The center of the quad.
+    origin: Point2<f32>,
+    /// From the left edge to the right edge of the quad.
+    axis_x: Vec2<f32>,
+    /// From the bottom edge to the top edge of the quad.
+    axis_y: Vec2<f32>,
+    uv: Rect<f32>,
+    layer: f32,
+    rotated: bool,
+    /// Bottom-left, top-left, bottom-right and top-right colors.
+    colors: [[u8; 4]; 4],
+    z: f32,
+    y: f32,
 }
 
 #[derive(Copy, Clone)]
@@ -16,172 +119,1190 @@ struct SpriteVertex {
     pos: Point2<f32>,
     tex: Point3<f32>,
     color: [u8; 4],
+    depth: f32,
+    slot: u8,
+    _padding: [u8; 3],
+}
+
+impl glutil::Vertex for SpriteVertex {
+    fn layout() -> glutil::VertexLayout {
+        use glutil::AttributeFormat::*;
+        glutil::VertexLayout::new()
+            .attribute("pos", Float(2))
+            .attribute("tex", Float(3))
+            .attribute("color", U8Norm(4))
+            .attribute("depth", Float(1))
+            .attribute("slot", U8(1))
+            .with_stride(32)
+    }
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct SpriteInstance {
+    axis_x: Vec2<f32>,
+    axis_y: Vec2<f32>,
+    origin: Point2<f32>,
+    uv: [f32; 4],
+    layer: f32,
+    depth: f32,
+    /// Bottom-left, top-left, bottom-right and top-right colors.
+    colors: [[u8; 4]; 4],
+    rotated: u8,
+    slot: u8,
+    _padding: [u8; 2],
+}
+
+impl glutil::Vertex for SpriteInstance {
+    fn layout() -> glutil::VertexLayout {
+        use glutil::AttributeFormat::*;
+        glutil::VertexLayout::new()
+            .attribute("axis_x", Float(2))
+            .attribute("axis_y", Float(2))
+            .attribute("origin", Float(2))
+            .attribute("uv", Float(4))
+            .attribute("layer", Float(1))
+            .attribute("depth", Float(1))
+            .attribute("color_bl", U8Norm(4))
+            .attribute("color_tl", U8Norm(4))
+            .attribute("color_br", U8Norm(4))
+            .attribute("color_tr", U8Norm(4))
+            .attribute("rotated", U8Norm(1))
+            .attribute("slot", U8(1))
+            .with_stride(68)
+            .per_instance()
+    }
+}
+
+/// Queued data with a depth value that is assigned when sorting.
+trait HasDepth: Copy {
+    fn set_depth(&mut self, depth: f32);
+}
+
+impl HasDepth for SpriteVertex {
+    fn set_depth(&mut self, depth: f32) {
+        self.depth = depth;
+    }
+}
+
+impl HasDepth for SpriteInstance {
+    fn set_depth(&mut self, depth: f32) {
+        self.depth = depth;
+    }
+}
+
+impl SpriteTexture {
+    fn raw(&self) -> glow::Texture {
+        match self {
+            SpriteTexture::Array(tex) => tex.raw(),
+            SpriteTexture::Image(tex) => tex.raw(),
+        }
+    }
+}
+
+impl PartialEq for SpriteTexture {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw() == other.raw()
+    }
+}
+
+impl From<TextureArray> for SpriteTexture {
+    fn from(tex: TextureArray) -> Self {
+        SpriteTexture::Array(tex)
+    }
+}
+
+impl From<&TextureArray> for SpriteTexture {
+    fn from(tex: &TextureArray) -> Self {
+        SpriteTexture::Array(tex.clone())
+    }
+}
+
+impl From<Texture> for SpriteTexture {
+    fn from(tex: Texture) -> Self {
+        SpriteTexture::Image(tex)
+    }
+}
+
+impl From<&Texture> for SpriteTexture {
+    fn from(tex: &Texture) -> Self {
+        SpriteTexture::Image(tex.clone())
+    }
+}
+
+impl DrawCall {
+    fn new(start: usize, material: usize) -> Self {
+        DrawCall {
+            start,
+            end: start,
+            material,
+            arrays: vec![],
+            images: vec![],
+        }
+    }
+
+    /// Finds or allocates the slot passed to the shader for a texture, or returns `None` if all
+    /// slots for its kind are taken.
+    fn slot(
+        &mut self,
+        texture: usize,
+        image: bool,
+        array_slots: usize,
+        image_slots: usize,
+    ) -> Option<u8> {
+        let (bound, capacity, base) = if image {
+            (&mut self.images, image_slots, array_slots)
+        } else {
+            (&mut self.arrays, array_slots, 0)
+        };
+        let index = match bound.iter().position(|&t| t == texture) {
+            Some(index) => index,
+            None if bound.len() < capacity => {
+                bound.push(texture);
+                bound.len() - 1
+            }
+            None => return None,
+        };
+        Some((base + index) as u8)
+    }
+}
+
+impl ShaderState {
+    fn new(
+        gl: &Gl,
+        shader: Rc<glutil::ShaderProgram>,
+        vbo: &glutil::StreamBuffer,
+        instanced: bool,
+    ) -> Result<Self, Error> {
+        let proj = shader.uniform("proj")?;
+        let alpha_cutoff = shader.uniform("alpha_cutoff").ok();
+
+        let array_slots = shader
+            .uniform_info("sprites")
+            .map_or(1, |u| u.size as usize);
+        let image_slots = shader.uniform_info("images").map_or(0, |u| u.size as usize);
+        {
+            let _saved = gl.save_state();
+            if shader.uniform_info("sprites").is_some() {
+                let units = (0..array_slots)
+                    .map(|i| glutil::Sampler(i as u32))
+                    .collect();
+                shader.set_uniform::<Vec<_>>("sprites", units)?;
+            }
+            if image_slots > 0 {
+                let units = (array_slots..array_slots + image_slots)
+                    .map(|i| glutil::Sampler(i as u32))
+                    .collect();
+                shader.set_uniform::<Vec<_>>("images", units)?;
+            }
+        }
+
+        let vao = if instanced {
+            glutil::VertexArray::new::<SpriteInstance>(gl, &shader, vbo.raw(), None)?
+        } else {
+            glutil::VertexArray::new::<SpriteVertex>(gl, &shader, vbo.raw(), None)?
+        };
+        Ok(ShaderState {
+            shader: Rc::downgrade(&shader),
+            proj,
+            alpha_cutoff,
+            array_slots,
+            image_slots,
+            vao,
+        })
+    }
+
+    /// Checks that sprites from `texture` can be drawn with the shader.
+    fn check_texture(&self, texture: &SpriteTexture) -> Result<(), Error> {
+        match texture {
+            SpriteTexture::Image(_) if self.image_slots == 0 => Err(Error::InvalidArgument(
+                "Sprite shader has no `images` samplers to draw 2D textures with".into(),
+            )),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl SpriteBatch {
-    pub fn new(gl: &Gl, shader: glow::Program, tex: glow::Texture) -> Result<Self, String> {
-        let proj_loc = glutil::get_uniform_location(gl, shader, "proj")?;
-        let vbo = unsafe { gl.create_buffer()? };
+    /// Creates a sprite batch drawing with `shader`, initially from `tex`.
+    ///
+    /// If the shader takes per-instance inputs like the one from `sprite_shader_instanced`, each
+    /// sprite is uploaded as a single instance; otherwise six vertices are uploaded per sprite as
+    /// for `sprite_shader_per_vertex`.
+    ///
+    /// Sprites from different textures are drawn in one draw call as long as the shader has
+    /// enough samplers. A shader may declare `uniform sampler2DArray sprites[N]` and
+    /// `uniform sampler2D images[M]`; the `slot` vertex input then selects `sprites[slot]` if
+    /// `slot < N` and `images[slot - N]` otherwise. A shader with a single `sprites` sampler
+    /// draws one texture array per draw call and cannot draw `SpriteTexture::Image`s.
+    ///
+    /// Fails if `tex` is a `SpriteTexture::Image` and the shader has no `images` samplers.
+    pub fn new(
+        gl: &Gl,
+        shader: glutil::ShaderProgram,
+        tex: impl Into<SpriteTexture>,
+    ) -> Result<Self, Error> {
+        let instanced = shader.attribute_info("axis_x").is_some();
+        let vbo = glutil::StreamBuffer::new(gl, glow::ARRAY_BUFFER)?;
+        let (buffer, label) = if instanced {
+            (Buffer::Instances(vec![]), "SpriteBatch instances")
+        } else {
+            (Buffer::Vertices(vec![]), "SpriteBatch vertices")
+        };
+        vbo.set_label(label);
+
+        let material = Material::new(shader).with_blend(BlendMode::Alpha);
+        let state = ShaderState::new(gl, material.shared_shader().clone(), &vbo, instanced)?;
+        let texture = tex.into();
+        state.check_texture(&texture)?;
         Ok(SpriteBatch {
             gl: gl.clone(),
             pixels_per_unit: 1.0,
-            shader,
-            tex,
+            sort: SortMode::Submission,
+            depth_test: false,
+            alpha_cutoff: 0.5,
+            material,
+            shader: 0,
+            materials: vec![],
+            shaders: vec![state],
+            texture,
+            textures: vec![],
             vbo,
-            proj_loc,
-            buffer: vec![],
+            buffer,
+            keys: vec![],
+            order: vec![],
         })
     }
 
-    fn draw_points(&mut self, sprite: &Sprite, points: [Point2<f32>; 4], color: [u8; 4]) {
-        let bl_tex = sprite.tex.origin + vec2(0.0, sprite.tex.size.height);
-        let tl_tex = sprite.tex.origin + vec2(0.0, 0.0);
-        let br_tex = sprite.tex.origin + vec2(sprite.tex.size.width, sprite.tex.size.height);
-        let tr_tex = sprite.tex.origin + vec2(sprite.tex.size.width, 0.0);
-        let bl = SpriteVertex {
-            pos: points[0],
-            color,
-            tex: if sprite.rotated { tl_tex } else { bl_tex }.extend(sprite.layer as f32),
+    /// Whether sprites are drawn as instances rather than as individual vertices.
+    pub fn is_instanced(&self) -> bool {
+        matches!(self.buffer, Buffer::Instances(_))
+    }
+
+    /// Creates a material using the given fragment shader and the vertex shader of the default
+    /// material of this batch.
+    ///
+    /// The fragment shader receives the same inputs as `sprite.frag.glsl`.
+    pub fn material(&self, fragment_shader: &str) -> Result<Material, Error> {
+        let vertex_shader = if self.is_instanced() {
+            include_str!("shaders/sprite_instanced.vert.glsl")
+        } else {
+            include_str!("shaders/sprite.vert.glsl")
         };
-        let tl = SpriteVertex {
-            pos: points[1],
-            color,
-            tex: if sprite.rotated { tr_tex } else { tl_tex }.extend(sprite.layer as f32),
+        let shader = glutil::ShaderProgram::from_source(&self.gl, vertex_shader, fragment_shader)?;
+        Ok(Material::new(shader))
+    }
+
+    /// The material the following draws use. Initially a material with the shader passed to
+    /// `new` and `BlendMode::Alpha`.
+    pub fn current_material(&self) -> &Material {
+        &self.material
+    }
+
+    /// Sets the material that the following draws use. Consecutive sprites with the same
+    /// material are drawn together.
+    ///
+    /// The shader must take the same vertex inputs as the shader passed to `new`. The batch
+    /// keeps a vertex array for each shader it draws with until the last material using the
+    /// shader is dropped.
+    ///
+    /// Fails if the current texture is a `SpriteTexture::Image` and the shader has no `images`
+    /// samplers; set an array texture first to switch to such a material.
+    pub fn set_material(&mut self, material: &Material) -> Result<(), Error> {
+        let shader = material.shared_shader();
+        let index = match self
+            .shaders
+            .iter()
+            .position(|s| s.shader.as_ptr() == Rc::as_ptr(shader))
+        {
+            Some(index) => index,
+            None => {
+                let instanced = self.is_instanced();
+                let state = ShaderState::new(&self.gl, shader.clone(), &self.vbo, instanced)?;
+                self.shaders.push(state);
+                self.shaders.len() - 1
+            }
         };
-        let br = SpriteVertex {
-            pos: points[2],
-            color,
-            tex: if sprite.rotated { bl_tex } else { br_tex }.extend(sprite.layer as f32),
+        self.shaders[index].check_texture(&self.texture)?;
+        self.shader = index;
+        self.material = material.clone();
+        Ok(())
+    }
+
+    /// Sets the blend mode of the current material for the following draws.
+    pub fn set_blend_mode(&mut self, blend: BlendMode) {
+        self.material.blend = Some(blend);
+    }
+
+    /// Sets the texture that the following draws take sprites from.
+    ///
+    /// Fails if the texture is a `SpriteTexture::Image` and the shader of the current material
+    /// has no `images` samplers.
+    pub fn set_texture(&mut self, texture: impl Into<SpriteTexture>) -> Result<(), Error> {
+        let texture = texture.into();
+        self.shaders[self.shader].check_texture(&texture)?;
+        self.texture = texture;
+        Ok(())
+    }
+
+    /// Queues the sort key for a sprite drawn with the current texture and material.
+    fn push_key(&mut self, z: f32, y: f32) {
+        let texture = match self.textures.iter().rposition(|t| *t == self.texture) {
+            Some(index) => index,
+            None => {
+                self.textures.push(self.texture.clone());
+                self.textures.len() - 1
+            }
         };
-        let tr = SpriteVertex {
-            pos: points[3],
-            color,
-            tex: if sprite.rotated { br_tex } else { tr_tex }.extend(sprite.layer as f32),
+        let material = match self.materials.last() {
+            Some((m, _)) if m.same_as(&self.material) => self.materials.len() - 1,
+            _ => {
+                self.materials.push((self.material.clone(), self.shader));
+                self.materials.len() - 1
+            }
         };
+        self.keys.push(SortKey {
+            z,
+            y,
+            texture,
+            material,
+        });
+    }
+
+    /// Starts drawing a sprite with more options than `draw` offers. The sprite is queued when
+    /// `SpriteDraw::submit` is called.
+    ///
+    /// ```ignore
+    /// batch
+    ///     .sprite(&sprites.player)
+    ///     .position(pos)
+    ///     .pivot(vec2(0.5, 0.0))
+    ///     .flip_x(facing_left)
+    ///     .rotation(Angle::degrees(15.0))
+    ///     .submit();
+    /// ```
+    pub fn sprite<'a>(&'a mut self, sprite: &'a Sprite) -> SpriteDraw<'a> {
+        SpriteDraw {
+            batch: self,
+            sprite,
+            position: point2(0.0, 0.0),
+            pivot: vec2(0.5, 0.5),
+            scale: vec2(1.0, 1.0),
+            rotation: Angle::zero(),
+            flip_x: false,
+            flip_y: false,
+            sub_rect: None,
+            colors: [[255; 4]; 4],
+            z: 0.0,
+            transform: Transform2D::identity(),
+        }
+    }
+
+    fn push_quad(&mut self, quad: Quad) {
+        self.push_key(quad.z, quad.y);
+        match &mut self.buffer {
+            Buffer::Instances(buffer) => buffer.push(SpriteInstance {
+                axis_x: quad.axis_x,
+                axis_y: quad.axis_y,
+                origin: quad.origin,
+                uv: [
+                    quad.uv.origin.x,
+                    quad.uv.origin.y,
+                    quad.uv.size.width,
+                    quad.uv.size.height,
+                ],
+                layer: quad.layer,
+                depth: 0.0,
+                colors: quad.colors,
+                rotated: if quad.rotated { 255 } else { 0 },
+                slot: 0,
+                _padding: [0; 2],
+            }),
+            Buffer::Vertices(buffer) => {
+                // Same corners and texture coordinates as the instanced vertex shader.
+                let vertex = |corner: Vec2<f32>, color: [u8; 4]| {
+                    let t = if quad.rotated {
+                        vec2(corner.y, corner.x)
+                    } else {
+                        vec2(corner.x, 1.0 - corner.y)
+                    };
+                    SpriteVertex {
+                        pos: quad.origin
+                            + quad.axis_x * (corner.x - 0.5)
+                            + quad.axis_y * (corner.y - 0.5),
+                        tex: (quad.uv.origin + t.component_mul(quad.uv.size.to_vector()))
+                            .extend(quad.layer),
+                        color,
+                        depth: 0.0,
+                        slot: 0,
+                        _padding: [0; 3],
+                    }
+                };
+                let bl = vertex(vec2(0.0, 0.0), quad.colors[0]);
+                let tl = vertex(vec2(0.0, 1.0), quad.colors[1]);
+                let br = vertex(vec2(1.0, 0.0), quad.colors[2]);
+                let tr = vertex(vec2(1.0, 1.0), quad.colors[3]);
 
-        self.buffer.push(bl);
-        self.buffer.push(tl);
-        self.buffer.push(br);
-        self.buffer.push(tl);
-        self.buffer.push(br);
-        self.buffer.push(tr);
+                buffer.push(bl);
+                buffer.push(tl);
+                buffer.push(br);
+                buffer.push(tl);
+                buffer.push(br);
+                buffer.push(tr);
+            }
+        }
     }
 
     pub fn draw_transform(&mut self, sprite: &Sprite, transform: Transform2D<f32>, color: [u8; 4]) {
-        self.draw_points(
-            sprite,
-            [
-                transform.transform_point(point2(
-                    -sprite.trimmed_size.width / 2.0 / self.pixels_per_unit,
-                    -sprite.trimmed_size.height / 2.0 / self.pixels_per_unit,
-                )),
-                transform.transform_point(point2(
-                    -sprite.trimmed_size.width / 2.0 / self.pixels_per_unit,
-                    sprite.trimmed_size.height / 2.0 / self.pixels_per_unit,
-                )),
-                transform.transform_point(point2(
-                    sprite.trimmed_size.width / 2.0 / self.pixels_per_unit,
-                    -sprite.trimmed_size.height / 2.0 / self.pixels_per_unit,
-                )),
-                transform.transform_point(point2(
-                    sprite.trimmed_size.width / 2.0 / self.pixels_per_unit,
-                    sprite.trimmed_size.height / 2.0 / self.pixels_per_unit,
-                )),
-            ],
-            color,
-        );
+        self.draw_transform_z(sprite, transform, 0.0, color);
+    }
+
+    /// Draws a sprite with the given z value, used by `sort` and `depth_test`.
+    pub fn draw_transform_z(
+        &mut self,
+        sprite: &Sprite,
+        transform: Transform2D<f32>,
+        z: f32,
+        color: [u8; 4],
+    ) {
+        self.sprite(sprite)
+            .transform(transform)
+            .z(z)
+            .color(color)
+            .submit();
     }
 
     pub fn draw(&mut self, sprite: &Sprite, pos: Point2<f32>, color: [u8; 4]) {
-        self.draw_points(
-            sprite,
-            [
-                pos + vec2(
-                    -sprite.trimmed_size.width / 2.0 / self.pixels_per_unit,
-                    -sprite.trimmed_size.height / 2.0 / self.pixels_per_unit,
-                ),
-                pos + vec2(
-                    -sprite.trimmed_size.width / 2.0 / self.pixels_per_unit,
-                    sprite.trimmed_size.height / 2.0 / self.pixels_per_unit,
-                ),
-                pos + vec2(
-                    sprite.trimmed_size.width / 2.0 / self.pixels_per_unit,
-                    -sprite.trimmed_size.height / 2.0 / self.pixels_per_unit,
-                ),
-                pos + vec2(
-                    sprite.trimmed_size.width / 2.0 / self.pixels_per_unit,
-                    sprite.trimmed_size.height / 2.0 / self.pixels_per_unit,
-                ),
-            ],
-            color,
-        );
+        self.draw_z(sprite, pos, 0.0, color);
+    }
+
+    /// Draws a sprite with the given z value, used by `sort` and `depth_test`.
+    pub fn draw_z(&mut self, sprite: &Sprite, pos: Point2<f32>, z: f32, color: [u8; 4]) {
+        self.sprite(sprite).position(pos).z(z).color(color).submit();
+    }
+
+    /// Draws a nine-slice sprite covering `area`. See `NineSlice`.
+    pub fn draw_nine_slice(&mut self, slice: &NineSlice, area: Rect<f32>, color: [u8; 4]) {
+        self.draw_nine_slice_z(slice, area, 0.0, color);
+    }
+
+    /// Draws a nine-slice sprite with the given z value, used by `sort` and `depth_test`.
+    pub fn draw_nine_slice_z(
+        &mut self,
+        slice: &NineSlice,
+        area: Rect<f32>,
+        z: f32,
+        color: [u8; 4],
+    ) {
+        let ppu = self.pixels_per_unit;
+        let size = slice.sprite.real_size;
+        let insets = slice.insets;
+        let x_axis = |mode| {
+            slice_axis(
+                size.width,
+                insets.left,
+                insets.right,
+                area.min_x(),
+                area.size.width,
+                ppu,
+                mode,
+            )
+        };
+        let y_axis = |mode| {
+            slice_axis(
+                size.height,
+                insets.bottom,
+                insets.top,
+                area.min_y(),
+                area.size.height,
+                ppu,
+                mode,
+            )
+        };
+        let columns = x_axis(slice.edges);
+        let rows = y_axis(slice.edges);
+        let center_columns = x_axis(slice.center);
+        let center_rows = y_axis(slice.center);
+
+        for (i, columns) in columns.iter().enumerate() {
+            for (j, rows) in rows.iter().enumerate() {
+                let (columns, rows) = if i == 1 && j == 1 {
+                    (&center_columns[1], &center_rows[1])
+                } else {
+                    (columns, rows)
+                };
+                for column in columns {
+                    for row in rows {
+                        self.sprite(&slice.sprite)
+                            .sub_rect(rect(
+                                column.src / size.width,
+                                row.src / size.height,
+                                column.src_len / size.width,
+                                row.src_len / size.height,
+                            ))
+                            .pivot(vec2(column.src / size.width, row.src / size.height))
+                            .position(point2(column.dst, row.dst))
+                            .scale(vec2(
+                                column.dst_len * ppu / column.src_len,
+                                row.dst_len * ppu / row.src_len,
+                            ))
+                            .z(z)
+                            .color(color)
+                            .submit();
+                    }
+                }
+            }
+        }
     }
 
-    /// Actually draws the queued glyphs.
+    /// Actually draws the queued sprites.
     ///
-    /// Touches the following Open_GL state:
-    /// - `GL_TEXTURE_2D_ARRAY` binding
-    /// - `GL_ARRAY_BUFFER` binding
-    /// - Current shader program
-    /// - Vertex attribute arrays for indices 0, 1, 2
+    /// Sprites are grouped into as few draw calls as their materials and the shaders' samplers
+    /// allow, without changing the order they are drawn in.
+    ///
+    /// Leaves the state tracked by `Gl::save_state` unchanged. If `depth_test` is set, this also
+    /// enables depth writes, sets the depth function to `GL_LEQUAL` and disables `GL_DEPTH_TEST`
+    /// afterwards. Blending is left as configured by the last material with a blend mode.
     pub fn render(&mut self, camera: Transform3D<f32>) {
+        self.arrange();
+        let calls = self.assign_slots();
+
+        let _saved = self.gl.save_state();
         unsafe {
-            self.gl.bind_texture(glow::TEXTURE_2D_ARRAY, Some(self.tex));
+            if self.depth_test {
+                self.gl.enable(glow::DEPTH_TEST);
+                self.gl.depth_func(glow::LEQUAL);
+                self.gl.depth_mask(true);
+            }
+
+            let first = match &self.buffer {
+                Buffer::Vertices(buffer) => self.vbo.write(buffer),
+                Buffer::Instances(buffer) => self.vbo.write(buffer),
+            };
+
+            let mut current_material = None;
+            for call in &calls {
+                let (material, shader) = &self.materials[call.material];
+                let state = &self.shaders[*shader];
+                if current_material != Some(call.material) {
+                    current_material = Some(call.material);
+                    material.bind();
+                    material.shader().set(&state.proj, camera);
+                    if let Some(alpha_cutoff) = &state.alpha_cutoff {
+                        let cutoff = if self.depth_test {
+                            self.alpha_cutoff
+                        } else {
+                            0.0
+                        };
+                        material.shader().set(alpha_cutoff, cutoff);
+                    }
+                    if let Some(blend) = material.blend {
+                        blend.apply(&self.gl);
+                    }
+                    state.vao.bind();
+                }
+
+                for (unit, &texture) in call.arrays.iter().enumerate() {
+                    self.gl.active_texture(glow::TEXTURE0 + unit as u32);
+                    self.gl
+                        .bind_texture(glow::TEXTURE_2D_ARRAY, Some(self.textures[texture].raw()));
+                }
+                for (i, &texture) in call.images.iter().enumerate() {
+                    let unit = state.array_slots + i;
+                    self.gl.active_texture(glow::TEXTURE0 + unit as u32);
+                    self.gl
+                        .bind_texture(glow::TEXTURE_2D, Some(self.textures[texture].raw()));
+                }
 
-            self.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vbo));
-            self.gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                glutil::as_u8_slice(&self.buffer),
-                glow::STREAM_DRAW,
-            );
+                let count = call.end - call.start;
+                match &self.buffer {
+                    Buffer::Vertices(_) => self.gl.draw_arrays(
+                        glow::TRIANGLES,
+                        (first + call.start * 6) as i32,
+                        (count * 6) as i32,
+                    ),
+                    Buffer::Instances(_) => {
+                        state.vao.set_first(0, first + call.start);
+                        self.gl
+                            .draw_arrays_instanced(glow::TRIANGLES, 0, 6, count as i32);
+                    }
+                }
+            }
 
-            self.gl
-                .vertex_attrib_pointer_f32(0, 2, glow::FLOAT, false, 24, 0);
-            self.gl
-                .vertex_attrib_pointer_f32(1, 3, glow::FLOAT, false, 24, 8);
-            self.gl
-                .vertex_attrib_pointer_f32(2, 4, glow::UNSIGNED_BYTE, true, 24, 20);
-            self.gl.enable_vertex_attrib_array(0);
-            self.gl.enable_vertex_attrib_array(1);
-            self.gl.enable_vertex_attrib_array(2);
+            if self.depth_test {
+                self.gl.disable(glow::DEPTH_TEST);
+            }
+        }
+        self.gl.check_errors("SpriteBatch::render");
 
-            self.gl.use_program(Some(self.shader));
-            let mat = camera.to_array();
-            self.gl
-                .uniform_matrix_4_f32_slice(Some(&self.proj_loc), false, &mat);
+        match &mut self.buffer {
+            Buffer::Vertices(buffer) => buffer.clear(),
+            Buffer::Instances(buffer) => buffer.clear(),
+        }
+        self.keys.clear();
+        self.textures.clear();
+        self.materials.clear();
+        self.drop_unused_shaders();
+    }
 
-            self.gl
-                .draw_arrays(glow::TRIANGLES, 0, self.buffer.len() as i32);
+    /// Drops the state of shaders whose materials are all gone. Queued sprites refer to shaders
+    /// by index, so this is only done with no sprites queued.
+    fn drop_unused_shaders(&mut self) {
+        let current = Rc::as_ptr(self.material.shared_shader());
+        self.shaders.retain(|s| s.shader.strong_count() > 0);
+        self.shader = self
+            .shaders
+            .iter()
+            .position(|s| s.shader.as_ptr() == current)
+            .unwrap();
+    }
+
+    /// Reorders the queued sprites into the order they are drawn in, according to `sort` and
+    /// `depth_test`, and assigns depths if `depth_test` is set.
+    fn arrange(&mut self) {
+        if self.sort != SortMode::Submission || self.depth_test {
+            let keys = &self.keys;
+            self.order.clear();
+            self.order.extend(0..keys.len());
+            let by_z = |&a: &usize, &b: &usize| {
+                keys[a]
+                    .z
+                    .partial_cmp(&keys[b].z)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            };
+            match self.sort {
+                SortMode::Submission => {}
+                SortMode::Z => self.order.sort_by(by_z),
+                SortMode::ZThenY => self.order.sort_by(|a, b| {
+                    by_z(a, b).then_with(|| {
+                        keys[*b]
+                            .y
+                            .partial_cmp(&keys[*a].y)
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    })
+                }),
+            }
+            if self.depth_test {
+                // Draw front to back so hidden fragments fail the depth test.
+                self.order.reverse();
+            }
+
+            permute(&mut self.keys, 1, &self.order);
+            match &mut self.buffer {
+                Buffer::Vertices(buffer) => permute(buffer, 6, &self.order),
+                Buffer::Instances(buffer) => permute(buffer, 1, &self.order),
+            }
+        }
 
-            self.gl.disable_vertex_attrib_array(0);
-            self.gl.disable_vertex_attrib_array(1);
-            self.gl.disable_vertex_attrib_array(2);
+        if self.depth_test {
+            match &mut self.buffer {
+                Buffer::Vertices(buffer) => assign_depths(buffer, 6),
+                Buffer::Instances(buffer) => assign_depths(buffer, 1),
+            }
         }
-        self.buffer.clear();
+    }
+
+    /// Splits the arranged sprites into draw calls and tells each sprite which of its draw
+    /// call's textures to sample.
+    fn assign_slots(&mut self) -> Vec<DrawCall> {
+        let textures = &self.textures;
+        let materials = &self.materials;
+        let shaders = &self.shaders;
+        let buffer = &mut self.buffer;
+        draw_calls(
+            &self.keys,
+            |texture| matches!(textures[texture], SpriteTexture::Image(_)),
+            |material| {
+                let state = &shaders[materials[material].1];
+                (state.array_slots, state.image_slots)
+            },
+            |i, slot| match buffer {
+                Buffer::Vertices(buffer) => {
+                    for vertex in &mut buffer[i * 6..i * 6 + 6] {
+                        vertex.slot = slot;
+                    }
+                }
+                Buffer::Instances(buffer) => buffer[i].slot = slot,
+            },
+        )
     }
 }
 
-impl Drop for SpriteBatch {
-    fn drop(&mut self) {
-        unsafe {
-            self.gl.delete_buffer(self.vbo);
+/// A sprite draw being configured, created by `SpriteBatch::sprite`.
+///
+/// Positions are in the units of the batch, scaled by `pixels_per_unit`. The pivot, scale,
+/// rotation and position are applied in that order, followed by `transform`.
+#[must_use = "the sprite is only drawn when `submit` is called"]
+pub struct SpriteDraw<'a> {
+    batch: &'a mut SpriteBatch,
+    sprite: &'a Sprite,
+    position: Point2<f32>,
+    pivot: Vec2<f32>,
+    scale: Vec2<f32>,
+    rotation: Angle<f32>,
+    flip_x: bool,
+    flip_y: bool,
+    sub_rect: Option<Rect<f32>>,
+    colors: [[u8; 4]; 4],
+    z: f32,
+    transform: Transform2D<f32>,
+}
+
+impl SpriteDraw<'_> {
+    /// Where the pivot of the sprite is placed. Defaults to the origin.
+    pub fn position(mut self, position: Point2<f32>) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// The point the sprite is positioned by, scaled and rotated around, as a fraction of its
+    /// untrimmed size from its bottom-left corner. Defaults to the center, `(0.5, 0.5)`.
+    pub fn pivot(mut self, pivot: Vec2<f32>) -> Self {
+        self.pivot = pivot;
+        self
+    }
+
+    /// Defaults to `(1.0, 1.0)`.
+    pub fn scale(mut self, scale: Vec2<f32>) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Counterclockwise rotation around the pivot.
+    pub fn rotation(mut self, rotation: Angle<f32>) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Mirrors the sprite horizontally around the center of its untrimmed size.
+    pub fn flip_x(mut self, flip: bool) -> Self {
+        self.flip_x = flip;
+        self
+    }
+
+    /// Mirrors the sprite vertically around the center of its untrimmed size.
+    pub fn flip_y(mut self, flip: bool) -> Self {
+        self.flip_y = flip;
+        self
+    }
+
+    /// Only draws the part of the sprite inside `rect`, given as fractions of its untrimmed size
+    /// from its bottom-left corner, before flipping. The cropped part stays where it would be
+    /// drawn without cropping, so this can be used for progress bars and similar effects.
+    pub fn sub_rect(mut self, rect: Rect<f32>) -> Self {
+        self.sub_rect = Some(rect);
+        self
+    }
+
+    /// Tints the whole sprite. Defaults to white.
+    pub fn color(mut self, color: [u8; 4]) -> Self {
+        self.colors = [color; 4];
+        self
+    }
+
+    /// Tints each corner of the sprite as drawn, interpolating the colors in between.
+    pub fn corner_colors(
+        mut self,
+        bottom_left: [u8; 4],
+        top_left: [u8; 4],
+        bottom_right: [u8; 4],
+        top_right: [u8; 4],
+    ) -> Self {
+        self.colors = [bottom_left, top_left, bottom_right, top_right];
+        self
+    }
+
+    /// The z value, used by `SpriteBatch::sort` and `SpriteBatch::depth_test`. Defaults to 0.
+    pub fn z(mut self, z: f32) -> Self {
+        self.z = z;
+        self
+    }
+
+    /// A transform applied after the position. Defaults to the identity.
+    pub fn transform(mut self, transform: Transform2D<f32>) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Queues the sprite.
+    pub fn submit(self) {
+        let sprite = self.sprite;
+        let real = sprite.real_size;
+
+        // Work in pixels of the untrimmed image, with y pointing up.
+        let trimmed = Rect::new(sprite.trim_offset.to_point(), sprite.trimmed_size);
+        let visible = match self.sub_rect {
+            Some(crop) => {
+                let crop = Rect::new(
+                    point2(crop.origin.x * real.width, crop.origin.y * real.height),
+                    size2(crop.size.width * real.width, crop.size.height * real.height),
+                );
+                match crop.intersection(&trimmed) {
+                    Some(v) if !v.is_empty() => v,
+                    _ => return,
+                }
+            }
+            None => trimmed,
+        };
+
+        // The visible part in texture coordinates, see the instanced vertex shader.
+        let s0 = (visible.min_x() - trimmed.min_x()) / trimmed.size.width;
+        let s1 = (visible.max_x() - trimmed.min_x()) / trimmed.size.width;
+        let t0 = (visible.min_y() - trimmed.min_y()) / trimmed.size.height;
+        let t1 = (visible.max_y() - trimmed.min_y()) / trimmed.size.height;
+        let tex = sprite.tex;
+        let uv = if sprite.rotated {
+            rect(
+                tex.origin.x + t0 * tex.size.width,
+                tex.origin.y + s0 * tex.size.height,
+                (t1 - t0) * tex.size.width,
+                (s1 - s0) * tex.size.height,
+            )
+        } else {
+            rect(
+                tex.origin.x + s0 * tex.size.width,
+                tex.origin.y + (1.0 - t1) * tex.size.height,
+                (s1 - s0) * tex.size.width,
+                (t1 - t0) * tex.size.height,
+            )
+        };
+
+        // Flipping mirrors the quad and turns its axes around, so the corner colors are swapped
+        // to stay on the corners they were given for.
+        let mut center = visible.center();
+        let mut size = visible.size.to_vector();
+        let mut colors = self.colors;
+        if self.flip_x {
+            center.x = real.width - center.x;
+            size.x = -size.x;
+            colors.swap(0, 2);
+            colors.swap(1, 3);
+        }
+        if self.flip_y {
+            center.y = real.height - center.y;
+            size.y = -size.y;
+            colors.swap(0, 1);
+            colors.swap(2, 3);
+        }
+
+        let pivot = point2(self.pivot.x * real.width, self.pivot.y * real.height);
+        let to_world = Transform2D::translation(-pivot.x, -pivot.y)
+            .then_scale(
+                self.scale.x / self.batch.pixels_per_unit,
+                self.scale.y / self.batch.pixels_per_unit,
+            )
+            .then_rotate(self.rotation)
+            .then_translate(self.position.to_vector())
+            .then(&self.transform);
+
+        self.batch.push_quad(Quad {
+            origin: to_world.transform_point(center),
+            axis_x: to_world.transform_vector(vec2(size.x, 0.0)),
+            axis_y: to_world.transform_vector(vec2(0.0, size.y)),
+            uv,
+            layer: sprite.layer,
+            rotated: sprite.rotated,
+            colors,
+            z: self.z,
+            y: to_world.transform_point(pivot).y,
+        });
+    }
+}
+
+/// Splits sprites, given by their keys in drawing order, into as few draw calls as their
+/// materials and the samplers of their shaders allow, passing the index and texture slot of each
+/// sprite to `set_slot`.
+///
+/// `is_image` tells whether a texture is a `SpriteTexture::Image`, and `slots` gives the number
+/// of array and image samplers of the shader of a material.
+fn draw_calls(
+    keys: &[SortKey],
+    is_image: impl Fn(usize) -> bool,
+    slots: impl Fn(usize) -> (usize, usize),
+    mut set_slot: impl FnMut(usize, u8),
+) -> Vec<DrawCall> {
+    let mut calls = vec![];
+    let mut call = DrawCall::new(0, keys.first().map_or(0, |k| k.material));
+    for (i, key) in keys.iter().enumerate() {
+        if key.material != call.material {
+            call.end = i;
+            calls.push(std::mem::replace(&mut call, DrawCall::new(i, key.material)));
+        }
+        let (array_slots, image_slots) = slots(key.material);
+        let image = is_image(key.texture);
+        let slot = loop {
+            match call.slot(key.texture, image, array_slots, image_slots) {
+                Some(slot) => break slot,
+                None => {
+                    call.end = i;
+                    calls.push(std::mem::replace(&mut call, DrawCall::new(i, key.material)));
+                }
+            }
+        };
+        set_slot(i, slot);
+    }
+    if call.start < keys.len() {
+        call.end = keys.len();
+        calls.push(call);
+    }
+    calls
+}
+
+/// Reorders `items`, which holds `per_sprite` items for each sprite, into `order`.
+fn permute<T: Copy>(items: &mut Vec<T>, per_sprite: usize, order: &[usize]) {
+    let mut permuted = Vec::with_capacity(items.len());
+    for &sprite in order {
+        permuted.extend_from_slice(&items[sprite * per_sprite..(sprite + 1) * per_sprite]);
+    }
+    *items = permuted;
+}
+
+/// Gives sprites increasing depths in the order they are drawn, so the first sprite is in front.
+fn assign_depths<T: HasDepth>(items: &mut [T], per_sprite: usize) {
+    let count = (items.len() / per_sprite) as f32;
+    for (i, sprite) in items.chunks_mut(per_sprite).enumerate() {
+        // Keep depths well inside the clip volume of the usual [-1, 1] ortho camera.
+        let depth = (i as f32 + 0.5) / count - 0.5;
+        for item in sprite {
+            item.set_depth(depth);
         }
     }
 }
 
+#[derive(Copy, Clone, Debug)]
 pub struct Sprite {
     pub tex: Rect<f32>,
+    /// The size in pixels of the part of the image stored in the atlas, without its transparent
+    /// margins.
     pub trimmed_size: Size2<f32>,
+    /// Where the part stored in the atlas is in the source image, in pixels from its bottom-left
+    /// corner. Pivots and positions refer to the source image, so trimmed sprites are drawn
+    /// where they would be untrimmed.
+    pub trim_offset: Vec2<f32>,
+    /// The size in pixels of the source image, which pivots are relative to.
     pub real_size: Size2<f32>,
     pub layer: f32,
     pub rotated: bool,
 }
 
-pub fn sprite_shader(gl: &Gl) -> glow::Program {
-    glutil::compile_shader_program(
+/// A sprite that can be drawn at any size by stretching or tiling its edges and center while
+/// keeping its corners intact, for UI panels and buttons.
+///
+/// The insets split the sprite into a 3x3 grid. Corners are drawn unscaled, the top and bottom
+/// edges are resized horizontally, the left and right edges vertically, and the center both
+/// ways. If the drawn rectangle is smaller than the corners, they are shrunk to fit.
+#[derive(Copy, Clone, Debug)]
+pub struct NineSlice {
+    pub sprite: Sprite,
+    /// The widths in pixels of the borders of the untrimmed sprite.
+    pub insets: SideOffsets2D<f32, UnknownUnit>,
+    /// How the edges are resized.
+    pub edges: SliceMode,
+    /// How the center is resized.
+    pub center: SliceMode,
+}
+
+/// How the parts of a `NineSlice` are resized.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum SliceMode {
+    Stretch,
+    /// Repeats the part at its original size, cropping the last repetition.
+    Tile,
+}
+
+/// A piece of a nine-slice sprite along one axis, with the source in pixels of the untrimmed
+/// sprite and the destination in world units.
+struct Segment {
+    src: f32,
+    src_len: f32,
+    dst: f32,
+    dst_len: f32,
+}
+
+/// Splits one axis of a nine-slice sprite into the segments of the start border, the middle
+/// and the end border.
+fn slice_axis(
+    size: f32,
+    start: f32,
+    end: f32,
+    dst: f32,
+    dst_len: f32,
+    pixels_per_unit: f32,
+    mode: SliceMode,
+) -> [Vec<Segment>; 3] {
+    let border_scale = (dst_len * pixels_per_unit / (start + end)).min(1.0);
+    let dst_start = start / pixels_per_unit * border_scale;
+    let dst_end = end / pixels_per_unit * border_scale;
+    let src_middle = size - start - end;
+    let dst_middle = dst_len - dst_start - dst_end;
+
+    let mut middle = vec![];
+    if src_middle > 0.0 && dst_middle > 0.0 {
+        match mode {
+            SliceMode::Stretch => middle.push(Segment {
+                src: start,
+                src_len: src_middle,
+                dst: dst + dst_start,
+                dst_len: dst_middle,
+            }),
+            SliceMode::Tile => {
+                let tile = src_middle / pixels_per_unit;
+                let mut offset = 0.0;
+                while offset < dst_middle {
+                    let len = tile.min(dst_middle - offset);
+                    middle.push(Segment {
+                        src: start,
+                        src_len: len * pixels_per_unit,
+                        dst: dst + dst_start + offset,
+                        dst_len: len,
+                    });
+                    offset += tile;
+                }
+            }
+        }
+    }
+
+    let border = |src, src_len, dst, dst_len| {
+        if src_len > 0.0 && dst_len > 0.0 {
+            vec![Segment {
+                src,
+                src_len,
+                dst,
+                dst_len,
+            }]
+        } else {
+            vec![]
+        }
+    };
+    [
+        border(0.0, start, dst, dst_start),
+        middle,
+        border(size - end, end, dst + dst_len - dst_end, dst_end),
+    ]
+}
+
+/// The default sprite shader: `sprite_shader_instanced`, falling back to
+/// `sprite_shader_per_vertex` if it fails to build.
+pub fn sprite_shader(gl: &Gl) -> Result<glutil::ShaderProgram, Error> {
+    sprite_shader_instanced(gl).or_else(|e| {
+        log::warn!("Instanced sprite shader unavailable, falling back: {}", e);
+        sprite_shader_per_vertex(gl)
+    })
+}
+
+/// A sprite shader that expands one instance per sprite into a quad.
+pub fn sprite_shader_instanced(gl: &Gl) -> Result<glutil::ShaderProgram, Error> {
+    let shader = glutil::ShaderProgram::from_source(
+        gl,
+        include_str!("shaders/sprite_instanced.vert.glsl"),
+        include_str!("shaders/sprite.frag.glsl"),
+    )?;
+    shader.set_label("instanced sprite shader");
+    Ok(shader)
+}
+
+/// A sprite shader taking six vertices per sprite.
+pub fn sprite_shader_per_vertex(gl: &Gl) -> Result<glutil::ShaderProgram, Error> {
+    let shader = glutil::ShaderProgram::from_source(
         gl,
         include_str!("shaders/sprite.vert.glsl"),
         include_str!("shaders/sprite.frag.glsl"),
-    )
-    .unwrap()
+    )?;
+    shader.set_label("sprite shader");
+    Ok(shader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sprites drawn with the given textures and materials. Textures from 100 on are images.
+    fn keys(sprites: &[(usize, usize)]) -> Vec<SortKey> {
+        sprites
+            .iter()
+            .map(|&(texture, material)| SortKey {
+                z: 0.0,
+                y: 0.0,
+                texture,
+                material,
+            })
+            .collect()
+    }
+
+    /// A draw call as its start, end, material, arrays and images.
+    type Call = (usize, usize, usize, Vec<usize>, Vec<usize>);
+
+    /// The draw calls and the slot of each sprite.
+    fn split(sprites: &[(usize, usize)], slots: (usize, usize)) -> (Vec<Call>, Vec<u8>) {
+        let keys = keys(sprites);
+        let mut assigned = vec![None; keys.len()];
+        let calls = draw_calls(
+            &keys,
+            |texture| texture >= 100,
+            |_| slots,
+            |i, slot| assigned[i] = Some(slot),
+        );
+        let calls = calls
+            .into_iter()
+            .map(|c| (c.start, c.end, c.material, c.arrays, c.images))
+            .collect();
+        (calls, assigned.into_iter().map(Option::unwrap).collect())
+    }
+
+    #[test]
+    fn textures_share_a_draw_call_up_to_the_sampler_count() {
+        let sprites = [(0, 0), (1, 0), (0, 0), (2, 0), (3, 0), (1, 0)];
+        let (calls, slots) = split(&sprites, (4, 0));
+        assert_eq!(calls, [(0, 6, 0, vec![0, 1, 2, 3], vec![])]);
+        assert_eq!(slots, [0, 1, 0, 2, 3, 1]);
+    }
+
+    #[test]
+    fn a_fifth_texture_starts_a_new_draw_call() {
+        let sprites = [(0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (0, 0)];
+        let (calls, slots) = split(&sprites, (4, 0));
+        assert_eq!(
+            calls,
+            [
+                (0, 4, 0, vec![0, 1, 2, 3], vec![]),
+                (4, 6, 0, vec![4, 0], vec![])
+            ]
+        );
+        assert_eq!(slots, [0, 1, 2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn images_take_the_slots_after_the_arrays() {
+        let sprites = [(100, 0), (0, 0), (101, 0), (100, 0), (102, 0)];
+        let (calls, slots) = split(&sprites, (1, 2));
+        assert_eq!(
+            calls,
+            [
+                (0, 4, 0, vec![0], vec![100, 101]),
+                (4, 5, 0, vec![], vec![102])
+            ]
+        );
+        assert_eq!(slots, [1, 0, 2, 1, 1]);
+    }
+
+    #[test]
+    fn materials_split_draw_calls_in_submission_order() {
+        let sprites = [(0, 0), (0, 1), (1, 1), (0, 0), (0, 0)];
+        let (calls, slots) = split(&sprites, (4, 4));
+        assert_eq!(
+            calls,
+            [
+                (0, 1, 0, vec![0], vec![]),
+                (1, 3, 1, vec![0, 1], vec![]),
+                (3, 5, 0, vec![0], vec![]),
+            ]
+        );
+        assert_eq!(slots, [0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn nothing_queued() {
+        let (calls, slots) = split(&[], (4, 4));
+        assert!(calls.is_empty() && slots.is_empty());
+    }
 }
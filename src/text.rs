@@ -1,4 +1,6 @@
+use crate::material::BlendMode;
 use crate::prelude::*;
+use crate::Error;
 use rusttype::gpu_cache::*;
 use rusttype::*;
 use scopeguard::ScopeGuard;
@@ -9,43 +11,47 @@ pub struct TextRenderer {
     cache: gpu_cache::Cache<'static>,
     render_queue: Vec<(PositionedGlyph<'static>, usize, [u8; 4])>,
     tex: glow::Texture,
-    vbo: glow::Buffer,
+    vbo: glutil::StreamBuffer,
+    vao: glutil::VertexArray,
     vbo_buf: Vec<TextVertex>,
     tex_size: i32,
     next_id: usize,
-    shader: glow::Program,
-    proj_loc: glow::UniformLocation,
+    shader: glutil::ShaderProgram,
+    proj: glutil::Uniform<Transform3D<f32>>,
 
     pub dpi: f32,
     pub screen_size: (f32, f32),
+    /// The blend mode text is rendered with. Defaults to `BlendMode::Alpha`.
+    pub blend: BlendMode,
 }
 
 impl TextRenderer {
     /// Constructor.
-    ///
-    /// Touches the following OpenGL state:
-    /// - `GL_TEXTURE_2D` binding
-    pub fn new(gl: &Gl) -> Result<TextRenderer, String> {
+    pub fn new(gl: &Gl) -> Result<TextRenderer, Error> {
+        let _saved = gl.save_state();
         unsafe {
             let tex_size = 512;
 
-            let vbo = scopeguard::guard(gl.create_buffer()?, |buf| gl.delete_buffer(buf));
+            let vbo = glutil::StreamBuffer::new(gl, glow::ARRAY_BUFFER)?;
+            vbo.set_label("TextRenderer vertices");
 
-            let tex = scopeguard::guard(gl.create_texture()?, |tex| gl.delete_texture(tex));
+            let tex = scopeguard::guard(gl.create_texture().map_err(Error::Gl)?, |tex| {
+                gl.delete_texture(tex)
+            });
             gl.bind_texture(glow::TEXTURE_2D, Some(*tex));
+            gl.label_texture(*tex, "TextRenderer glyph cache");
             gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAX_LEVEL, 0);
 
             let cache = allocate(gl, Cache::builder(), tex_size);
 
-            let shader = scopeguard::guard(
-                glutil::compile_shader_program(
-                    gl,
-                    include_str!("shaders/text.vert.glsl"),
-                    include_str!("shaders/text.frag.glsl"),
-                )?,
-                |shader| gl.delete_program(shader),
-            );
-            let proj_loc = glutil::get_uniform_location(gl, *shader, "proj")?;
+            let shader = glutil::ShaderProgram::from_source(
+                gl,
+                include_str!("shaders/text.vert.glsl"),
+                include_str!("shaders/text.frag.glsl"),
+            )?;
+            shader.set_label("text shader");
+            let proj = shader.uniform("proj")?;
+            let vao = glutil::VertexArray::new::<TextVertex>(gl, &shader, vbo.raw(), None)?;
 
             Ok(TextRenderer {
                 gl: gl.clone(),
@@ -53,27 +59,26 @@ impl TextRenderer {
                 cache,
                 render_queue: vec![],
                 tex: ScopeGuard::into_inner(tex),
-                vbo: ScopeGuard::into_inner(vbo),
+                vbo,
+                vao,
                 vbo_buf: vec![],
                 tex_size,
                 next_id: 0,
-                shader: ScopeGuard::into_inner(shader),
-                proj_loc,
+                shader,
+                proj,
                 dpi: 1.0,
                 screen_size: (0.0, 0.0),
+                blend: BlendMode::Alpha,
             })
         }
     }
 
     /// Actually draws the queued glyphs.
     ///
-    /// Touches the following OpenGL state:
-    /// - `GL_TEXTURE_2D` binding
-    /// - `GL_ARRAY_BUFFER` binding
-    /// - `GL_UNPACK_ALIGNMENT` pixel store parameter
-    /// - Current shader program
-    /// - Vertex attribute arrays for indices 0, 1, 2
+    /// Leaves the state tracked by `Gl::save_state` unchanged. Blending is left as configured for
+    /// `blend`.
     pub fn render(&mut self) {
+        let _saved = self.gl.save_state();
         for (glyph, font_id, _) in self.render_queue.iter().cloned() {
             self.cache.queue_glyph(font_id, glyph);
         }
@@ -143,23 +148,8 @@ impl TextRenderer {
         }
 
         unsafe {
-            self.gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vbo));
-            self.gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                glutil::as_u8_slice(&self.vbo_buf),
-                glow::STREAM_DRAW,
-            );
-            self.gl
-                .vertex_attrib_pointer_f32(0, 2, glow::FLOAT, false, 20, 0);
-            self.gl
-                .vertex_attrib_pointer_f32(1, 2, glow::FLOAT, false, 20, 8);
-            self.gl
-                .vertex_attrib_pointer_f32(2, 4, glow::UNSIGNED_BYTE, true, 20, 16);
-            self.gl.enable_vertex_attrib_array(0);
-            self.gl.enable_vertex_attrib_array(1);
-            self.gl.enable_vertex_attrib_array(2);
-
-            self.gl.use_program(Some(self.shader));
+            let first = self.vbo.write(&self.vbo_buf);
+            self.vao.bind();
 
             let mat = euclid::default::Transform3D::ortho(
                 0.0,
@@ -168,18 +158,14 @@ impl TextRenderer {
                 0.0,
                 -1.0,
                 1.0,
-            )
-            .to_array();
-            self.gl
-                .uniform_matrix_4_f32_slice(Some(&self.proj_loc), false, &mat);
+            );
+            self.shader.set(&self.proj, mat);
+            self.blend.apply(&self.gl);
 
             self.gl
-                .draw_arrays(glow::TRIANGLES, 0, self.vbo_buf.len() as i32);
-
-            self.gl.disable_vertex_attrib_array(0);
-            self.gl.disable_vertex_attrib_array(1);
-            self.gl.disable_vertex_attrib_array(2);
+                .draw_arrays(glow::TRIANGLES, first as i32, self.vbo_buf.len() as i32);
         }
+        self.gl.check_errors("TextRenderer::render");
 
         self.render_queue.clear();
     }
@@ -321,8 +307,6 @@ impl Drop for TextRenderer {
     fn drop(&mut self) {
         unsafe {
             self.gl.delete_texture(self.tex);
-            self.gl.delete_buffer(self.vbo);
-            self.gl.delete_program(self.shader);
         }
     }
 }
@@ -357,6 +341,7 @@ fn pick_font<'a>(
     (first.0, &first.1, first.1.glyph(chr), 1.0)
 }
 
+#[derive(Copy, Clone)]
 #[repr(C)]
 struct TextVertex {
     pos: Vec2<f32>,
@@ -364,6 +349,16 @@ struct TextVertex {
     color: [u8; 4],
 }
 
+impl glutil::Vertex for TextVertex {
+    fn layout() -> glutil::VertexLayout {
+        use glutil::AttributeFormat::*;
+        glutil::VertexLayout::new()
+            .attribute("pos", Float(2))
+            .attribute("tex", Float(2))
+            .attribute("color", U8Norm(4))
+    }
+}
+
 #[derive(Clone)]
 pub struct Glyph {
     glyph: ScaledGlyph<'static>,
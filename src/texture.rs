@@ -0,0 +1,606 @@
+use std::rc::Rc;
+
+use crate::backend::glutil as backend;
+use crate::prelude::*;
+use crate::Error;
+
+/// A 2D texture.
+///
+/// This is a shared handle; cloning it does not copy the texture. The texture is deleted when
+/// the last handle is dropped.
+#[derive(Clone)]
+pub struct Texture(Rc<TextureInner>);
+
+/// A 2D array texture.
+///
+/// This is a shared handle; cloning it does not copy the texture. The texture is deleted when
+/// the last handle is dropped.
+#[derive(Clone)]
+pub struct TextureArray(Rc<TextureInner>);
+
+struct TextureInner {
+    gl: Gl,
+    raw: glow::Texture,
+    width: u32,
+    height: u32,
+    layers: u32,
+    format: TextureFormat,
+    options: TextureOptions,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TextureFormat {
+    /// 8-bit normalized RGBA.
+    Rgba8,
+    /// 8-bit normalized single channel.
+    R8,
+    /// 16-bit unsigned integer single channel. Must be sampled with a `usampler` and `Nearest`
+    /// filtering.
+    R16Ui,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Wrap {
+    ClampToEdge,
+    Repeat,
+    MirroredRepeat,
+}
+
+/// Sampling options for a texture.
+///
+/// If `mipmaps` is true, mipmaps are generated whenever the texture's contents are changed, and
+/// `min_filter` is also used to filter between mipmap levels.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TextureOptions {
+    pub min_filter: Filter,
+    pub mag_filter: Filter,
+    pub wrap_s: Wrap,
+    pub wrap_t: Wrap,
+    pub mipmaps: bool,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions {
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            wrap_s: Wrap::ClampToEdge,
+            wrap_t: Wrap::ClampToEdge,
+            mipmaps: false,
+        }
+    }
+}
+
+impl TextureOptions {
+    /// Nearest-neighbor filtering without mipmaps, suitable for pixel art and integer textures.
+    pub fn nearest() -> Self {
+        TextureOptions {
+            min_filter: Filter::Nearest,
+            mag_filter: Filter::Nearest,
+            ..Default::default()
+        }
+    }
+
+    /// Linear filtering with mipmaps.
+    pub fn mipmapped() -> Self {
+        TextureOptions {
+            mipmaps: true,
+            ..Default::default()
+        }
+    }
+
+    unsafe fn apply(&self, gl: &Gl, target: u32) {
+        let min_filter = match (self.min_filter, self.mipmaps) {
+            (Filter::Nearest, false) => glow::NEAREST,
+            (Filter::Linear, false) => glow::LINEAR,
+            (Filter::Nearest, true) => glow::NEAREST_MIPMAP_NEAREST,
+            (Filter::Linear, true) => glow::LINEAR_MIPMAP_LINEAR,
+        };
+        let mag_filter = match self.mag_filter {
+            Filter::Nearest => glow::NEAREST,
+            Filter::Linear => glow::LINEAR,
+        };
+        gl.tex_parameter_i32(target, glow::TEXTURE_MIN_FILTER, min_filter as i32);
+        gl.tex_parameter_i32(target, glow::TEXTURE_MAG_FILTER, mag_filter as i32);
+        gl.tex_parameter_i32(target, glow::TEXTURE_WRAP_S, self.wrap_s.to_gl() as i32);
+        gl.tex_parameter_i32(target, glow::TEXTURE_WRAP_T, self.wrap_t.to_gl() as i32);
+        if !self.mipmaps {
+            gl.tex_parameter_i32(target, glow::TEXTURE_MAX_LEVEL, 0);
+        }
+    }
+}
+
+impl Wrap {
+    fn to_gl(self) -> u32 {
+        match self {
+            Wrap::ClampToEdge => glow::CLAMP_TO_EDGE,
+            Wrap::Repeat => glow::REPEAT,
+            Wrap::MirroredRepeat => glow::MIRRORED_REPEAT,
+        }
+    }
+}
+
+impl TextureFormat {
+    pub fn internal_format(self) -> u32 {
+        match self {
+            TextureFormat::Rgba8 => glow::RGBA8,
+            TextureFormat::R8 => glow::R8,
+            TextureFormat::R16Ui => glow::R16UI,
+        }
+    }
+
+    pub fn format(self) -> u32 {
+        match self {
+            TextureFormat::Rgba8 => glow::RGBA,
+            TextureFormat::R8 => glow::RED,
+            TextureFormat::R16Ui => glow::RED_INTEGER,
+        }
+    }
+
+    pub fn data_type(self) -> u32 {
+        match self {
+            TextureFormat::Rgba8 | TextureFormat::R8 => glow::UNSIGNED_BYTE,
+            TextureFormat::R16Ui => glow::UNSIGNED_SHORT,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            TextureFormat::Rgba8 => 4,
+            TextureFormat::R8 => 1,
+            TextureFormat::R16Ui => 2,
+        }
+    }
+}
+
+impl TextureInner {
+    unsafe fn create(
+        gl: &Gl,
+        target: u32,
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: TextureFormat,
+        options: TextureOptions,
+    ) -> Result<Self, Error> {
+        let raw = gl.create_texture().map_err(Error::Gl)?;
+        gl.bind_texture(target, Some(raw));
+        options.apply(gl, target);
+        Ok(TextureInner {
+            gl: gl.clone(),
+            raw,
+            width,
+            height,
+            layers,
+            format,
+            options,
+        })
+    }
+
+    fn check_region(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let outside = |start: u32, len: u32, size: u32| {
+            start.checked_add(len).filter(|&end| end <= size).is_none()
+        };
+        if outside(x, width, self.width) || outside(y, height, self.height) {
+            return Err(Error::InvalidArgument(
+                "Texture update area outside of texture bounds".to_owned(),
+            ));
+        }
+        let expected = width as usize * height as usize * self.format.bytes_per_pixel();
+        if data.len() != expected {
+            return Err(Error::InvalidArgument(format!(
+                "Improper data length of {} for {}x{} texture region (expected {})",
+                data.len(),
+                width,
+                height,
+                expected
+            )));
+        }
+        Ok(())
+    }
+
+    unsafe fn generate_mipmaps(&self, target: u32) {
+        if self.options.mipmaps {
+            self.gl.generate_mipmap(target);
+        }
+    }
+}
+
+impl Drop for TextureInner {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.delete_texture(self.raw);
+        }
+    }
+}
+
+impl Texture {
+    /// Creates a texture with uninitialized contents.
+    pub fn new(
+        gl: &Gl,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        options: TextureOptions,
+    ) -> Result<Self, Error> {
+        Self::from_data(gl, width, height, format, None, options)
+    }
+
+    /// Creates a texture from raw pixel data in the given format, or with uninitialized
+    /// contents if `data` is `None`.
+    pub fn from_data(
+        gl: &Gl,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        data: Option<&[u8]>,
+        options: TextureOptions,
+    ) -> Result<Self, Error> {
+        unsafe {
+            let _saved = gl.save_state();
+            let inner =
+                TextureInner::create(gl, glow::TEXTURE_2D, width, height, 1, format, options)?;
+            if let Some(data) = data {
+                inner.check_region(0, 0, width, height, data)?;
+            }
+            gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                format.internal_format() as i32,
+                width as i32,
+                height as i32,
+                0,
+                format.format(),
+                format.data_type(),
+                data,
+            );
+            if data.is_some() {
+                inner.generate_mipmaps(glow::TEXTURE_2D);
+            }
+            Ok(Texture(Rc::new(inner)))
+        }
+    }
+
+    /// Creates an RGBA texture from a raw buffer of 8-bit RGBA pixels.
+    pub fn from_rgba(
+        gl: &Gl,
+        width: u32,
+        height: u32,
+        data: &[u8],
+        options: TextureOptions,
+    ) -> Result<Self, Error> {
+        Self::from_data(gl, width, height, TextureFormat::Rgba8, Some(data), options)
+    }
+
+    /// Loads an RGBA texture from an image file (desktop) or URL (web).
+    pub async fn load(gl: &Gl, source: &str, options: TextureOptions) -> Result<Self, Error> {
+        let image = backend::load_image(source).await?;
+        Self::from_image(gl, &image, options)
+    }
+
+    /// Decodes an RGBA texture from an encoded image (e.g. the contents of a PNG file).
+    pub async fn from_bytes(gl: &Gl, data: &[u8], options: TextureOptions) -> Result<Self, Error> {
+        let image = backend::decode_image(data).await?;
+        Self::from_image(gl, &image, options)
+    }
+
+    fn from_image(gl: &Gl, image: &backend::Image, options: TextureOptions) -> Result<Self, Error> {
+        let (width, height) = backend::image_size(image);
+        unsafe {
+            let _saved = gl.save_state();
+            let inner = TextureInner::create(
+                gl,
+                glow::TEXTURE_2D,
+                width,
+                height,
+                1,
+                TextureFormat::Rgba8,
+                options,
+            )?;
+            backend::tex_image_2d(gl, image);
+            inner.generate_mipmaps(glow::TEXTURE_2D);
+            Ok(Texture(Rc::new(inner)))
+        }
+    }
+
+    /// Replaces a region of the texture with raw pixel data in the texture's format.
+    pub fn update(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let inner = &self.0;
+        inner.check_region(x, y, width, height, data)?;
+        unsafe {
+            let _saved = inner.gl.save_state();
+            inner.gl.bind_texture(glow::TEXTURE_2D, Some(inner.raw));
+            inner.gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
+            inner.gl.tex_sub_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                x as i32,
+                y as i32,
+                width as i32,
+                height as i32,
+                inner.format.format(),
+                inner.format.data_type(),
+                glow::PixelUnpackData::Slice(data),
+            );
+            inner.generate_mipmaps(glow::TEXTURE_2D);
+        }
+        Ok(())
+    }
+
+    /// Binds this texture to `GL_TEXTURE_2D` of the given texture unit.
+    ///
+    /// Touches the following OpenGL state:
+    /// - Active texture (set to `unit`)
+    /// - `GL_TEXTURE_2D` binding
+    pub fn bind(&self, unit: u32) {
+        unsafe {
+            self.0.gl.active_texture(glow::TEXTURE0 + unit);
+            self.0.gl.bind_texture(glow::TEXTURE_2D, Some(self.0.raw));
+        }
+    }
+
+    pub fn raw(&self) -> glow::Texture {
+        self.0.raw
+    }
+
+    /// Attaches a debug label to the texture, shown in debug output and graphics debuggers.
+    pub fn set_label(&self, label: &str) {
+        self.0.gl.label_texture(self.0.raw, label);
+    }
+
+    pub fn width(&self) -> u32 {
+        self.0.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.0.height
+    }
+
+    pub fn size(&self) -> Size2<u32> {
+        size2(self.0.width, self.0.height)
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.0.format
+    }
+
+    pub fn options(&self) -> TextureOptions {
+        self.0.options
+    }
+}
+
+impl TextureArray {
+    /// Creates an array texture with uninitialized contents.
+    pub fn new(
+        gl: &Gl,
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: TextureFormat,
+        options: TextureOptions,
+    ) -> Result<Self, Error> {
+        Self::create(gl, width, height, layers, format, None, options)
+    }
+
+    /// Creates an array texture with every texel set to zero, which is transparent black for
+    /// RGBA textures. For layers that are only partly filled, such as sprite atlas pages smaller
+    /// than the layers, where filtering and mipmaps would otherwise pick up garbage.
+    pub fn new_cleared(
+        gl: &Gl,
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: TextureFormat,
+        options: TextureOptions,
+    ) -> Result<Self, Error> {
+        let zeros =
+            vec![0; width as usize * height as usize * layers as usize * format.bytes_per_pixel()];
+        Self::create(gl, width, height, layers, format, Some(&zeros), options)
+    }
+
+    fn create(
+        gl: &Gl,
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: TextureFormat,
+        data: Option<&[u8]>,
+        options: TextureOptions,
+    ) -> Result<Self, Error> {
+        unsafe {
+            let _saved = gl.save_state();
+            let inner = TextureInner::create(
+                gl,
+                glow::TEXTURE_2D_ARRAY,
+                width,
+                height,
+                layers,
+                format,
+                options,
+            )?;
+            gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
+            gl.tex_image_3d(
+                glow::TEXTURE_2D_ARRAY,
+                0,
+                format.internal_format() as i32,
+                width as i32,
+                height as i32,
+                layers as i32,
+                0,
+                format.format(),
+                format.data_type(),
+                data,
+            );
+            Ok(TextureArray(Rc::new(inner)))
+        }
+    }
+
+    /// Loads an image file (desktop) or URL (web) into a layer of an RGBA array texture.
+    ///
+    /// Mipmaps are not regenerated; call `generate_mipmaps` once all layers are loaded.
+    pub async fn load_layer(&self, source: &str, layer: u32) -> Result<(), Error> {
+        let image = backend::load_image(source).await?;
+        self.upload_image(&image, layer, source)
+    }
+
+    /// Decodes an encoded image into a layer of an RGBA array texture.
+    ///
+    /// Mipmaps are not regenerated; call `generate_mipmaps` once all layers are loaded.
+    pub async fn layer_from_bytes(&self, data: &[u8], layer: u32) -> Result<(), Error> {
+        let image = backend::decode_image(data).await?;
+        self.upload_image(&image, layer, "image")
+    }
+
+    fn upload_image(&self, image: &backend::Image, layer: u32, name: &str) -> Result<(), Error> {
+        let inner = &self.0;
+        if inner.format != TextureFormat::Rgba8 {
+            return Err(Error::InvalidArgument(
+                "Images can only be loaded into RGBA textures".to_owned(),
+            ));
+        }
+        if layer >= inner.layers {
+            return Err(Error::InvalidArgument(format!(
+                "Layer {} out of range for texture array with {} layers",
+                layer, inner.layers
+            )));
+        }
+        let (width, height) = backend::image_size(image);
+        if width > inner.width || height > inner.height {
+            return Err(Error::InvalidArgument(format!(
+                "{} is {}x{}, which does not fit in a {}x{} texture array",
+                name, width, height, inner.width, inner.height
+            )));
+        }
+        unsafe {
+            let _saved = inner.gl.save_state();
+            inner
+                .gl
+                .bind_texture(glow::TEXTURE_2D_ARRAY, Some(inner.raw));
+            backend::tex_sub_image_3d(&inner.gl, layer as i32, image);
+        }
+        Ok(())
+    }
+
+    /// Replaces a region of a layer with raw pixel data in the texture's format.
+    ///
+    /// Mipmaps are regenerated if enabled.
+    pub fn update_layer(
+        &self,
+        layer: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let inner = &self.0;
+        inner.check_region(x, y, width, height, data)?;
+        if layer >= inner.layers {
+            return Err(Error::InvalidArgument(format!(
+                "Layer {} out of range for texture array with {} layers",
+                layer, inner.layers
+            )));
+        }
+        unsafe {
+            let _saved = inner.gl.save_state();
+            inner
+                .gl
+                .bind_texture(glow::TEXTURE_2D_ARRAY, Some(inner.raw));
+            inner.gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
+            inner.gl.tex_sub_image_3d(
+                glow::TEXTURE_2D_ARRAY,
+                0,
+                x as i32,
+                y as i32,
+                layer as i32,
+                width as i32,
+                height as i32,
+                1,
+                inner.format.format(),
+                inner.format.data_type(),
+                glow::PixelUnpackData::Slice(data),
+            );
+            inner.generate_mipmaps(glow::TEXTURE_2D_ARRAY);
+        }
+        Ok(())
+    }
+
+    /// Regenerates mipmaps if they are enabled.
+    pub fn generate_mipmaps(&self) {
+        unsafe {
+            let _saved = self.0.gl.save_state();
+            self.0
+                .gl
+                .bind_texture(glow::TEXTURE_2D_ARRAY, Some(self.0.raw));
+            self.0.generate_mipmaps(glow::TEXTURE_2D_ARRAY);
+        }
+    }
+
+    /// Binds this texture to `GL_TEXTURE_2D_ARRAY` of the given texture unit.
+    ///
+    /// Touches the following OpenGL state:
+    /// - Active texture (set to `unit`)
+    /// - `GL_TEXTURE_2D_ARRAY` binding
+    pub fn bind(&self, unit: u32) {
+        unsafe {
+            self.0.gl.active_texture(glow::TEXTURE0 + unit);
+            self.0
+                .gl
+                .bind_texture(glow::TEXTURE_2D_ARRAY, Some(self.0.raw));
+        }
+    }
+
+    pub fn raw(&self) -> glow::Texture {
+        self.0.raw
+    }
+
+    /// Attaches a debug label to the texture, shown in debug output and graphics debuggers.
+    pub fn set_label(&self, label: &str) {
+        self.0.gl.label_texture(self.0.raw, label);
+    }
+
+    pub fn width(&self) -> u32 {
+        self.0.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.0.height
+    }
+
+    pub fn layers(&self) -> u32 {
+        self.0.layers
+    }
+
+    pub fn size(&self) -> Size3<u32> {
+        size3(self.0.width, self.0.height, self.0.layers)
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.0.format
+    }
+
+    pub fn options(&self) -> TextureOptions {
+        self.0.options
+    }
+}
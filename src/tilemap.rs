@@ -1,118 +1,98 @@
+use crate::material::BlendMode;
 use crate::prelude::*;
-use scopeguard::ScopeGuard;
+use crate::texture::{Texture, TextureArray, TextureFormat, TextureOptions};
+use crate::Error;
 
 /// Utility to draw a layer of a tilemap.
 pub struct TilemapRenderer {
+    /// The blend mode the tilemap is rendered with. Defaults to `BlendMode::Alpha`.
+    pub blend: BlendMode,
     gl: Gl,
-    shader: glow::Program,
-    tilemap: glow::Texture,
-    proj_loc: glow::UniformLocation,
-    size_loc: glow::UniformLocation,
-    offset_loc: glow::UniformLocation,
-    tilemap_size_loc: glow::UniformLocation,
-    tileset_loc: glow::UniformLocation,
+    shader: glutil::ShaderProgram,
+    tilemap: Texture,
+    proj: glutil::Uniform<Transform3D<f32>>,
+    size: glutil::Uniform<Size2<f32>>,
+    offset: glutil::Uniform<Point2<f32>>,
+    tilemap_size: glutil::Uniform<[i32; 2]>,
+    tileset: glutil::Uniform<glutil::Sampler>,
     width: usize,
     height: usize,
 }
 
 impl TilemapRenderer {
     /// Creates a new `TilemapRenderer` for a map of the specified size.
-    ///
-    /// Touches the following OpenGL state:
-    /// - `GL_TEXTURE_2D` binding
-    /// - `GL_UNPACK_ALIGNMENT` pixel store parameter
     pub fn new(
         gl: &Gl,
-        shader: glow::Program,
+        shader: glutil::ShaderProgram,
         width: usize,
         height: usize,
         tiles: &[u16],
-    ) -> Result<Self, String> {
+    ) -> Result<Self, Error> {
         if tiles.len() != width * height {
-            panic!(
+            return Err(Error::InvalidArgument(format!(
                 "Improper tile array length of {} for {}x{} tilemap",
                 tiles.len(),
                 width,
                 height
-            );
+            )));
         }
 
-        unsafe {
-            let tilemap = scopeguard::guard(gl.create_texture()?, |tex| gl.delete_texture(tex));
-            gl.bind_texture(glow::TEXTURE_2D, Some(*tilemap));
-            gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
-            gl.tex_image_2d(
-                glow::TEXTURE_2D,
-                0,
-                glow::R16UI as i32,
-                width as i32,
-                height as i32,
-                0,
-                glow::RED_INTEGER,
-                glow::UNSIGNED_SHORT,
-                Some(glutil::as_u8_slice(tiles)),
-            );
-            gl.tex_parameter_i32(
-                glow::TEXTURE_2D,
-                glow::TEXTURE_MIN_FILTER,
-                glow::NEAREST as i32,
-            );
-            gl.tex_parameter_i32(
-                glow::TEXTURE_2D,
-                glow::TEXTURE_MAG_FILTER,
-                glow::NEAREST as i32,
-            );
+        let tilemap = Texture::from_data(
+            gl,
+            width as u32,
+            height as u32,
+            TextureFormat::R16Ui,
+            Some(glutil::as_u8_slice(tiles)),
+            TextureOptions::nearest(),
+        )?;
+        tilemap.set_label("TilemapRenderer tilemap");
 
-            Ok(TilemapRenderer {
-                gl: gl.clone(),
-                proj_loc: glutil::get_uniform_location(gl, shader, "proj")?,
-                size_loc: glutil::get_uniform_location(gl, shader, "size")?,
-                offset_loc: glutil::get_uniform_location(gl, shader, "offset")?,
-                tilemap_size_loc: glutil::get_uniform_location(gl, shader, "tilemapSize")?,
-                tileset_loc: glutil::get_uniform_location(gl, shader, "tileset")?,
-                width,
-                height,
-                shader,
-                tilemap: ScopeGuard::into_inner(tilemap),
-            })
-        }
+        Ok(TilemapRenderer {
+            blend: BlendMode::Alpha,
+            gl: gl.clone(),
+            proj: shader.uniform("proj")?,
+            size: shader.uniform("size")?,
+            offset: shader.uniform("offset")?,
+            tilemap_size: shader.uniform("tilemapSize")?,
+            tileset: shader.uniform("tileset")?,
+            width,
+            height,
+            shader,
+            tilemap,
+        })
     }
 
     /// Updates a section of the tilemap.
-    ///
-    /// Touches the following OpenGL state:
-    /// - `GL_TEXTURE_2D` binding
-    /// - `GL_UNPACK_ALIGNMENT` pixel store parameter
-    pub fn update(&mut self, x: usize, y: usize, width: usize, height: usize, tiles: &[u16]) {
+    pub fn update(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        tiles: &[u16],
+    ) -> Result<(), Error> {
         if tiles.len() != width * height {
-            panic!(
+            return Err(Error::InvalidArgument(format!(
                 "Improper tile array length of {} for {}x{} tilemap",
                 tiles.len(),
                 width,
                 height
-            );
+            )));
         }
 
         if x + width > self.width || y + height > self.height {
-            panic!("Tilemap update area outside of tilemap bounds");
+            return Err(Error::InvalidArgument(
+                "Tilemap update area outside of tilemap bounds".to_owned(),
+            ));
         }
 
-        unsafe {
-            self.gl.bind_texture(glow::TEXTURE_2D, Some(self.tilemap));
-            self.gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
-
-            self.gl.tex_sub_image_2d(
-                glow::TEXTURE_2D,
-                0,
-                x as i32,
-                y as i32,
-                width as i32,
-                height as i32,
-                glow::RED_INTEGER,
-                glow::UNSIGNED_SHORT,
-                glow::PixelUnpackData::Slice(glutil::as_u8_slice(tiles)),
-            );
-        }
+        self.tilemap.update(
+            x as u32,
+            y as u32,
+            width as u32,
+            height as u32,
+            glutil::as_u8_slice(tiles),
+        )
     }
 
     /// Renders the tilemap using the given tileset.
@@ -121,7 +101,7 @@ impl TilemapRenderer {
     /// (width, height).
     ///
     /// See also: `Self::render_section`
-    pub fn render(&self, camera: Transform3D<f32>, tileset: glow::Texture) {
+    pub fn render(&self, camera: Transform3D<f32>, tileset: &TextureArray) {
         self.render_section(
             camera,
             tileset,
@@ -134,57 +114,39 @@ impl TilemapRenderer {
     /// The bottom-left corner of the tilemap section is at (0, 0), and the top-right corner is at
     /// (rect.width, rect.height).
     ///
-    /// Touches the following OpenGL state:
-    /// - `GL_TEXTURE_2D` binding
-    /// - `GL_TEXTURE_2D_ARRAY` binding
-    /// - Active texture (set to 0)
-    /// - Current shader program
+    /// Leaves the state tracked by `Gl::save_state` unchanged. Blending is left as configured for
+    /// `blend`.
     pub fn render_section(
         &self,
         camera: Transform3D<f32>,
-        tileset: glow::Texture,
+        tileset: &TextureArray,
         rect: Rect<f32>,
     ) {
+        let _saved = self.gl.save_state();
         unsafe {
-            self.gl.use_program(Some(self.shader));
+            self.shader.set(&self.tileset, glutil::Sampler(1));
+            self.shader.set(&self.size, rect.size);
+            self.shader.set(&self.offset, rect.origin);
+            self.shader
+                .set(&self.tilemap_size, [self.width as i32, self.height as i32]);
+            self.shader.set(&self.proj, camera);
+            self.blend.apply(&self.gl);
 
-            self.gl.uniform_1_i32(Some(&self.tileset_loc), 1);
-            self.gl
-                .uniform_2_f32(Some(&self.size_loc), rect.size.width, rect.size.height);
-            self.gl
-                .uniform_2_f32(Some(&self.offset_loc), rect.origin.x, rect.origin.y);
-            self.gl.uniform_2_i32(
-                Some(&self.tilemap_size_loc),
-                self.width as i32,
-                self.height as i32,
-            );
-            let camera_matrix = camera.to_array();
-            self.gl
-                .uniform_matrix_4_f32_slice(Some(&self.proj_loc), false, &camera_matrix);
-
-            self.gl.active_texture(glow::TEXTURE1);
-            self.gl.bind_texture(glow::TEXTURE_2D_ARRAY, Some(tileset));
-            self.gl.active_texture(glow::TEXTURE0);
-            self.gl.bind_texture(glow::TEXTURE_2D, Some(self.tilemap));
+            tileset.bind(1);
+            self.tilemap.bind(0);
 
             self.gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
         }
+        self.gl.check_errors("TilemapRenderer::render");
     }
 }
 
-impl Drop for TilemapRenderer {
-    fn drop(&mut self) {
-        unsafe {
-            self.gl.delete_texture(self.tilemap);
-        }
-    }
-}
-
-pub fn tilemap_shader(gl: &Gl) -> glow::Program {
-    glutil::compile_shader_program(
+pub fn tilemap_shader(gl: &Gl) -> Result<glutil::ShaderProgram, Error> {
+    let shader = glutil::ShaderProgram::from_source(
         gl,
         include_str!("shaders/tilemap.vert.glsl"),
         include_str!("shaders/tilemap.frag.glsl"),
-    )
-    .unwrap()
+    )?;
+    shader.set_label("tilemap shader");
+    Ok(shader)
 }
@@ -1,78 +1,219 @@
 use crate::prelude::*;
+use crate::Error;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::HtmlImageElement;
+use web_sys::{Blob, HtmlImageElement, Url};
 
 #[derive(Clone)]
-pub struct Gl(std::rc::Rc<(glow::Context, web_sys::WebGl2RenderingContext)>);
+pub struct Gl(std::rc::Rc<GlInner>);
+
+struct GlInner {
+    gl: glow::Context,
+    webgl: web_sys::WebGl2RenderingContext,
+    state: glutil::StateCache,
+    debug: bool,
+}
 
 impl Gl {
-    pub(crate) fn new(gl: web_sys::WebGl2RenderingContext) -> Self {
-        Gl(std::rc::Rc::new((
-            glow::Context::from_webgl2_context(gl.clone()),
-            gl,
-        )))
+    /// Wraps a context. WebGL has no `KHR_debug`, so if `debug` is true renderers check
+    /// `getError` after rendering instead.
+    pub fn new(webgl: web_sys::WebGl2RenderingContext, debug: bool) -> Self {
+        Gl(std::rc::Rc::new(GlInner {
+            gl: glow::Context::from_webgl2_context(webgl.clone()),
+            webgl,
+            state: glutil::StateCache::new(),
+            debug,
+        }))
+    }
+
+    pub(crate) fn state(&self) -> &glutil::StateCache {
+        &self.0.state
+    }
+
+    /// Whether the context was created in debug mode.
+    pub fn is_debug(&self) -> bool {
+        self.0.debug
+    }
+
+    /// Does nothing; WebGL does not support object labels.
+    pub fn label_buffer(&self, _buffer: glow::Buffer, _label: &str) {}
+
+    /// Does nothing; WebGL does not support object labels.
+    pub fn label_texture(&self, _texture: glow::Texture, _label: &str) {}
+
+    /// Does nothing; WebGL does not support object labels.
+    pub fn label_program(&self, _program: glow::Program, _label: &str) {}
+
+    /// Logs any pending WebGL errors if in debug mode. `context` identifies the operation that
+    /// caused the errors.
+    pub fn check_errors(&self, context: &str) {
+        if self.0.debug {
+            glutil::log_errors(&self.0.gl, context);
+        }
     }
 }
 
 impl std::ops::Deref for Gl {
     type Target = glow::Context;
     fn deref(&self) -> &glow::Context {
-        &self.0 .0
+        &self.0.gl
     }
 }
 
-async fn load_image(source: &str) -> Result<HtmlImageElement, String> {
+pub(crate) type Image = HtmlImageElement;
+
+pub(crate) async fn load_image(source: &str) -> Result<Image, Error> {
     let image = HtmlImageElement::new().unwrap();
     image.set_src(source);
+    // The browser does not distinguish between failing to fetch and failing to decode.
     JsFuture::from(image.decode())
         .await
-        .map_err(super::js_err)?;
+        .map_err(|e| Error::decode(source, super::js_err(e)))?;
     Ok(image)
 }
 
-pub async fn load_texture_2d(gl: &Gl, source: &str) -> Result<glow::Texture, String> {
+pub(crate) async fn decode_image(data: &[u8]) -> Result<Image, Error> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(data));
+    let blob = Blob::new_with_u8_array_sequence(&parts)
+        .map_err(|e| Error::decode("<memory>", super::js_err(e)))?;
+    let url = Url::create_object_url_with_blob(&blob)
+        .map_err(|e| Error::decode("<memory>", super::js_err(e)))?;
+    let image = load_image(&url).await.map_err(|e| match e {
+        Error::Decode { message, .. } => Error::decode("<memory>", message),
+        e => e,
+    });
+    Url::revoke_object_url(&url).ok();
+    image
+}
+
+pub(crate) fn image_size(image: &Image) -> (u32, u32) {
+    (image.width(), image.height())
+}
+
+/// Uploads an image to level 0 of the `GL_TEXTURE_2D` binding.
+pub(crate) unsafe fn tex_image_2d(gl: &Gl, image: &Image) {
+    gl.tex_image_2d_with_html_image(
+        glow::TEXTURE_2D,
+        0,
+        glow::RGBA8 as i32,
+        glow::RGBA,
+        glow::UNSIGNED_BYTE,
+        image,
+    );
+}
+
+/// Uploads an image to a layer of level 0 of the `GL_TEXTURE_2D_ARRAY` binding.
+pub(crate) unsafe fn tex_sub_image_3d(gl: &Gl, layer: i32, image: &Image) {
+    gl.0.webgl
+        .tex_sub_image_3d_with_html_image_element(
+            glow::TEXTURE_2D_ARRAY,
+            0,
+            0,
+            0,
+            layer,
+            image.width() as i32,
+            image.height() as i32,
+            1,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            image,
+        )
+        .ok();
+}
+
+#[deprecated(note = "use `texture::Texture::load` instead")]
+pub async fn load_texture_2d(gl: &Gl, source: &str) -> Result<glow::Texture, Error> {
     let image = load_image(source).await?;
     let texture;
     unsafe {
-        texture = gl.create_texture()?;
+        texture = gl.create_texture().map_err(Error::Gl)?;
         gl.bind_texture(glow::TEXTURE_2D, Some(texture));
-        gl.tex_image_2d_with_html_image(
-            glow::TEXTURE_2D,
-            0,
-            glow::RGBA8 as i32,
-            glow::RGBA,
-            glow::UNSIGNED_BYTE,
-            &image,
-        );
+        tex_image_2d(gl, &image);
         gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAX_LEVEL, 0);
     }
     Ok(texture)
 }
 
+#[deprecated(note = "use `texture::TextureArray::load_layer` instead")]
 pub async fn load_texture_layer(
     gl: &Gl,
     source: &str,
     texture: glow::Texture,
     layer: i32,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     let image = load_image(source).await?;
     unsafe {
         gl.bind_texture(glow::TEXTURE_2D_ARRAY, Some(texture));
-        gl.0 .1
-            .tex_sub_image_3d_with_html_image_element(
-                glow::TEXTURE_2D_ARRAY,
-                0,
-                0,
-                0,
-                layer,
-                image.width() as i32,
-                image.height() as i32,
-                1,
-                glow::RGBA,
-                glow::UNSIGNED_BYTE,
-                &image,
-            )
-            .ok();
+        tex_sub_image_3d(gl, layer, &image);
     }
     Ok(())
 }
+
+const GPU_DISJOINT_EXT: u32 = 0x8FBB;
+
+/// Timer queries require `EXT_disjoint_timer_query_webgl2`.
+pub(crate) fn timer_queries_supported(gl: &Gl) -> bool {
+    matches!(
+        gl.0.webgl.get_extension("EXT_disjoint_timer_query_webgl2"),
+        Ok(Some(_))
+    )
+}
+
+/// Whether a timing-invalidating event (e.g. a GPU clock change) has happened since the last
+/// call, in which case in-flight timer query results are meaningless.
+pub(crate) fn gpu_disjoint(gl: &Gl) -> bool {
+    gl.0.webgl
+        .get_parameter(GPU_DISJOINT_EXT)
+        .ok()
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+/// A `GL_TIME_ELAPSED` query object.
+///
+/// This uses the WebGL context directly, since glow reads boolean query parameters as numbers.
+pub(crate) struct TimerQuery {
+    gl: Gl,
+    query: web_sys::WebGlQuery,
+}
+
+impl TimerQuery {
+    pub(crate) fn new(gl: &Gl) -> Result<Self, Error> {
+        Ok(TimerQuery {
+            gl: gl.clone(),
+            query: gl
+                .0
+                .webgl
+                .create_query()
+                .ok_or_else(|| Error::Gl("Could not create query object".to_owned()))?,
+        })
+    }
+
+    pub(crate) fn begin(&self) {
+        self.gl.0.webgl.begin_query(glow::TIME_ELAPSED, &self.query);
+    }
+
+    pub(crate) fn end(&self) {
+        self.gl.0.webgl.end_query(glow::TIME_ELAPSED);
+    }
+
+    /// The elapsed time in nanoseconds, or `None` if the result is not available yet.
+    pub(crate) fn result(&self) -> Option<u64> {
+        let webgl = &self.gl.0.webgl;
+        let available = webgl
+            .get_query_parameter(&self.query, glow::QUERY_RESULT_AVAILABLE)
+            .as_bool()?;
+        if !available {
+            return None;
+        }
+        webgl
+            .get_query_parameter(&self.query, glow::QUERY_RESULT)
+            .as_f64()
+            .map(|ns| ns as u64)
+    }
+}
+
+impl Drop for TimerQuery {
+    fn drop(&mut self) {
+        self.gl.0.webgl.delete_query(Some(&self.query));
+    }
+}
@@ -1,4 +1,4 @@
-use js_sys::{ArrayBuffer, Error};
+use js_sys::ArrayBuffer;
 use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
 
@@ -6,30 +6,34 @@ pub mod glutil;
 pub mod sound;
 pub mod util;
 
-async fn load_buffer(source: &str) -> Result<ArrayBuffer, String> {
+async fn load_buffer(source: &str) -> Result<ArrayBuffer, crate::Error> {
+    let io_err = |e| crate::Error::io(source, js_err(e));
     let response: web_sys::Response =
         JsFuture::from(web_sys::window().unwrap().fetch_with_str(source))
             .await
-            .map_err(js_err)?
+            .map_err(io_err)?
             .dyn_into()
-            .unwrap();
+            .map_err(io_err)?;
     if !response.ok() {
-        return Err(format!(
-            "Server responded with {} {}",
-            response.status(),
-            response.status_text()
+        return Err(crate::Error::io(
+            source,
+            format!(
+                "Server responded with {} {}",
+                response.status(),
+                response.status_text()
+            ),
         ));
     }
-    let buffer = JsFuture::from(response.array_buffer().unwrap())
+    let buffer = JsFuture::from(response.array_buffer().map_err(io_err)?)
         .await
-        .unwrap()
+        .map_err(io_err)?
         .dyn_into()
-        .unwrap();
+        .map_err(io_err)?;
     Ok(buffer)
 }
 
 fn js_err(err: JsValue) -> String {
-    match err.dyn_into::<Error>() {
+    match err.dyn_into::<js_sys::Error>() {
         Ok(err) => err.to_string().into(),
         Err(err) => match err.as_string() {
             Some(msg) => msg,
@@ -8,6 +8,7 @@ use web_sys::{
 use webutil::event::EventTargetExt;
 
 use crate::sound::SoundCommand;
+use crate::Error;
 
 pub(crate) type InternalSound = AudioBuffer;
 
@@ -15,12 +16,14 @@ thread_local! {
     static AUDIO_CONTEXT: AudioContext = AudioContext::new().unwrap();
 }
 
-pub(crate) async fn load(source: &str) -> Result<InternalSound, String> {
+pub(crate) async fn load(source: &str) -> Result<InternalSound, Error> {
     let buffer = super::load_buffer(source).await?;
-    JsFuture::from(AUDIO_CONTEXT.with(|ctx| ctx.decode_audio_data(&buffer).unwrap()))
+    let decode_err = |e| Error::decode(source, super::js_err(e));
+    JsFuture::from(AUDIO_CONTEXT.with(|ctx| ctx.decode_audio_data(&buffer).map_err(decode_err))?)
         .await
-        .map_err(super::js_err)
-        .map(|v| v.dyn_into().unwrap())
+        .map_err(decode_err)?
+        .dyn_into()
+        .map_err(decode_err)
 }
 
 enum ServiceState {
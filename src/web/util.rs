@@ -1,5 +1,6 @@
 use crate::gameloop::*;
 use crate::prelude::*;
+use crate::Error;
 
 use bincode::Options;
 use js_sys::JsString;
@@ -15,10 +16,15 @@ use winit::event_loop::{EventLoop, EventLoopProxy};
 use winit::platform::web::WindowExtWebSys;
 use winit::window::{Window, WindowBuilder};
 
+/// Creates the window and OpenGL context and runs the game loop.
+///
+/// If `debug` is true, OpenGL debug output is enabled and routed to the `log` crate. See
+/// `Gl::check_errors`.
 pub fn launch<G, F>(
     wb: WindowBuilder,
     ups: f64,
     lockstep: bool,
+    debug: bool,
     init: impl FnOnce(&Window, Gl, EventLoopProxy<G::UserEvent>, LocalExecutor) -> F,
 ) where
     G: Game + 'static,
@@ -45,6 +51,7 @@ pub fn launch<G, F>(
             .unwrap()
             .dyn_into()
             .unwrap(),
+        debug,
     );
 
     unsafe {
@@ -114,7 +121,7 @@ impl LocalExecutor {
     }
 }
 
-pub async fn load_binary(source: &str) -> Result<Vec<u8>, String> {
+pub async fn load_binary(source: &str) -> Result<Vec<u8>, Error> {
     let buffer = super::load_buffer(source).await?;
     Ok(js_sys::Uint8Array::new(&buffer).to_vec())
 }
@@ -128,8 +135,9 @@ extern "C" {
     fn set_item(key: &str, value: &JsString) -> Result<(), JsValue>;
 }
 
-pub fn store<T: Serialize>(key: &str, value: &T, human_readable: bool) -> Result<(), String> {
-    let mut serialized = bincode::serialize(value).map_err(|e| e.to_string())?;
+pub fn store<T: Serialize>(key: &str, value: &T, human_readable: bool) -> Result<(), Error> {
+    let mut serialized =
+        bincode::serialize(value).map_err(|e| Error::Serialization(e.to_string()))?;
     if serialized.len() % 2 != 0 {
         serialized.push(0);
     }
@@ -144,14 +152,14 @@ pub fn store<T: Serialize>(key: &str, value: &T, human_readable: bool) -> Result
         // This is little-endian because wasm32 is little-endian.
         std::slice::from_raw_parts(serialized.as_ptr() as *const _, serialized.len() / 2)
     });
-    set_item(key, &value).map_err(super::js_err)
+    set_item(key, &value).map_err(|e| Error::io(key, super::js_err(e)))
 }
 
-pub fn load<T: DeserializeOwned>(key: &str, human_readable: bool) -> Result<Option<T>, String> {
+pub fn load<T: DeserializeOwned>(key: &str, human_readable: bool) -> Result<Option<T>, Error> {
     let data = match get_item(key) {
         Ok(Some(v)) => v.iter().collect::<Vec<_>>(),
         Ok(None) => return Ok(None),
-        Err(e) => return Err(super::js_err(e)),
+        Err(e) => return Err(Error::io(key, super::js_err(e))),
     };
     let data = unsafe {
         // View the [u16] as a [u8].
@@ -161,5 +169,5 @@ pub fn load<T: DeserializeOwned>(key: &str, human_readable: bool) -> Result<Opti
     bincode::options()
         .allow_trailing_bytes()
         .deserialize(data)
-        .map_err(|e| e.to_string())
+        .map_err(|e| Error::Serialization(e.to_string()))
 }